@@ -1,49 +1,21 @@
 //! Benchmarks for read performance.
 use criterion::{BatchSize, Criterion};
-use layer8_tungstenite::{protocol::Role, Message, WebSocket};
-use std::{
-    io::{self, Read, Write},
-    sync::{Arc, Mutex},
-};
-
-/// Mock stream with no artificial delays.
-#[derive(Default, Clone)]
-struct MockIo(Arc<Mutex<Vec<u8>>>);
-
-impl Read for MockIo {
-    fn read(&mut self, to: &mut [u8]) -> io::Result<usize> {
-        let mut data = self.0.lock().unwrap();
-        if data.is_empty() {
-            return Err(io::Error::new(io::ErrorKind::WouldBlock, "not ready"));
-        }
-        let len = data.len().min(to.len());
-        to[..len].copy_from_slice(data.drain(..len).as_slice());
-        Ok(len)
-    }
-}
-
-impl Write for MockIo {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.lock().unwrap().write(buf)
-    }
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
-    }
-}
+use layer8_tungstenite::{protocol::Role, testing::duplex, Message, WebSocket};
+use std::io::Cursor;
 
 fn benchmark(c: &mut Criterion) {
     /// Benchmark reading 100k mix of binary & text messages.
     fn read_100k(role: Role, b: &mut criterion::Bencher<'_>) {
-        let io = MockIo::default();
+        let (stream, peer) = duplex();
         let mut writer = WebSocket::from_raw_socket(
-            io.clone(),
+            peer,
             match role {
                 Role::Client => Role::Server,
                 Role::Server => Role::Client,
             },
             None,
         );
-        let mut ws = WebSocket::from_raw_socket(io, role, None);
+        let mut ws = WebSocket::from_raw_socket(stream, role, None);
 
         b.iter_batched(
             || {
@@ -86,6 +58,48 @@ fn benchmark(c: &mut Criterion) {
     c.bench_function("read 100k small messages (client)", |b| {
         read_100k(Role::Client, b);
     });
+
+    /// Benchmark reading 10 text messages of 1 MiB, mostly ASCII, from memory.
+    fn read_10_large_text(role: Role, b: &mut criterion::Bencher<'_>) {
+        let text: String = (0..)
+            .map(|i| format!("{{\"id\":{i},\"name\":\"Zoë\"}},"))
+            .flat_map(|item| item.chars().collect::<Vec<_>>())
+            .take(1024 * 1024)
+            .collect();
+        let mut writer = WebSocket::from_raw_socket(
+            Cursor::new(Vec::new()),
+            match role {
+                Role::Client => Role::Server,
+                Role::Server => Role::Client,
+            },
+            None,
+        );
+        for _ in 0..10 {
+            writer.send(Message::text(text.clone())).unwrap();
+        }
+        let data = writer.get_ref().get_ref().clone();
+
+        b.iter_batched(
+            || WebSocket::from_raw_socket(Cursor::new(data.clone()), role, None),
+            |mut ws| {
+                for _ in 0..10 {
+                    match ws.read().unwrap() {
+                        Message::Text(msg) => assert_eq!(msg.len(), text.len()),
+                        m => panic!("Unexpected {m}"),
+                    }
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    }
+
+    c.bench_function("read+unmask 10 1 MiB text messages (server)", |b| {
+        read_10_large_text(Role::Server, b);
+    });
+
+    c.bench_function("read 10 1 MiB text messages (client)", |b| {
+        read_10_large_text(Role::Client, b);
+    });
 }
 
 criterion::criterion_group!(read_benches, benchmark);
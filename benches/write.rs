@@ -1,6 +1,9 @@
 //! Benchmarks for write performance.
 use criterion::Criterion;
-use layer8_tungstenite::{protocol::Role, Message, WebSocket};
+use layer8_tungstenite::{
+    protocol::{PreparedMessage, Role},
+    Bytes, Message, WebSocket,
+};
 use std::{
     hint, io,
     time::{Duration, Instant},
@@ -11,7 +14,8 @@ const MOCK_WRITE_LEN: usize = 8 * 1024 * 1024;
 /// `Write` impl that simulates slowish writes and slow flushes.
 ///
 /// Each `write` can buffer up to 8 MiB before flushing but takes an additional **~80ns**
-/// to simulate stuff going on in the underlying stream.
+/// to simulate stuff going on in the underlying stream, a `write_vectored` takes the same
+/// for all its buffers.
 /// Each `flush` takes **~8µs** to simulate flush io.
 struct MockWrite(Vec<u8>);
 
@@ -31,6 +35,19 @@ impl io::Write for MockWrite {
         Ok(buf.len())
     }
 
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let len = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        if self.0.len() + len > MOCK_WRITE_LEN {
+            self.flush()?;
+        }
+        // simulate io
+        spin(Duration::from_nanos(80));
+        for buf in bufs {
+            self.0.extend_from_slice(buf);
+        }
+        Ok(len)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         if !self.0.is_empty() {
             // simulate io
@@ -72,6 +89,62 @@ fn benchmark(c: &mut Criterion) {
     c.bench_function("write+mask 100k small messages then flush (client)", |b| {
         write_100k_then_flush(Role::Client, b);
     });
+
+    fn write_100_large_then_flush(role: Role, b: &mut criterion::Bencher<'_>) {
+        let mut ws =
+            WebSocket::from_raw_socket(MockWrite(Vec::with_capacity(MOCK_WRITE_LEN)), role, None);
+        let payload = Bytes::from(vec![0x42; 1024 * 1024]);
+
+        b.iter(|| {
+            for _ in 0..100 {
+                ws.write(Message::Binary(payload.clone())).unwrap();
+            }
+            ws.flush().unwrap();
+        });
+    }
+
+    c.bench_function("write 100 1 MiB messages then flush (server)", |b| {
+        write_100_large_then_flush(Role::Server, b);
+    });
+
+    c.bench_function("write+mask 100 1 MiB messages then flush (client)", |b| {
+        write_100_large_then_flush(Role::Client, b);
+    });
+
+    fn fan_out(prepare: bool, b: &mut criterion::Bencher<'_>) {
+        let mut sockets: Vec<_> = (0..100)
+            .map(|_| {
+                let stream = MockWrite(Vec::with_capacity(MOCK_WRITE_LEN));
+                WebSocket::from_raw_socket(stream, Role::Server, None)
+            })
+            .collect();
+        let payload = vec![0x42; 16 * 1024];
+
+        b.iter(|| {
+            if prepare {
+                let msg = PreparedMessage::binary(payload.clone());
+                for ws in &mut sockets {
+                    ws.write_prepared(&msg).unwrap();
+                }
+            } else {
+                let msg = Message::binary(payload.clone());
+                for ws in &mut sockets {
+                    ws.write(msg.clone()).unwrap();
+                }
+            }
+            for ws in &mut sockets {
+                ws.flush().unwrap();
+            }
+        });
+    }
+
+    c.bench_function("write a 16 KiB message to 100 sockets then flush", |b| {
+        fan_out(false, b);
+    });
+
+    c.bench_function("write a prepared 16 KiB message to 100 sockets then flush", |b| {
+        fan_out(true, b);
+    });
 }
 
 criterion::criterion_group!(write_benches, benchmark);
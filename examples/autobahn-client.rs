@@ -23,10 +23,10 @@ fn run_test(case: u32) -> Result<()> {
     let (mut socket, _) = connect(case_url)?;
     loop {
         match socket.read()? {
-            msg @ Message::Text(_) | msg @ Message::Binary(_) => {
+            msg @ Message::Text(_) | msg @ Message::Binary(_) | msg @ Message::Chunk(_) => {
                 socket.send(msg)?;
             }
-            Message::Ping(_) | Message::Pong(_) | Message::Close(_) | Message::Frame(_) => {}
+            _ => {}
         }
     }
 }
@@ -20,10 +20,10 @@ fn handle_client(stream: TcpStream) -> Result<()> {
     info!("Running test");
     loop {
         match socket.read()? {
-            msg @ Message::Text(_) | msg @ Message::Binary(_) => {
+            msg @ Message::Text(_) | msg @ Message::Binary(_) | msg @ Message::Chunk(_) => {
                 socket.send(msg)?;
             }
-            Message::Ping(_) | Message::Pong(_) | Message::Close(_) | Message::Frame(_) => {}
+            _ => {}
         }
     }
 }
@@ -0,0 +1,24 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate layer8_tungstenite;
+extern crate tungstenite_fuzz;
+
+use layer8_tungstenite::http::Request;
+use tungstenite_fuzz::{http_response, input_stream, KEY};
+
+fuzz_target!(|data: &[u8]| {
+    let request = Request::builder()
+        .uri("ws://localhost/chat")
+        .header("Host", "localhost")
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", KEY)
+        .header("Sec-WebSocket-Protocol", "chat")
+        .body(())
+        .unwrap();
+    let (stream, _peer) = input_stream(&http_response(data));
+    if let Ok((mut socket, _)) = layer8_tungstenite::client(request, stream) {
+        while socket.read().is_ok() {}
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate layer8_tungstenite;
+extern crate tungstenite_fuzz;
+
+use tungstenite_fuzz::{http_request, input_stream};
+
+fuzz_target!(|data: &[u8]| {
+    let (stream, _peer) = input_stream(&http_request(data));
+    layer8_tungstenite::accept(stream).ok();
+});
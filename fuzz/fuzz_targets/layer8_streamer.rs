@@ -0,0 +1,13 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate layer8_tungstenite;
+extern crate tungstenite_fuzz;
+
+use layer8_tungstenite::layer8_streamer::Layer8Streamer;
+use tungstenite_fuzz::{input_stream, layer8_stream, shared_secret};
+
+fuzz_target!(|data: &[u8]| {
+    let (stream, _peer) = input_stream(&layer8_stream(data, shared_secret(), true));
+    let mut streamer = Layer8Streamer::new(stream, Some(shared_secret().clone()));
+    while let Ok(Some(_)) = streamer.read() {}
+});
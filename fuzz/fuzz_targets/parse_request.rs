@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate layer8_tungstenite;
+
+use layer8_tungstenite::handshake::machine::TryParse;
+use layer8_tungstenite::handshake::server::{create_response, Request};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(Some((_, request))) = Request::try_parse(data) {
+        create_response(&request).ok();
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate layer8_tungstenite;
+
+use layer8_tungstenite::handshake::client::Response;
+use layer8_tungstenite::handshake::machine::TryParse;
+
+fuzz_target!(|data: &[u8]| {
+    Response::try_parse(data).ok();
+});
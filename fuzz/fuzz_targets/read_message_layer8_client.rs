@@ -0,0 +1,15 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate layer8_tungstenite;
+extern crate tungstenite_fuzz;
+
+use layer8_tungstenite::WebSocket;
+use layer8_tungstenite::protocol::Role;
+use tungstenite_fuzz::{input_stream, layer8_stream, shared_secret};
+
+fuzz_target!(|data: &[u8]| {
+    let (stream, _peer) = input_stream(&layer8_stream(data, shared_secret(), false));
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Client, None);
+    socket.set_shared_secret(shared_secret().clone());
+    while socket.read().is_ok() {}
+});
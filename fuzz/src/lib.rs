@@ -0,0 +1,154 @@
+//! Structured input generators shared by the fuzz targets.
+//!
+//! Fuzzed bytes are turned into inputs which get past the outer layers, e.g. an encrypted layer8
+//! envelope around a fuzzed frame or a handshake request with the required headers, so the
+//! fuzzer spends its time on the code behind them.
+//!
+//! Every target has a seed corpus, run e.g. `cargo fuzz run parse_request fuzz/seeds/parse_request`.
+
+use std::io::Write;
+use std::sync::OnceLock;
+
+use layer8_primitives::crypto::{generate_key_pair, Jwk, KeyUse};
+use layer8_primitives::types::RoundtripEnvelope;
+use layer8_tungstenite::protocol::frame::coding::{Data, OpCode};
+use layer8_tungstenite::protocol::frame::Frame;
+use layer8_tungstenite::testing::{duplex, DuplexStream};
+
+/// The `Sec-WebSocket-Key` sent by the client targets, from RFC 6455.
+pub const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+/// The `Sec-WebSocket-Accept` matching [`KEY`].
+pub const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+/// Create a stream which reads `data`, then fails with `WouldBlock`. What is written to it can
+/// be read from the other end, which has to be kept alive.
+pub fn input_stream(data: &[u8]) -> (DuplexStream, DuplexStream) {
+    let (stream, mut peer) = duplex();
+    peer.write_all(data).unwrap();
+    (stream, peer)
+}
+
+/// The layer8 shared secret used by the targets.
+pub fn shared_secret() -> &'static Jwk {
+    static SECRET: OnceLock<Jwk> = OnceLock::new();
+    SECRET.get_or_init(|| {
+        let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+        private_key.get_ecdh_shared_secret(&public_key).unwrap()
+    })
+}
+
+/// Build a stream of layer8 frames, masked if `mask` is set.
+///
+/// The input is a list of records, each a kind byte, a little endian `u16` length and that many
+/// bytes (or the rest of the input). The lower two bits of the kind select the record:
+///
+/// - `0`: the bytes are a frame, sent encrypted in an envelope,
+/// - `1`: the bytes are sent in an envelope without encryption,
+/// - `2`: the bytes are the payload of the outer frame, e.g. broken envelope JSON,
+/// - `3`: the bytes are written to the stream as they are.
+///
+/// Bit `0x80` sends the outer frame as text instead of binary, bit `0x40` splits it into two
+/// fragments.
+pub fn layer8_stream(mut data: &[u8], secret: &Jwk, mask: bool) -> Vec<u8> {
+    let mut stream = Vec::new();
+    while let [kind, rest @ ..] = data {
+        let (len, rest) = match rest {
+            [lo, hi, rest @ ..] => (u16::from_le_bytes([*lo, *hi]) as usize, rest),
+            _ => (rest.len(), &[][..]),
+        };
+        let (bytes, rest) = rest.split_at(len.min(rest.len()));
+        data = rest;
+
+        let payload = match kind & 0b11 {
+            0 => match secret.symmetric_encrypt(bytes) {
+                Ok(encrypted) => RoundtripEnvelope::encode(&encrypted).to_json_bytes(),
+                Err(_) => continue,
+            },
+            1 => RoundtripEnvelope::encode(bytes).to_json_bytes(),
+            2 => bytes.to_vec(),
+            _ => {
+                stream.extend_from_slice(bytes);
+                continue;
+            }
+        };
+
+        let opcode = OpCode::Data(if kind & 0x80 == 0 { Data::Binary } else { Data::Text });
+        if kind & 0x40 == 0 {
+            write_frame(&mut stream, Frame::message(payload, opcode, true), mask);
+        } else {
+            let (first, second) = payload.split_at(payload.len() / 2);
+            write_frame(&mut stream, Frame::message(first.to_vec(), opcode, false), mask);
+            let continuation = OpCode::Data(Data::Continue);
+            write_frame(&mut stream, Frame::message(second.to_vec(), continuation, true), mask);
+        }
+    }
+    stream
+}
+
+fn write_frame(stream: &mut Vec<u8>, mut frame: Frame, mask: bool) {
+    if mask {
+        frame.header_mut().mask = Some([0x6d, 0xb6, 0xb2, 0x80]);
+    }
+    frame.format(stream).unwrap();
+}
+
+/// Build a handshake request.
+///
+/// The first byte selects the headers of a valid request: bit 0 `Host`, bit 1 `Connection`,
+/// bit 2 `Upgrade`, bit 3 `Sec-WebSocket-Version`, bit 4 `Sec-WebSocket-Key` and bit 7 leaves
+/// out the request line. The rest of the input up to the first zero byte are more header lines
+/// separated by `\n`, the bytes after it follow the request.
+pub fn http_request(data: &[u8]) -> Vec<u8> {
+    let headers = [
+        "Host: localhost".to_owned(),
+        "Connection: Upgrade".to_owned(),
+        "Upgrade: websocket".to_owned(),
+        "Sec-WebSocket-Version: 13".to_owned(),
+        format!("Sec-WebSocket-Key: {KEY}"),
+    ];
+    http_message("GET /chat HTTP/1.1", &headers, data)
+}
+
+/// Build a handshake response, see [`http_request`].
+///
+/// The bits of the first byte select `Upgrade`, `Connection`, `Sec-WebSocket-Accept`,
+/// `Sec-WebSocket-Protocol` and `Sec-WebSocket-Extensions` headers, bit 7 leaves out the
+/// status line.
+pub fn http_response(data: &[u8]) -> Vec<u8> {
+    let headers = [
+        "Upgrade: websocket".to_owned(),
+        "Connection: Upgrade".to_owned(),
+        format!("Sec-WebSocket-Accept: {ACCEPT}"),
+        "Sec-WebSocket-Protocol: chat".to_owned(),
+        "Sec-WebSocket-Extensions: permessage-deflate".to_owned(),
+    ];
+    http_message("HTTP/1.1 101 Switching Protocols", &headers, data)
+}
+
+fn http_message(start_line: &str, headers: &[String], data: &[u8]) -> Vec<u8> {
+    let (flags, rest) = match data {
+        [flags, rest @ ..] => (*flags, rest),
+        [] => (0, data),
+    };
+    let (lines, tail) = match rest.iter().position(|&byte| byte == 0) {
+        Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+        None => (rest, &[][..]),
+    };
+
+    let mut message = Vec::new();
+    if flags & 0x80 == 0 {
+        write!(message, "{start_line}\r\n").unwrap();
+    }
+    for (bit, header) in headers.iter().enumerate() {
+        if flags & (1 << bit) != 0 {
+            write!(message, "{header}\r\n").unwrap();
+        }
+    }
+    for line in lines.split(|&byte| byte == b'\n').filter(|line| !line.is_empty()) {
+        message.extend_from_slice(line);
+        message.extend_from_slice(b"\r\n");
+    }
+    message.extend_from_slice(b"\r\n");
+    message.extend_from_slice(tail);
+    message
+}
@@ -3,10 +3,16 @@
 //! The `ReadBuffer` is a buffer of bytes similar to a first-in, first-out queue.
 //! It is filled by reading from a stream supporting `Read` and is then
 //! accessible as a cursor for reading bytes.
+//!
+//! The `BufferPool` lends read buffers to many connections.
 
-use std::io::{Cursor, Read, Result as IoResult};
+use std::{
+    fmt,
+    io::{Cursor, Read, Result as IoResult},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
 
-use bytes::Buf;
+use bytes::{Buf, BytesMut};
 
 /// A FIFO buffer for reading packets from the network.
 #[derive(Debug)]
@@ -87,6 +93,85 @@ impl<const CHUNK_SIZE: usize> Default for ReadBuffer<CHUNK_SIZE> {
     }
 }
 
+/// A pool of read buffers shared by many connections, see
+/// [`WebSocketConfig::buffer_pool`](crate::protocol::WebSocketConfig::buffer_pool).
+///
+/// A connection using the pool only holds a buffer while a frame is being assembled and
+/// returns it as soon as no more data is buffered, so idle connections hold no read buffer.
+/// Returned buffers are kept for reuse up to a maximum number, buffers which grew for bigger
+/// frames are freed.
+///
+/// The configurations of the connections share the pool through an [`Arc`](std::sync::Arc):
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use layer8_tungstenite::{buffer::BufferPool, protocol::WebSocketConfig};
+///
+/// let pool = Arc::new(BufferPool::new(16 * 1024, 1024));
+/// let config = WebSocketConfig::default().buffer_pool(pool.clone());
+/// ```
+pub struct BufferPool {
+    buffer_size: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<BytesMut>>,
+}
+
+impl BufferPool {
+    /// Create a pool of buffers with `buffer_size` bytes, keeping up to `max_idle` returned
+    /// buffers for reuse.
+    pub const fn new(buffer_size: usize, max_idle: usize) -> Self {
+        Self { buffer_size, max_idle, idle: Mutex::new(Vec::new()) }
+    }
+
+    /// The size of the buffers in bytes.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// The number of buffers kept for reuse.
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Free idle buffers until at most `max_idle` are left.
+    pub fn shrink_to(&self, max_idle: usize) {
+        self.lock().truncate(max_idle);
+    }
+
+    /// Take an empty buffer, reusing an idle one if possible.
+    pub(crate) fn take(&self) -> BytesMut {
+        self.lock().pop().unwrap_or_else(|| BytesMut::with_capacity(self.buffer_size))
+    }
+
+    /// Return a buffer to the pool.
+    pub(crate) fn put(&self, mut buffer: BytesMut) {
+        buffer.clear();
+        // Buffers still shared with a frame payload or grown past the size are freed.
+        if !buffer.try_reclaim(self.buffer_size) || buffer.capacity() > self.buffer_size {
+            return;
+        }
+        let mut idle = self.lock();
+        if idle.len() < self.max_idle {
+            idle.push(buffer);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<BytesMut>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.buffer_size)
+            .field("max_idle", &self.max_idle)
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,23 +1,36 @@
 //! Methods to connect to a WebSocket as a client.
 
 use std::{
-    io::{Read, Write},
-    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
     result::Result as StdResult,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::PathBuf};
 
-use http::{request::Parts, HeaderName, Uri};
+use http::{header, request::Parts, HeaderMap, HeaderName, Uri};
 use log::*;
 
 use crate::{
-    handshake::client::{generate_key, Request, Response},
+    handshake::{
+        auth::percent_encode,
+        client::{generate_key, Request, Response},
+    },
     protocol::WebSocketConfig,
+    proxy::Proxy,
     stream::MaybeTlsStream,
 };
 
 use crate::{
-    error::{Error, Result, UrlError},
-    handshake::{client::ClientHandshake, HandshakeError},
+    error::{ConnectError, Error, Result, UrlError},
+    handshake::{client::ClientHandshake, machine::HandshakeConfig, HandshakeError},
     protocol::WebSocket,
     stream::{Mode, NoDelay},
 };
@@ -44,10 +57,309 @@ pub fn connect_with_config<Req: IntoClientRequest>(
     request: Req,
     config: Option<WebSocketConfig>,
     max_redirects: u8,
+) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
+    connect_with_options(
+        request,
+        ConnectOptions::default().config(config).max_redirects(max_redirects),
+    )
+}
+
+/// Settings for [`connect_with_options`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectOptions {
+    /// The WebSocket configuration. The default value is `None`.
+    pub config: Option<WebSocketConfig>,
+    /// How to follow redirects. The default value follows up to 3 redirects.
+    pub redirect: RedirectPolicy,
+    /// The proxy to connect through. The default value is `None`.
+    pub proxy: Option<Proxy>,
+    /// When set to `true` and no [`proxy`](Self::proxy) is set, the proxy is taken from the
+    /// `http_proxy`, `https_proxy`, `all_proxy` and `no_proxy` environment variables, see
+    /// [`Proxy::from_env`]. The default value is `false`.
+    pub proxy_from_env: bool,
+    /// The time a single TCP connection attempt may take. The default value is `None`, leaving
+    /// it to the operating system, which may take minutes to give up on an unreachable address.
+    pub connect_timeout: Option<Duration>,
+    /// The delay before racing a connection attempt to the next address while the previous
+    /// attempts are still pending, as described in RFC 8305 ("Happy Eyeballs"). The addresses
+    /// are tried alternating between IPv6 and IPv4, with at most four attempts pending at the
+    /// same time. A failed attempt starts the next one right away. With `None` the addresses are
+    /// tried one after another. The default value is 250ms.
+    pub happy_eyeballs_delay: Option<Duration>,
+    /// The time the whole TLS handshake may take. The default value is `None`.
+    pub tls_timeout: Option<Duration>,
+    /// The time the whole WebSocket handshake, or the handshake with the proxy, may take. The
+    /// default value is `None`.
+    pub handshake_timeout: Option<Duration>,
+    /// The limits for reading the handshake response, including a deadline for the whole
+    /// WebSocket handshake. The default value is [`HandshakeConfig::default`].
+    pub handshake_config: HandshakeConfig,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            config: None,
+            redirect: RedirectPolicy::default(),
+            proxy: None,
+            proxy_from_env: false,
+            connect_timeout: None,
+            happy_eyeballs_delay: Some(Duration::from_millis(250)),
+            tls_timeout: None,
+            handshake_timeout: None,
+            handshake_config: HandshakeConfig::default(),
+        }
+    }
+}
+
+impl ConnectOptions {
+    /// Set [`Self::config`].
+    pub fn config(mut self, config: Option<WebSocketConfig>) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set [`Self::redirect`].
+    pub fn redirect(mut self, redirect: RedirectPolicy) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    /// Set [`RedirectPolicy::max_redirects`] of [`Self::redirect`].
+    pub fn max_redirects(mut self, max_redirects: u8) -> Self {
+        self.redirect.max_redirects = max_redirects;
+        self
+    }
+
+    /// Set [`Self::proxy`].
+    pub fn proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Set [`Self::proxy_from_env`].
+    pub fn proxy_from_env(mut self, proxy_from_env: bool) -> Self {
+        self.proxy_from_env = proxy_from_env;
+        self
+    }
+
+    /// Set [`Self::connect_timeout`].
+    pub fn connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Set [`Self::happy_eyeballs_delay`].
+    pub fn happy_eyeballs_delay(mut self, happy_eyeballs_delay: Option<Duration>) -> Self {
+        self.happy_eyeballs_delay = happy_eyeballs_delay;
+        self
+    }
+
+    /// Set [`Self::tls_timeout`].
+    pub fn tls_timeout(mut self, tls_timeout: Option<Duration>) -> Self {
+        self.tls_timeout = tls_timeout;
+        self
+    }
+
+    /// Set [`Self::handshake_timeout`].
+    pub fn handshake_timeout(mut self, handshake_timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Set [`Self::handshake_config`].
+    pub fn handshake_config(mut self, handshake_config: HandshakeConfig) -> Self {
+        self.handshake_config = handshake_config;
+        self
+    }
+}
+
+/// How [`connect_with_options`] follows HTTP redirects answering the handshake.
+///
+/// Relative `Location` values are resolved against the URI of the redirected request. The `Host`
+/// and `Sec-WebSocket-Key` headers are updated for every hop, other headers are replayed unless
+/// the redirect leads to another origin and they are listed in [`Self::sensitive_headers`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RedirectPolicy {
+    /// The maximum number of redirects to follow. The default value is 3.
+    pub max_redirects: u8,
+    /// Follow redirects from `wss://` (or `https://`) to `ws://` (or `http://`). The default
+    /// value is `false`.
+    pub allow_tls_downgrade: bool,
+    /// Follow redirects to another origin, i.e. another scheme, host or port. The default value
+    /// is `true`.
+    pub allow_cross_origin: bool,
+    /// Headers removed from the request when following a redirect to another origin. They are
+    /// not sent again, even if a later redirect leads back. The default value contains
+    /// `Authorization`, `Proxy-Authorization` and `Cookie`.
+    pub sensitive_headers: Vec<HeaderName>,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: 3,
+            allow_tls_downgrade: false,
+            allow_cross_origin: true,
+            sensitive_headers: vec![
+                header::AUTHORIZATION,
+                header::PROXY_AUTHORIZATION,
+                header::COOKIE,
+            ],
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// A policy following no redirects.
+    pub fn none() -> Self {
+        Self { max_redirects: 0, ..Self::default() }
+    }
+
+    /// Set [`Self::max_redirects`].
+    pub fn max_redirects(mut self, max_redirects: u8) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Set [`Self::allow_tls_downgrade`].
+    pub fn allow_tls_downgrade(mut self, allow_tls_downgrade: bool) -> Self {
+        self.allow_tls_downgrade = allow_tls_downgrade;
+        self
+    }
+
+    /// Set [`Self::allow_cross_origin`].
+    pub fn allow_cross_origin(mut self, allow_cross_origin: bool) -> Self {
+        self.allow_cross_origin = allow_cross_origin;
+        self
+    }
+
+    /// Set [`Self::sensitive_headers`].
+    pub fn sensitive_headers(mut self, sensitive_headers: Vec<HeaderName>) -> Self {
+        self.sensitive_headers = sensitive_headers;
+        self
+    }
+
+    /// Check whether the redirect from `from` to `to` may be followed and adjust the headers of
+    /// the request accordingly.
+    fn follow(&self, from: &Uri, to: &Uri, headers: &mut HeaderMap) -> Result<()> {
+        if let (Mode::Tls, Mode::Plain) = (uri_mode(from)?, uri_mode(to)?) {
+            if !self.allow_tls_downgrade {
+                return Err(Error::Url(UrlError::TlsDowngradeRedirect(to.to_string())));
+            }
+        }
+        if origin(from)? != origin(to)? {
+            if !self.allow_cross_origin {
+                return Err(Error::Url(UrlError::CrossOriginRedirect(to.to_string())));
+            }
+            for name in &self.sensitive_headers {
+                headers.remove(name);
+            }
+        }
+
+        let authority = to.authority().ok_or(Error::Url(UrlError::NoHostName))?.as_str();
+        let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+        headers.insert(header::HOST, host.parse()?);
+        if headers.contains_key(header::SEC_WEBSOCKET_KEY) {
+            headers.insert(header::SEC_WEBSOCKET_KEY, generate_key().parse()?);
+        }
+        Ok(())
+    }
+}
+
+/// The URI the handshake of [`connect_with_options`] succeeded with after following redirects,
+/// available from the extensions of the returned [`Response`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalUri(pub Uri);
+
+/// The scheme, host and port identifying the origin of the URI. `ws` and `http` as well as `wss`
+/// and `https` are considered the same scheme.
+fn origin(uri: &Uri) -> Result<(&str, String, u16)> {
+    let scheme = match uri.scheme_str() {
+        Some("ws") | Some("http") => "http",
+        Some("wss") | Some("https") => "https",
+        scheme => scheme.ok_or(Error::Url(UrlError::UnsupportedUrlScheme))?,
+    };
+    let host = uri.host().ok_or(Error::Url(UrlError::NoHostName))?.to_ascii_lowercase();
+    let port = uri.port_u16().unwrap_or(match uri_mode(uri)? {
+        Mode::Plain => 80,
+        Mode::Tls => 443,
+    });
+    Ok((scheme, host, port))
+}
+
+/// Resolve the value of a `Location` header against the URI of the redirected request
+/// (RFC 3986 section 5.2).
+fn resolve_location(base: &Uri, location: &str) -> Result<Uri> {
+    // Fragments are never sent to the server.
+    let location = location.split('#').next().unwrap_or_default();
+    let is_absolute = location.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    if is_absolute {
+        return Ok(location.parse()?);
+    }
+
+    let scheme = base.scheme_str().ok_or(Error::Url(UrlError::UnsupportedUrlScheme))?;
+    let authority = base.authority().ok_or(Error::Url(UrlError::NoHostName))?;
+    let base_path = base.path();
+    let resolved = if location.starts_with("//") {
+        format!("{scheme}:{location}")
+    } else if location.starts_with('/') {
+        format!("{scheme}://{authority}{}", remove_dot_segments(location))
+    } else if location.is_empty() || location.starts_with('?') {
+        let query = if location.is_empty() { base.query().map(|q| format!("?{q}")) } else { None };
+        format!("{scheme}://{authority}{base_path}{}", query.as_deref().unwrap_or(location))
+    } else {
+        let directory = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+        let path = format!("{}{location}", if directory.is_empty() { "/" } else { directory });
+        format!("{scheme}://{authority}{}", remove_dot_segments(&path))
+    };
+    Ok(resolved.parse()?)
+}
+
+/// Remove `.` and `..` segments from an absolute path followed by an optional query.
+fn remove_dot_segments(path_and_query: &str) -> String {
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+    let mut segments = Vec::new();
+    for segment in path.split('/').skip(1) {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut resolved: String = segments.iter().flat_map(|segment| ["/", segment]).collect();
+    if resolved.is_empty() || path.ends_with("/.") || path.ends_with("/..") {
+        resolved.push('/');
+    }
+    if let Some(query) = query {
+        resolved.push('?');
+        resolved.push_str(query);
+    }
+    resolved
+}
+
+/// Connect to the given WebSocket in blocking mode with the given options.
+///
+/// Works like [`connect_with_config`], additionally supporting proxies, timeouts and a
+/// [`RedirectPolicy`]. The URI the handshake finally succeeded with is available as [`FinalUri`]
+/// from the extensions of the returned [`Response`].
+pub fn connect_with_options<Req: IntoClientRequest>(
+    request: Req,
+    options: ConnectOptions,
 ) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
     fn try_client_handshake(
         request: Request,
-        config: Option<WebSocketConfig>,
+        options: &ConnectOptions,
     ) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
         let uri = request.uri();
         let mode = uri_mode(uri)?;
@@ -57,44 +369,77 @@ pub fn connect_with_config<Req: IntoClientRequest>(
             return Err(Error::Url(UrlError::TlsFeatureNotEnabled));
         }
 
+        // Unix domain sockets have a stream type of their own, see `connect_unix`.
+        #[cfg(unix)]
+        if uri.scheme_str() == Some(UNIX_SCHEME) {
+            return Err(Error::Url(UrlError::UnsupportedUrlScheme));
+        }
+
         let host = request.uri().host().ok_or(Error::Url(UrlError::NoHostName))?;
         let host = if host.starts_with('[') { &host[1..host.len() - 1] } else { host };
         let port = uri.port_u16().unwrap_or(match mode {
             Mode::Plain => 80,
             Mode::Tls => 443,
         });
-        let addrs = (host, port).to_socket_addrs()?;
-        let mut stream = connect_to_some(addrs.as_slice(), request.uri())?;
+        let proxy = match &options.proxy {
+            Some(proxy) => Some(proxy.clone()),
+            None if options.proxy_from_env => Proxy::from_env(uri)?,
+            None => None,
+        };
+        let mut stream = match proxy {
+            Some(proxy) => proxy.connect_with_options(host, port, options)?,
+            None => {
+                let addrs = (host, port).to_socket_addrs()?;
+                connect_to_some(addrs.as_slice(), &uri.to_string(), options)?
+            }
+        };
         NoDelay::set_nodelay(&mut stream, true)?;
 
+        // Keep a handle to the socket to enforce the deadlines once it is wrapped.
+        let socket = stream.try_clone()?;
+
         #[cfg(not(any(feature = "native-tls", feature = "__rustls-tls")))]
-        let client = client_with_config(request, MaybeTlsStream::Plain(stream), config);
+        let stream = MaybeTlsStream::Plain(stream);
         #[cfg(any(feature = "native-tls", feature = "__rustls-tls"))]
-        let client = crate::tls::client_tls_with_config(request, stream, config, None);
+        let stream = with_deadline(&socket, options.tls_timeout, || {
+            #[allow(unused_mut)]
+            let mut stream = crate::tls::wrap_stream(stream, uri, None)?;
+            // rustls handshakes lazily, complete it while the TLS deadline applies.
+            #[cfg(feature = "__rustls-tls")]
+            if let MaybeTlsStream::Rustls(tls) = &mut stream {
+                while tls.conn.is_handshaking() {
+                    tls.conn.complete_io(&mut tls.sock)?;
+                }
+            }
+            Ok(stream)
+        })?;
 
-        client.map_err(|e| match e {
-            HandshakeError::Failure(f) => f,
-            HandshakeError::Interrupted(_) => panic!("Bug: blocking handshake not blocked"),
-        })
+        with_deadline(&socket, options.handshake_timeout, || handshake(request, stream, options))
     }
 
     fn create_request(parts: &Parts, uri: &Uri) -> Request {
         let mut builder =
             Request::builder().uri(uri.clone()).method(parts.method.clone()).version(parts.version);
         *builder.headers_mut().expect("Failed to create `Request`") = parts.headers.clone();
+        *builder.extensions_mut().expect("Failed to create `Request`") = parts.extensions.clone();
         builder.body(()).expect("Failed to create `Request`")
     }
 
-    let (parts, _) = request.into_client_request()?.into_parts();
+    let (mut parts, _) = request.into_client_request()?.into_parts();
     let mut uri = parts.uri.clone();
 
-    for attempt in 0..=max_redirects {
+    let policy = &options.redirect;
+    for attempt in 0..=policy.max_redirects {
         let request = create_request(&parts, &uri);
 
-        match try_client_handshake(request, config) {
-            Err(Error::Http(res)) if res.status().is_redirection() && attempt < max_redirects => {
+        match try_client_handshake(request, &options) {
+            Err(Error::Http(res))
+                if res.status().is_redirection() && attempt < policy.max_redirects =>
+            {
                 if let Some(location) = res.headers().get("Location") {
-                    uri = location.to_str()?.parse::<Uri>()?;
+                    let location = resolve_location(&uri, location.to_str()?)?;
+                    policy.follow(&uri, &location, &mut parts.headers)?;
+                    uri = location;
                     debug!("Redirecting to {uri:?}");
                     continue;
                 } else {
@@ -102,13 +447,67 @@ pub fn connect_with_config<Req: IntoClientRequest>(
                     return Err(Error::Http(res));
                 }
             }
-            other => return other,
+            Ok((socket, mut response)) => {
+                response.extensions_mut().insert(FinalUri(uri));
+                return Ok((socket, response));
+            }
+            Err(err) => return Err(err),
         }
     }
 
     unreachable!("Bug in a redirect handling logic")
 }
 
+/// Do the WebSocket handshake over a blocking stream.
+fn handshake<S: Read + Write>(
+    request: Request,
+    stream: S,
+    options: &ConnectOptions,
+) -> Result<(WebSocket<S>, Response)> {
+    let handshake = ClientHandshake::start(stream, request, options.config.clone())?;
+    handshake.with_handshake_config(options.handshake_config).handshake().map_err(|e| match e {
+        HandshakeError::Failure(f) => timed_out(f),
+        // A blocking socket with a read or write timeout.
+        HandshakeError::Interrupted(_) if options.handshake_timeout.is_some() => {
+            timed_out(Error::Io(io::ErrorKind::WouldBlock.into()))
+        }
+        HandshakeError::Interrupted(_) => panic!("Bug: blocking handshake not blocked"),
+    })
+}
+
+/// Connect to a WebSocket over a Unix domain socket in blocking mode.
+///
+/// The URL has the form `ws+unix:///path/to.sock:/resource`, see [`UnixSocketPath`]. Requests
+/// of other types must carry a [`UnixSocketPath`] in their extensions and use the `ws+unix`
+/// scheme. Other URLs are rejected, as are `ws+unix` URLs by [`connect`].
+#[cfg(unix)]
+pub fn connect_unix<Req: IntoClientRequest>(
+    request: Req,
+) -> Result<(WebSocket<UnixStream>, Response)> {
+    connect_unix_with_options(request, ConnectOptions::default())
+}
+
+/// Connect to a WebSocket over a Unix domain socket in blocking mode with the given options.
+///
+/// Works like [`connect_unix`]. Of the [`ConnectOptions`] only the WebSocket configuration, the
+/// handshake timeout and the handshake limits apply: there are no redirects, proxies or TLS.
+#[cfg(unix)]
+pub fn connect_unix_with_options<Req: IntoClientRequest>(
+    request: Req,
+    options: ConnectOptions,
+) -> Result<(WebSocket<UnixStream>, Response)> {
+    let request = request.into_client_request()?;
+    if request.uri().scheme_str() != Some(UNIX_SCHEME) {
+        return Err(Error::Url(UrlError::UnsupportedUrlScheme));
+    }
+    let UnixSocketPath(path) =
+        request.extensions().get().cloned().ok_or(Error::Url(UrlError::NoUnixSocketPath))?;
+    debug!("Trying to contact {} at {}...", request.uri(), path.display());
+    let stream = UnixStream::connect(path)?;
+    let socket = stream.try_clone()?;
+    with_deadline(&socket, options.handshake_timeout, || handshake(request, stream, &options))
+}
+
 /// Connect to the given WebSocket in blocking mode.
 ///
 /// The URL may be either ws:// or wss://.
@@ -127,14 +526,193 @@ pub fn connect<Req: IntoClientRequest>(
     connect_with_config(request, None, 3)
 }
 
-fn connect_to_some(addrs: &[SocketAddr], uri: &Uri) -> Result<TcpStream> {
-    for addr in addrs {
-        debug!("Trying to contact {uri} at {addr}...");
-        if let Ok(stream) = TcpStream::connect(addr) {
-            return Ok(stream);
+/// Connect to one of the addresses of `target`, honouring the connect timeout and racing the
+/// attempts if [`ConnectOptions::happy_eyeballs_delay`] is set.
+pub(crate) fn connect_to_some(
+    addrs: &[SocketAddr],
+    target: &str,
+    options: &ConnectOptions,
+) -> Result<TcpStream> {
+    let addrs = interleave_families(addrs);
+    let mut attempts = Vec::new();
+    let connected = match options.happy_eyeballs_delay {
+        Some(delay) if addrs.len() > 1 => {
+            race(&addrs, target, delay, options.connect_timeout, &mut attempts)
         }
+        _ => addrs.iter().find_map(|&addr| {
+            connect_addr(addr, target, options.connect_timeout)
+                .map_err(|err| attempts.push((addr, err)))
+                .ok()
+        }),
+    };
+    connected.ok_or_else(|| Error::Connect(ConnectError::new(target, attempts)))
+}
+
+/// Order the addresses alternating between IPv6 and IPv4, starting with the family of the first
+/// address (RFC 8305 section 4).
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else { return Vec::new() };
+    let (preferred, other): (Vec<_>, Vec<_>) =
+        addrs.iter().partition(|addr| addr.is_ipv6() == first.is_ipv6());
+    let mut other = other.into_iter();
+    let mut ordered = Vec::with_capacity(addrs.len());
+    for addr in preferred {
+        ordered.push(addr);
+        ordered.extend(other.next());
+    }
+    ordered.extend(other);
+    ordered
+}
+
+/// The maximum number of connection attempts [`race`] keeps pending at the same time.
+const MAX_RACING_ATTEMPTS: usize = 4;
+
+/// Start a connection attempt every `delay` or as soon as the previous one failed, returning the
+/// first established connection. At most [`MAX_RACING_ATTEMPTS`] attempts are pending at the
+/// same time, further ones wait for one of them to fail.
+///
+/// Attempts still pending when a connection is established finish in the background and
+/// are dropped.
+fn race(
+    addrs: &[SocketAddr],
+    target: &str,
+    delay: Duration,
+    timeout: Option<Duration>,
+    attempts: &mut Vec<(SocketAddr, io::Error)>,
+) -> Option<TcpStream> {
+    let (tx, rx) = mpsc::channel();
+    let mut pending = addrs.iter().copied();
+    let mut running = 0;
+    loop {
+        if running < MAX_RACING_ATTEMPTS {
+            if let Some(addr) = pending.next() {
+                let (tx, target) = (tx.clone(), target.to_owned());
+                thread::spawn(move || {
+                    let _ = tx.send((addr, connect_addr(addr, &target, timeout)));
+                });
+                running += 1;
+            }
+        }
+        if running == 0 {
+            return None;
+        }
+
+        let (addr, result) = if pending.len() > 0 && running < MAX_RACING_ATTEMPTS {
+            match rx.recv_timeout(delay) {
+                Ok(received) => received,
+                Err(_) => continue,
+            }
+        } else {
+            rx.recv().ok()?
+        };
+        running -= 1;
+        match result {
+            Ok(stream) => return Some(stream),
+            Err(err) => attempts.push((addr, err)),
+        }
+    }
+}
+
+fn connect_addr(
+    addr: SocketAddr,
+    target: &str,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    debug!("Trying to contact {target} at {addr}...");
+    let result = match timeout {
+        Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+        None => TcpStream::connect(addr),
+    };
+    if let Err(err) = &result {
+        debug!("Failed to contact {target} at {addr}: {err}");
+    }
+    result
+}
+
+/// A blocking socket a deadline can be enforced on, see [`with_deadline`].
+pub(crate) trait Socket: Sized + Send + 'static {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn clone_handle(&self) -> io::Result<Self>;
+    fn shut_down(&self) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn clone_handle(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn shut_down(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn clone_handle(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn shut_down(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+/// Run `phase`, e.g. a handshake, over a blocking socket within `timeout` in total.
+///
+/// Socket timeouts only limit a single read or write, so a peer sending a byte at a time would
+/// keep the phase going. Instead a watchdog thread shuts the socket down once the deadline
+/// passes, failing the read or write blocked at that moment, even inside a TLS library. Errors
+/// after the deadline are reported as [`io::ErrorKind::TimedOut`].
+pub(crate) fn with_deadline<S: Socket, T>(
+    socket: &S,
+    timeout: Option<Duration>,
+    phase: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let Some(timeout) = timeout else { return phase() };
+    // Also bounds a single read or write if the socket cannot be shut down.
+    socket.set_timeouts(Some(timeout))?;
+    let watched = socket.clone_handle()?;
+    let expired = Arc::new(AtomicBool::new(false));
+    let (cancel, cancelled) = mpsc::channel::<()>();
+    let watchdog = {
+        let expired = expired.clone();
+        thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+                expired.store(true, Ordering::Relaxed);
+                let _ = watched.shut_down();
+            }
+        })
+    };
+
+    let result = phase();
+    drop(cancel);
+    let _ = watchdog.join();
+    if expired.load(Ordering::Relaxed) {
+        return Err(Error::Io(io::ErrorKind::TimedOut.into()));
+    }
+    socket.set_timeouts(None)?;
+    result.map_err(timed_out)
+}
+
+/// Report a blocking socket running into its read or write timeout as [`io::ErrorKind::TimedOut`]
+/// on every platform.
+pub(crate) fn timed_out(err: Error) -> Error {
+    match err {
+        Error::Io(err) if err.kind() == io::ErrorKind::WouldBlock => {
+            Error::Io(io::ErrorKind::TimedOut.into())
+        }
+        err => err,
     }
-    Err(Error::Url(UrlError::UnableToConnect(uri.to_string())))
 }
 
 /// Get the mode of the given URL.
@@ -145,6 +723,8 @@ pub fn uri_mode(uri: &Uri) -> Result<Mode> {
     match uri.scheme_str() {
         Some("ws") | Some("http") => Ok(Mode::Plain),
         Some("wss") | Some("https") => Ok(Mode::Tls),
+        #[cfg(unix)]
+        Some(UNIX_SCHEME) => Ok(Mode::Plain),
         _ => Err(Error::Url(UrlError::UnsupportedUrlScheme)),
     }
 }
@@ -198,10 +778,46 @@ pub trait IntoClientRequest {
 
 impl IntoClientRequest for &str {
     fn into_client_request(self) -> Result<Request> {
+        #[cfg(unix)]
+        if let Some(socket) =
+            self.strip_prefix(UNIX_SCHEME).and_then(|rest| rest.strip_prefix("://"))
+        {
+            return unix_socket_request(socket);
+        }
         self.parse::<Uri>()?.into_client_request()
     }
 }
 
+/// The scheme of WebSocket URLs connecting over a Unix domain socket.
+#[cfg(unix)]
+const UNIX_SCHEME: &str = "ws+unix";
+
+/// The path of the Unix domain socket a `ws+unix://` request connects to, stored in the
+/// extensions of the request.
+///
+/// Requests created from `ws+unix:///path/to.sock:/resource` URLs carry it along with the URI
+/// `ws+unix://localhost/resource`. The socket path ends at the first colon followed by a slash
+/// or at the end of the URL, so it may contain colons but not `:/`. Without a resource, `/` is
+/// requested.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSocketPath(pub PathBuf);
+
+#[cfg(unix)]
+fn unix_socket_request(url: &str) -> Result<Request> {
+    let (socket, resource) = match url.find(":/") {
+        Some(index) => (&url[..index], &url[index + 1..]),
+        None => (url.strip_suffix(':').unwrap_or(url), "/"),
+    };
+    if socket.is_empty() {
+        return Err(Error::Url(UrlError::NoUnixSocketPath));
+    }
+    let uri: Uri = format!("{UNIX_SCHEME}://localhost{resource}").parse()?;
+    let mut request = uri.into_client_request()?;
+    request.extensions_mut().insert(UnixSocketPath(socket.into()));
+    Ok(request)
+}
+
 impl IntoClientRequest for &String {
     fn into_client_request(self) -> Result<Request> {
         <&str as IntoClientRequest>::into_client_request(self)
@@ -296,13 +912,23 @@ pub struct ClientRequestBuilder {
     additional_headers: Vec<(String, String)>,
     /// Handsake subprotocols
     subprotocols: Vec<String>,
+    /// Query parameters appended to the URI
+    query_params: Vec<(String, String)>,
+    /// Cookies sent in a single `Cookie` header
+    cookies: Vec<(String, String)>,
 }
 
 impl ClientRequestBuilder {
     /// Initializes an empty request builder
     #[must_use]
     pub const fn new(uri: Uri) -> Self {
-        Self { uri, additional_headers: Vec::new(), subprotocols: Vec::new() }
+        Self {
+            uri,
+            additional_headers: Vec::new(),
+            subprotocols: Vec::new(),
+            query_params: Vec::new(),
+            cookies: Vec::new(),
+        }
     }
 
     /// Adds (`key`, `value`) as an additional header to the handshake request
@@ -323,11 +949,76 @@ impl ClientRequestBuilder {
         self.subprotocols.push(protocol.into());
         self
     }
+
+    /// Adds an `Authorization: Bearer <token>` header to the handshake request
+    pub fn with_bearer_token<T>(self, token: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.with_header("Authorization", format!("Bearer {}", token.into()))
+    }
+
+    /// Adds an `Authorization: Basic` header with `username` and `password` to the handshake
+    /// request
+    pub fn with_basic_auth<U, P>(self, username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let credentials = format!("{}:{}", username.into(), password.into());
+        let encoded = data_encoding::BASE64.encode(credentials.as_bytes());
+        self.with_header("Authorization", format!("Basic {encoded}"))
+    }
+
+    /// Adds the query parameter `name` with the percent-encoded `token` to the request URI
+    pub fn with_query_token<N, T>(mut self, name: N, token: T) -> Self
+    where
+        N: Into<String>,
+        T: Into<String>,
+    {
+        self.query_params.push((name.into(), token.into()));
+        self
+    }
+
+    /// Adds the cookie `name` with `value` to the `Cookie` header of the handshake request
+    pub fn with_cookie<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.cookies.push((name.into(), value.into()));
+        self
+    }
+
+    /// Adds `prefix` followed by `token` to the handshake request subprotocols, for servers
+    /// reading tokens from `Sec-WebSocket-Protocol` like browser clients have to send them
+    pub fn with_protocol_token<P, T>(self, prefix: P, token: T) -> Self
+    where
+        P: Into<String>,
+        T: Into<String>,
+    {
+        self.with_sub_protocol(format!("{}{}", prefix.into(), token.into()))
+    }
 }
 
 impl IntoClientRequest for ClientRequestBuilder {
     fn into_client_request(self) -> Result<Request> {
-        let mut request = self.uri.into_client_request()?;
+        let uri = if self.query_params.is_empty() {
+            self.uri
+        } else {
+            let mut path_and_query =
+                self.uri.path_and_query().map_or("/", |pq| pq.as_str()).to_owned();
+            for (name, value) in &self.query_params {
+                path_and_query.push(if path_and_query.contains('?') { '&' } else { '?' });
+                path_and_query.push_str(&percent_encode(name));
+                path_and_query.push('=');
+                path_and_query.push_str(&percent_encode(value));
+            }
+            let mut parts = self.uri.into_parts();
+            parts.path_and_query = Some(path_and_query.parse()?);
+            Uri::from_parts(parts).map_err(http::Error::from)?
+        };
+        let mut request = uri.into_client_request()?;
         let headers = request.headers_mut();
         for (k, v) in self.additional_headers {
             let key = HeaderName::try_from(k)?;
@@ -338,6 +1029,123 @@ impl IntoClientRequest for ClientRequestBuilder {
             let protocols = self.subprotocols.join(", ").parse()?;
             headers.append("Sec-WebSocket-Protocol", protocols);
         }
+        if !self.cookies.is_empty() {
+            let cookies: Vec<_> =
+                self.cookies.iter().map(|(name, value)| format!("{name}={value}")).collect();
+            headers.append(header::COOKIE, cookies.join("; ").parse()?);
+        }
         Ok(request)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        time::{Duration, Instant},
+    };
+
+    use http::{header, HeaderMap, Uri};
+
+    use super::{interleave_families, race, resolve_location, RedirectPolicy};
+    use crate::error::{Error, UrlError};
+
+    #[test]
+    fn interleaves_address_families() {
+        let addrs: Vec<SocketAddr> =
+            ["[::1]:1", "[::2]:1", "[::3]:1", "127.0.0.1:1", "127.0.0.2:1"]
+                .iter()
+                .map(|addr| addr.parse().unwrap())
+                .collect();
+        let ordered: Vec<_> = interleave_families(&addrs).iter().map(|a| a.to_string()).collect();
+        assert_eq!(ordered, ["[::1]:1", "127.0.0.1:1", "[::2]:1", "127.0.0.2:1", "[::3]:1"]);
+    }
+
+    #[test]
+    fn failed_attempt_starts_next_right_away() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [closed, listener.local_addr().unwrap()];
+
+        let start = Instant::now();
+        let mut attempts = Vec::new();
+        let stream = race(&addrs, "test", Duration::from_secs(30), None, &mut attempts);
+        assert_eq!(stream.unwrap().peer_addr().unwrap(), addrs[1]);
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].0, closed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_urls() {
+        use super::{IntoClientRequest, UnixSocketPath};
+
+        let request = "ws+unix:///tmp/agent.sock:/events?x=1".into_client_request().unwrap();
+        assert_eq!(request.uri(), "ws+unix://localhost/events?x=1");
+        assert_eq!(request.headers()[header::HOST], "localhost");
+        let path = request.extensions().get::<UnixSocketPath>().unwrap();
+        assert_eq!(path.0.to_str(), Some("/tmp/agent.sock"));
+
+        let request = "ws+unix:///tmp/agent.sock".into_client_request().unwrap();
+        assert_eq!(request.uri(), "ws+unix://localhost/");
+
+        // Colons not followed by a slash belong to the socket path.
+        let request = "ws+unix:///run/agent:1.sock:/events".into_client_request().unwrap();
+        assert_eq!(request.uri(), "ws+unix://localhost/events");
+        let path = request.extensions().get::<UnixSocketPath>().unwrap();
+        assert_eq!(path.0.to_str(), Some("/run/agent:1.sock"));
+        let request = "ws+unix:///run/agent:1.sock".into_client_request().unwrap();
+        let path = request.extensions().get::<UnixSocketPath>().unwrap();
+        assert_eq!(path.0.to_str(), Some("/run/agent:1.sock"));
+
+        let result = "ws+unix://:/events".into_client_request();
+        assert!(matches!(result, Err(Error::Url(UrlError::NoUnixSocketPath))));
+    }
+
+    #[test]
+    fn resolves_locations() {
+        let base: Uri = "wss://example.com:9001/a/b/socket?x=1".parse().unwrap();
+        for (location, expected) in [
+            ("ws://other.org/ws", "ws://other.org/ws"),
+            ("//other.org/ws", "wss://other.org/ws"),
+            ("/ws?y=2", "wss://example.com:9001/ws?y=2"),
+            ("other", "wss://example.com:9001/a/b/other"),
+            ("../c/./d#fragment", "wss://example.com:9001/a/c/d"),
+            ("../../..", "wss://example.com:9001/"),
+            ("?y=2", "wss://example.com:9001/a/b/socket?y=2"),
+            ("", "wss://example.com:9001/a/b/socket?x=1"),
+        ] {
+            assert_eq!(resolve_location(&base, location).unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn redirect_policy() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "example.com".parse().unwrap());
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        headers.insert(header::SEC_WEBSOCKET_KEY, "key".parse().unwrap());
+        let from: Uri = "wss://example.com/ws".parse().unwrap();
+
+        // Same origin keeps the credentials.
+        let policy = RedirectPolicy::default();
+        let to: Uri = "wss://EXAMPLE.com:443/other".parse().unwrap();
+        policy.follow(&from, &to, &mut headers).unwrap();
+        assert!(headers.contains_key(header::AUTHORIZATION));
+        assert_ne!(headers[header::SEC_WEBSOCKET_KEY], "key");
+
+        let downgrade: Uri = "ws://example.com/ws".parse().unwrap();
+        let result = policy.follow(&from, &downgrade, &mut headers.clone());
+        assert!(matches!(result, Err(Error::Url(UrlError::TlsDowngradeRedirect(_)))));
+
+        let cross_origin: Uri = "wss://user@other.org:8443/ws".parse().unwrap();
+        let result =
+            policy.clone().allow_cross_origin(false).follow(&from, &cross_origin, &mut headers);
+        assert!(matches!(result, Err(Error::Url(UrlError::CrossOriginRedirect(_)))));
+
+        policy.follow(&from, &cross_origin, &mut headers).unwrap();
+        assert!(!headers.contains_key(header::AUTHORIZATION));
+        assert_eq!(headers[header::HOST], "other.org:8443");
+    }
+}
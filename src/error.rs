@@ -1,6 +1,6 @@
 //! Error handling.
 
-use std::{io, result, str, string};
+use std::{fmt, io, net::SocketAddr, result, str, string};
 
 use crate::protocol::{frame::coding::Data, Message};
 #[cfg(feature = "handshake")]
@@ -45,11 +45,14 @@ pub enum Error {
     /// to provide a feature-agnostic API surface.
     #[error("TLS error: {0}")]
     Tls(#[from] TlsError),
-    /// - When reading: buffer capacity exhausted.
-    /// - When writing: your message is bigger than the configured max message size
-    ///   (64MB by default).
+    /// Buffer capacity exhausted when reading, e.g. a message or frame is bigger than
+    /// the configured max message or frame size (64MB and 16MB by default).
     #[error("Space limit exceeded: {0}")]
     Capacity(#[from] CapacityError),
+    /// The outgoing message is bigger than the configured max outgoing message or frame size.
+    /// The message is handed back unchanged.
+    #[error("Outgoing space limit exceeded: {1}")]
+    OutgoingCapacity(Message, CapacityError),
     /// Protocol violation.
     #[error("WebSocket protocol error: {0}")]
     Protocol(#[from] ProtocolError),
@@ -65,6 +68,12 @@ pub enum Error {
     /// Invalid URL.
     #[error("URL error: {0}")]
     Url(#[from] UrlError),
+    /// Failed to connect through a proxy.
+    #[error("Proxy error: {0}")]
+    Proxy(#[from] ProxyError),
+    /// Failed to open a TCP connection to any address of the host.
+    #[error("Connection error: {0}")]
+    Connect(#[from] ConnectError),
     /// HTTP error.
     #[error("HTTP error: {}", .0.status())]
     #[cfg(feature = "handshake")]
@@ -139,6 +148,13 @@ pub enum CapacityError {
     #[error("Too many headers")]
     TooManyHeaders,
     /// Received header is too long.
+    #[error("Header too long: {size} > {max_size}")]
+    HeaderTooLong {
+        /// The size of the header, or the part of it received so far.
+        size: usize,
+        /// The maximum allowed header size.
+        max_size: usize,
+    },
     /// Message is bigger than the maximum allowed size.
     #[error("Message too long: {size} > {max_size}")]
     MessageTooLong {
@@ -188,6 +204,9 @@ pub enum ProtocolError {
     /// Missing `Sec-WebSocket-Key` HTTP header.
     #[error("No \"Sec-WebSocket-Key\" header")]
     MissingSecWebSocketKey,
+    /// Missing `:protocol: websocket` pseudo-header in an HTTP/2 extended CONNECT request.
+    #[error("No \":protocol: websocket\" pseudo-header")]
+    MissingConnectProtocol,
     /// The `Sec-WebSocket-Accept` header is either not present or does not specify the correct key value.
     #[error("Key mismatch in \"Sec-WebSocket-Accept\" header")]
     SecWebSocketAcceptKeyMismatch,
@@ -244,6 +263,9 @@ pub enum ProtocolError {
     /// Received data while waiting for more fragments.
     #[error("While waiting for more fragments received: {0}")]
     ExpectedFragment(Data),
+    /// Tried to send a message while a fragmented message is still being sent.
+    #[error("Cannot send a message while a fragmented message is in progress")]
+    FragmentedMessageInProgress,
     /// Connection closed without performing the closing handshake.
     #[error("Connection reset without closing handshake")]
     ResetWithoutClosingHandshake,
@@ -253,6 +275,9 @@ pub enum ProtocolError {
     /// The payload for the closing frame is invalid.
     #[error("Invalid close sequence")]
     InvalidCloseSequence,
+    /// A layer8 envelope contained data after the frame inside it.
+    #[error("Trailing data after the frame in a layer8 envelope")]
+    TrailingEnvelopeData,
 }
 
 /// Indicates the specific type/cause of URL error.
@@ -276,8 +301,78 @@ pub enum UrlError {
     /// The URL does not include a path/query.
     #[error("No path/query in URL")]
     NoPathOrQuery,
+    /// A redirect from `wss://` to `ws://` was refused, see `RedirectPolicy::allow_tls_downgrade`.
+    #[error("Refused to follow redirect from TLS to plain text: {0}")]
+    TlsDowngradeRedirect(String),
+    /// A `ws+unix://` URL does not include the path of the socket.
+    #[error("No Unix domain socket path in the URL")]
+    NoUnixSocketPath,
+    /// A redirect to another origin was refused, see `RedirectPolicy::allow_cross_origin`.
+    #[error("Refused to follow redirect to another origin: {0}")]
+    CrossOriginRedirect(String),
+}
+
+/// Indicates the specific type/cause of a proxy error.
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum ProxyError {
+    /// The proxy URL is invalid or uses an unsupported scheme.
+    #[error("Invalid proxy URL: {0}")]
+    InvalidUrl(String),
+    /// The HTTP proxy refused the `CONNECT` request.
+    #[error("HTTP proxy responded with status {0}")]
+    HttpConnect(u16),
+    /// The HTTP proxy sent an invalid response.
+    #[error("Invalid response from HTTP proxy")]
+    InvalidHttpResponse,
+    /// The SOCKS5 proxy supports none of the offered authentication methods.
+    #[error("SOCKS5 proxy requires an unsupported authentication method")]
+    Socks5NoAcceptableAuth,
+    /// The SOCKS5 proxy rejected the credentials.
+    #[error("SOCKS5 authentication failed")]
+    Socks5AuthFailed,
+    /// The SOCKS5 proxy failed to connect to the target.
+    #[error("SOCKS5 proxy failed to connect, reply code {0}")]
+    Socks5Connect(u8),
+    /// The target host name is longer than the 255 bytes a SOCKS5 request can carry.
+    #[error("Host name too long for a SOCKS5 proxy: {0}")]
+    Socks5HostTooLong(String),
+    /// The SOCKS5 proxy sent an invalid response.
+    #[error("Invalid response from SOCKS5 proxy")]
+    InvalidSocks5Response,
+}
+
+/// Failed to open a TCP connection to any address of a host, see [`Error::Connect`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ConnectError {
+    /// The host the connection was opened to.
+    pub target: String,
+    /// Every attempted address with the error it failed with, in the order of the failures.
+    pub attempts: Vec<(SocketAddr, io::Error)>,
 }
 
+impl ConnectError {
+    /// Create an error for the given host and failed attempts.
+    pub fn new(target: impl Into<String>, attempts: Vec<(SocketAddr, io::Error)>) -> Self {
+        Self { target: target.into(), attempts }
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to connect to {}", self.target)?;
+        if self.attempts.is_empty() {
+            return write!(f, ": no addresses");
+        }
+        for (i, (addr, err)) in self.attempts.iter().enumerate() {
+            write!(f, "{} {addr}: {err}", if i == 0 { ":" } else { "," })?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
 /// TLS errors.
 ///
 /// Note that even if you enable only the rustls-based TLS support, the error at runtime could still
@@ -13,9 +13,9 @@ use log::*;
 
 use super::{
     derive_accept_key,
-    headers::{FromHttparse, MAX_HEADERS},
+    headers::{header_buffer, FromHttparse},
     machine::{HandshakeMachine, StageResult, TryParse},
-    HandshakeRole, MidHandshake, ProcessingResult,
+    selected_subprotocol, HandshakeRole, MidHandshake, ProcessingResult,
 };
 use crate::{
     error::{Error, ProtocolError, Result, SubProtocolError, UrlError},
@@ -99,8 +99,9 @@ impl<S: Read + Write> HandshakeRole for ClientHandshake<S> {
                 };
 
                 debug!("Client handshake done.");
-                let websocket =
-                    WebSocket::from_partially_read(stream, tail, Role::Client, self.config);
+                let mut websocket =
+                    WebSocket::from_partially_read(stream, tail, Role::Client, self.config.clone());
+                websocket.set_subprotocol(selected_subprotocol(&result));
                 ProcessingResult::Done((websocket, result))
             }
         })
@@ -184,7 +185,7 @@ pub fn generate_request(mut request: Request) -> Result<(Vec<u8>, String)> {
     Ok((req, key))
 }
 
-fn extract_subprotocols_from_request(request: &Request) -> Result<Option<Vec<String>>> {
+pub(super) fn extract_subprotocols_from_request(request: &Request) -> Result<Option<Vec<String>>> {
     if let Some(subprotocols) = request.headers().get("Sec-WebSocket-Protocol") {
         Ok(Some(subprotocols.to_str()?.split(',').map(|s| s.trim().to_string()).collect()))
     } else {
@@ -255,35 +256,45 @@ impl VerifyData {
         // not present in the client's handshake (the server has indicated a
         // subprotocol not requested by the client), the client MUST _Fail
         // the WebSocket Connection_. (RFC 6455)
-        if headers.get("Sec-WebSocket-Protocol").is_none() && self.subprotocols.is_some() {
-            return Err(Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(
-                SubProtocolError::NoSubProtocol,
-            )));
-        }
+        verify_subprotocol(headers, &self.subprotocols)?;
 
-        if headers.get("Sec-WebSocket-Protocol").is_some() && self.subprotocols.is_none() {
-            return Err(Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(
-                SubProtocolError::ServerSentSubProtocolNoneRequested,
-            )));
-        }
+        Ok(response)
+    }
+}
+
+/// Check that the server selected one of the requested subprotocols, if any.
+pub(super) fn verify_subprotocol(
+    headers: &HeaderMap,
+    subprotocols: &Option<Vec<String>>,
+) -> Result<()> {
+    if headers.get("Sec-WebSocket-Protocol").is_none() && subprotocols.is_some() {
+        return Err(Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(
+            SubProtocolError::NoSubProtocol,
+        )));
+    }
+
+    if headers.get("Sec-WebSocket-Protocol").is_some() && subprotocols.is_none() {
+        return Err(Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(
+            SubProtocolError::ServerSentSubProtocolNoneRequested,
+        )));
+    }
 
-        if let Some(returned_subprotocol) = headers.get("Sec-WebSocket-Protocol") {
-            if let Some(accepted_subprotocols) = &self.subprotocols {
-                if !accepted_subprotocols.contains(&returned_subprotocol.to_str()?.to_string()) {
-                    return Err(Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(
-                        SubProtocolError::InvalidSubProtocol,
-                    )));
-                }
+    if let Some(returned_subprotocol) = headers.get("Sec-WebSocket-Protocol") {
+        if let Some(accepted_subprotocols) = subprotocols {
+            if !accepted_subprotocols.contains(&returned_subprotocol.to_str()?.to_string()) {
+                return Err(Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(
+                    SubProtocolError::InvalidSubProtocol,
+                )));
             }
         }
-
-        Ok(response)
     }
+
+    Ok(())
 }
 
 impl TryParse for Response {
     fn try_parse(buf: &[u8]) -> Result<Option<(usize, Self)>> {
-        let mut hbuffer = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut hbuffer = header_buffer(buf);
         let mut req = httparse::Response::new(&mut hbuffer);
         Ok(match req.parse(buf)? {
             Status::Partial => None,
@@ -0,0 +1,267 @@
+//! WebSocket handshakes over HTTP/2 with extended CONNECT ([RFC 8441]).
+//!
+//! Over HTTP/2 a WebSocket runs on a single stream of a multiplexed connection. The client
+//! opens the stream with a `CONNECT` request carrying the `:protocol: websocket` pseudo-header,
+//! the server accepts it with `200 OK`. There is no `Upgrade`, `Connection` or
+//! `Sec-WebSocket-Key` header.
+//!
+//! The HTTP/2 connection itself is managed by the caller, e.g. with the `h2` crate, and the
+//! server must advertise `SETTINGS_ENABLE_CONNECT_PROTOCOL`. This module creates and checks
+//! the request and response, with the `:protocol` pseudo-header stored as a [`Protocol`] in
+//! the request extensions. Once they are exchanged the stream, as a `Read + Write`, is turned
+//! into a WebSocket with [`WebSocket::from_raw_socket`](crate::WebSocket::from_raw_socket),
+//! using the frame layer unchanged.
+//!
+//! # Example
+//!
+//! ```rust no_run
+//! use std::io::{Read, Write};
+//!
+//! use layer8_tungstenite::{
+//!     handshake::{client, h2, server},
+//!     protocol::Role,
+//!     Result, WebSocket,
+//! };
+//!
+//! /// Open a WebSocket with `send_request`, which sends the request on a new HTTP/2 stream.
+//! fn connect<S: Read + Write>(
+//!     send_request: impl FnOnce(client::Request) -> (S, client::Response),
+//! ) -> Result<WebSocket<S>> {
+//!     let request = h2::create_request("wss://example.com/chat")?;
+//!     let (stream, response) = send_request(request.clone());
+//!     h2::verify_response(&request, response)?;
+//!     Ok(WebSocket::from_raw_socket(stream, Role::Client, None))
+//! }
+//!
+//! /// Accept a WebSocket on the HTTP/2 stream of `request`, answering with `send_response`.
+//! fn accept<S: Read + Write>(
+//!     request: &server::Request,
+//!     stream: S,
+//!     send_response: impl FnOnce(server::Response),
+//! ) -> Result<WebSocket<S>> {
+//!     send_response(h2::create_response(request)?);
+//!     Ok(WebSocket::from_raw_socket(stream, Role::Server, None))
+//! }
+//! ```
+//!
+//! [RFC 8441]: https://tools.ietf.org/html/rfc8441
+
+use http::{Method, StatusCode, Uri, Version};
+
+use super::{
+    client::{self, extract_subprotocols_from_request, verify_subprotocol},
+    server,
+};
+use crate::{
+    client::{uri_mode, IntoClientRequest},
+    error::{Error, ProtocolError, Result, UrlError},
+    stream::Mode,
+};
+
+/// Headers of an HTTP/1.1 handshake request which are not sent over HTTP/2.
+const HTTP1_HEADERS: [&str; 4] = ["Host", "Connection", "Upgrade", "Sec-WebSocket-Key"];
+
+/// The `:protocol` pseudo-header of an extended CONNECT request, stored in the request
+/// extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol(pub String);
+
+impl Protocol {
+    /// The `websocket` protocol.
+    pub fn websocket() -> Self {
+        Self("websocket".into())
+    }
+}
+
+/// Create the extended CONNECT request for a `ws://` or `wss://` URL.
+///
+/// Headers added to the request, e.g. by a
+/// [`ClientRequestBuilder`](crate::ClientRequestBuilder), are kept, except for the HTTP/1.1
+/// `Host`, `Connection`, `Upgrade` and `Sec-WebSocket-Key` headers. The URI is rewritten to
+/// the `http` or `https` scheme, which becomes the `:scheme` pseudo-header.
+pub fn create_request<Req: IntoClientRequest>(request: Req) -> Result<client::Request> {
+    let mut request = request.into_client_request()?;
+    let uri = request.uri();
+    let scheme = match uri_mode(uri)? {
+        Mode::Plain => "http",
+        Mode::Tls => "https",
+    };
+    let authority = uri.authority().ok_or(Error::Url(UrlError::NoHostName))?;
+    let path = uri.path_and_query().ok_or(Error::Url(UrlError::NoPathOrQuery))?;
+    let uri: Uri = format!("{scheme}://{authority}{path}").parse()?;
+
+    *request.uri_mut() = uri;
+    *request.method_mut() = Method::CONNECT;
+    *request.version_mut() = Version::HTTP_2;
+    for header in HTTP1_HEADERS {
+        request.headers_mut().remove(header);
+    }
+    request.headers_mut().insert("Sec-WebSocket-Version", "13".parse()?);
+    request.extensions_mut().insert(Protocol::websocket());
+    Ok(request)
+}
+
+/// Check an extended CONNECT request and create the `200 OK` response accepting it.
+///
+/// Subprotocols and other headers are added to the response like for HTTP/1.1 handshakes.
+pub fn create_response(request: &server::Request) -> Result<server::Response> {
+    if request.method() != Method::CONNECT {
+        return Err(Error::Protocol(ProtocolError::WrongHttpMethod));
+    }
+
+    if request.version() != Version::HTTP_2 {
+        return Err(Error::Protocol(ProtocolError::WrongHttpVersion));
+    }
+
+    if !request
+        .extensions()
+        .get::<Protocol>()
+        .map(|protocol| protocol.0.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false)
+    {
+        return Err(Error::Protocol(ProtocolError::MissingConnectProtocol));
+    }
+
+    if !request.headers().get("Sec-WebSocket-Version").map(|h| h == "13").unwrap_or(false) {
+        return Err(Error::Protocol(ProtocolError::MissingSecWebSocketVersionHeader));
+    }
+
+    Ok(server::Response::builder().status(StatusCode::OK).version(Version::HTTP_2).body(())?)
+}
+
+/// Check the response of the server to an extended CONNECT request.
+///
+/// Responses other than `2xx` are returned as [`Error::Http`]. The selected subprotocol is
+/// checked like for HTTP/1.1 handshakes.
+pub fn verify_response(
+    request: &client::Request,
+    response: client::Response,
+) -> Result<client::Response> {
+    if !response.status().is_success() {
+        return Err(Error::Http(response));
+    }
+
+    let subprotocols = extract_subprotocols_from_request(request)?;
+    verify_subprotocol(response.headers(), &subprotocols)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use http::{Method, StatusCode, Version};
+
+    use super::{create_request, create_response, verify_response, Protocol};
+    use crate::{
+        client::ClientRequestBuilder,
+        error::{Error, ProtocolError},
+        handshake::{client::Response, server::Request},
+        protocol::Role,
+        testing::duplex,
+        Message, WebSocket,
+    };
+
+    #[test]
+    fn extended_connect_request() {
+        let builder =
+            ClientRequestBuilder::new("wss://example.com:8443/chat?room=1".parse().unwrap())
+                .with_sub_protocol("chat")
+                .with_header("Origin", "https://example.com");
+        let request = create_request(builder).unwrap();
+
+        assert_eq!(request.method(), Method::CONNECT);
+        assert_eq!(request.version(), Version::HTTP_2);
+        assert_eq!(request.uri(), "https://example.com:8443/chat?room=1");
+        assert_eq!(request.extensions().get(), Some(&Protocol::websocket()));
+        assert_eq!(request.headers()["Sec-WebSocket-Version"], "13");
+        assert_eq!(request.headers()["Sec-WebSocket-Protocol"], "chat");
+        assert_eq!(request.headers()["Origin"], "https://example.com");
+        for header in ["Host", "Connection", "Upgrade", "Sec-WebSocket-Key"] {
+            assert!(!request.headers().contains_key(header), "{header}");
+        }
+
+        let response = create_response(&request).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+    }
+
+    #[test]
+    fn protocol_round_trip() {
+        let request = create_request("wss://example.com/chat").unwrap();
+        // The value sent as the `:protocol` pseudo-header.
+        let protocol = request.extensions().get::<Protocol>().unwrap().0.clone();
+        assert_eq!(protocol, "websocket");
+
+        // The request as an HTTP/2 server hands it over, with the received pseudo-header.
+        let received = |protocol: &str| {
+            let mut received = Request::builder()
+                .method(Method::CONNECT)
+                .version(Version::HTTP_2)
+                .uri(request.uri().clone())
+                .body(())
+                .unwrap();
+            *received.headers_mut() = request.headers().clone();
+            received.extensions_mut().insert(Protocol(protocol.to_owned()));
+            received
+        };
+        assert_eq!(create_response(&received(&protocol)).unwrap().status(), StatusCode::OK);
+        assert!(create_response(&received("WebSocket")).is_ok());
+        assert!(matches!(
+            create_response(&received("webtransport")),
+            Err(Error::Protocol(ProtocolError::MissingConnectProtocol))
+        ));
+    }
+
+    #[test]
+    fn rejects_http1_requests() {
+        let mut request = create_request("ws://example.com/").unwrap();
+        request.extensions_mut().clear();
+        assert!(matches!(
+            create_response(&request),
+            Err(Error::Protocol(ProtocolError::MissingConnectProtocol))
+        ));
+        *request.method_mut() = Method::GET;
+        assert!(matches!(
+            create_response(&request),
+            Err(Error::Protocol(ProtocolError::WrongHttpMethod))
+        ));
+    }
+
+    #[test]
+    fn verifies_response() {
+        let request = create_request(
+            ClientRequestBuilder::new("ws://example.com/".parse().unwrap())
+                .with_sub_protocol("chat"),
+        )
+        .unwrap();
+
+        let mut response = Response::new(None);
+        assert!(verify_response(&request, response.clone()).is_err());
+        response.headers_mut().insert("Sec-WebSocket-Protocol", "chat".parse().unwrap());
+        assert!(verify_response(&request, response.clone()).is_ok());
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        assert!(matches!(verify_response(&request, response), Err(Error::Http(_))));
+    }
+
+    #[test]
+    fn websocket_over_stream() {
+        let (mut client_stream, mut server_stream) = duplex();
+        client_stream.set_nonblocking(false);
+        server_stream.set_nonblocking(false);
+        let request = create_request("ws://example.com/echo").unwrap();
+        let response = create_response(&request).unwrap();
+        let response = verify_response(&request, response.map(|()| None)).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let server = thread::spawn(move || {
+            let mut socket = WebSocket::from_raw_socket(server_stream, Role::Server, None);
+            let message = socket.read().unwrap();
+            socket.send(message).unwrap();
+        });
+        let mut socket = WebSocket::from_raw_socket(client_stream, Role::Client, None);
+        socket.send(Message::text("over h2")).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::text("over h2"));
+        server.join().unwrap();
+    }
+}
@@ -6,9 +6,16 @@ use httparse::Status;
 use super::machine::TryParse;
 use crate::error::Result;
 
-/// Limit for the number of header lines.
+/// The default limit for the number of header lines, see
+/// [`HandshakeConfig::max_headers`](super::machine::HandshakeConfig::max_headers).
 pub const MAX_HEADERS: usize = 124;
 
+/// Header slots for parsing `head`, one per line. The number of lines is limited by the
+/// [`HandshakeMachine`](super::machine::HandshakeMachine) before the head is parsed.
+pub(crate) fn header_buffer<'b>(head: &[u8]) -> Vec<httparse::Header<'b>> {
+    vec![httparse::EMPTY_HEADER; head.iter().filter(|&&byte| byte == b'\n').count()]
+}
+
 /// Trait to convert raw objects into HTTP parseables.
 pub(crate) trait FromHttparse<T>: Sized {
     /// Convert raw object into parsed HTTP headers.
@@ -30,7 +37,7 @@ impl<'b: 'h, 'h> FromHttparse<&'b [httparse::Header<'h>]> for HeaderMap {
 }
 impl TryParse for HeaderMap {
     fn try_parse(buf: &[u8]) -> Result<Option<(usize, Self)>> {
-        let mut hbuffer = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut hbuffer = header_buffer(buf);
         Ok(match httparse::parse_headers(buf, &mut hbuffer)? {
             Status::Partial => None,
             Status::Complete((size, hdr)) => Some((size, HeaderMap::from_httparse(hdr)?)),
@@ -2,29 +2,110 @@
 
 use bytes::Buf;
 use log::*;
-use std::io::{Cursor, Read, Write};
+use std::{
+    io::{self, Cursor, Read, Write},
+    time::{Duration, Instant},
+};
 
+use super::headers::MAX_HEADERS;
 use crate::{
-    error::{Error, ProtocolError, Result},
+    error::{CapacityError, Error, ProtocolError, Result},
     util::NonBlockingResult,
     ReadBuffer,
 };
 
+/// Limits for reading the handshake request or response of the peer.
+///
+/// # Example
+/// ```
+/// # use std::time::Duration;
+/// # use layer8_tungstenite::handshake::machine::HandshakeConfig;
+/// let conf = HandshakeConfig::default()
+///     .timeout(Some(Duration::from_secs(5)))
+///     .min_bytes_per_second(Some(256));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HandshakeConfig {
+    /// The maximum size of the request or response head, i.e. the request or status line and
+    /// the headers. Bigger heads fail with [`CapacityError::HeaderTooLong`]. The default value
+    /// is 64 KiB.
+    pub max_header_bytes: usize,
+    /// The maximum number of header lines. More headers fail with
+    /// [`CapacityError::TooManyHeaders`]. The default value is [`MAX_HEADERS`].
+    pub max_headers: usize,
+    /// The time the whole handshake may take, failing with [`io::ErrorKind::TimedOut`] once
+    /// exceeded. It is checked before and after every read and write, so blocking streams need
+    /// a read timeout for it to apply while the peer sends nothing. The default value is `None`,
+    /// i.e. no deadline.
+    pub timeout: Option<Duration>,
+    /// The minimum average rate the peer must send its head at, in bytes per second, measured
+    /// from the first byte received. Peers dripping their head slower fail with
+    /// [`Error::AttackAttempt`]. It is checked once the head has been read for a second. The
+    /// default value is `None`, i.e. no minimum.
+    pub min_bytes_per_second: Option<usize>,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: 64 * 1024,
+            max_headers: MAX_HEADERS,
+            timeout: None,
+            min_bytes_per_second: None,
+        }
+    }
+}
+
+impl HandshakeConfig {
+    /// Set [`Self::max_header_bytes`].
+    pub fn max_header_bytes(mut self, max_header_bytes: usize) -> Self {
+        self.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    /// Set [`Self::max_headers`].
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// Set [`Self::timeout`].
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set [`Self::min_bytes_per_second`].
+    pub fn min_bytes_per_second(mut self, min_bytes_per_second: Option<usize>) -> Self {
+        self.min_bytes_per_second = min_bytes_per_second;
+        self
+    }
+}
+
+/// How long a peer may send its head before [`HandshakeConfig::min_bytes_per_second`] applies.
+const MIN_RATE_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
 /// A generic handshake state machine.
 #[derive(Debug)]
 pub struct HandshakeMachine<Stream> {
     stream: Stream,
     state: HandshakeState,
+    config: HandshakeConfig,
+    /// When the handshake started.
+    started: Instant,
 }
 
 impl<Stream> HandshakeMachine<Stream> {
     /// Start reading data from the peer.
     pub fn start_read(stream: Stream) -> Self {
-        Self { stream, state: HandshakeState::Reading(ReadBuffer::new(), AttackCheck::new()) }
+        let state = HandshakeState::Reading(ReadBuffer::new(), HeadScanner::new());
+        Self { stream, state, config: HandshakeConfig::default(), started: Instant::now() }
     }
     /// Start writing data to the peer.
     pub fn start_write<D: Into<Vec<u8>>>(stream: Stream, data: D) -> Self {
-        HandshakeMachine { stream, state: HandshakeState::Writing(Cursor::new(data.into())) }
+        let state = HandshakeState::Writing(Cursor::new(data.into()));
+        Self { stream, state, config: HandshakeConfig::default(), started: Instant::now() }
     }
     /// Returns a shared reference to the inner stream.
     pub fn get_ref(&self) -> &Stream {
@@ -34,41 +115,87 @@ impl<Stream> HandshakeMachine<Stream> {
     pub fn get_mut(&mut self) -> &mut Stream {
         &mut self.stream
     }
+    /// Change the limits for reading the head of the peer.
+    pub fn set_config(&mut self, config: HandshakeConfig) {
+        self.config = config;
+    }
+    /// Read the limits for reading the head of the peer.
+    pub fn get_config(&self) -> &HandshakeConfig {
+        &self.config
+    }
+    /// Continue the handshake of a previous stage, keeping its limits and start time.
+    pub(crate) fn continue_from(mut self, config: HandshakeConfig, started: Instant) -> Self {
+        self.config = config;
+        self.started = started;
+        self
+    }
+    pub(crate) fn started(&self) -> Instant {
+        self.started
+    }
+}
+
+impl<Stream> HandshakeMachine<Stream> {
+    /// Fail once the handshake took longer than [`HandshakeConfig::timeout`].
+    fn check_deadline(&self) -> Result<()> {
+        if self.config.timeout.is_some_and(|timeout| self.started.elapsed() > timeout) {
+            return Err(Error::Io(io::ErrorKind::TimedOut.into()));
+        }
+        Ok(())
+    }
 }
 
 impl<Stream: Read + Write> HandshakeMachine<Stream> {
     /// Perform a single handshake round.
-    pub fn single_round<Obj: TryParse>(mut self) -> Result<RoundResult<Obj, Stream>> {
+    pub fn single_round<Obj: TryParse>(self) -> Result<RoundResult<Obj, Stream>> {
         trace!("Doing handshake round.");
+        self.check_deadline()?;
+        let result = self.round();
+        if let Ok(RoundResult::Incomplete(machine) | RoundResult::WouldBlock(machine)) = &result {
+            // The read or write may have blocked past the deadline.
+            machine.check_deadline()?;
+        }
+        result
+    }
+
+    fn round<Obj: TryParse>(mut self) -> Result<RoundResult<Obj, Stream>> {
         match self.state {
-            HandshakeState::Reading(mut buf, mut attack_check) => {
+            HandshakeState::Reading(mut buf, mut scanner) => {
                 let read = buf.read_from(&mut self.stream).no_block()?;
-                match read {
-                    Some(0) => Err(Error::Protocol(ProtocolError::HandshakeIncomplete)),
-                    Some(count) => {
-                        attack_check.check_incoming_packet_size(count)?;
-                        // TODO: this is slow for big headers with too many small packets.
-                        // The parser has to be reworked in order to work on streams instead
-                        // of buffers.
-                        Ok(if let Some((size, obj)) = Obj::try_parse(Buf::chunk(&buf))? {
-                            buf.advance(size);
-                            RoundResult::StageFinished(StageResult::DoneReading {
-                                result: obj,
-                                stream: self.stream,
-                                tail: buf.into_vec(),
-                            })
-                        } else {
-                            RoundResult::Incomplete(HandshakeMachine {
-                                state: HandshakeState::Reading(buf, attack_check),
-                                ..self
-                            })
-                        })
+                if let Some(0) = read {
+                    return Err(Error::Protocol(ProtocolError::HandshakeIncomplete));
+                }
+                if read.is_some() && scanner.first_byte.is_none() {
+                    scanner.first_byte = Some(Instant::now());
+                }
+                let head_len = scanner.scan(Buf::chunk(&buf), &self.config)?;
+                if let Some(head_len) = head_len {
+                    let head = &Buf::chunk(&buf)[..head_len];
+                    let Some((size, obj)) = Obj::try_parse(head)? else {
+                        return Err(Error::Protocol(ProtocolError::HandshakeIncomplete));
+                    };
+                    buf.advance(size);
+                    return Ok(RoundResult::StageFinished(StageResult::DoneReading {
+                        result: obj,
+                        stream: self.stream,
+                        tail: buf.into_vec(),
+                    }));
+                }
+                if let (Some(min_rate), Some(first_byte)) =
+                    (self.config.min_bytes_per_second, scanner.first_byte)
+                {
+                    let elapsed = first_byte.elapsed();
+                    if elapsed >= MIN_RATE_GRACE_PERIOD
+                        && (buf.remaining() as f64) < min_rate as f64 * elapsed.as_secs_f64()
+                    {
+                        return Err(Error::AttackAttempt);
                     }
-                    None => Ok(RoundResult::WouldBlock(HandshakeMachine {
-                        state: HandshakeState::Reading(buf, attack_check),
-                        ..self
-                    })),
                 }
+                let machine =
+                    HandshakeMachine { state: HandshakeState::Reading(buf, scanner), ..self };
+                Ok(match read {
+                    Some(_) => RoundResult::Incomplete(machine),
+                    None => RoundResult::WouldBlock(machine),
+                })
             }
             HandshakeState::Writing(mut buf) => {
                 assert!(buf.has_remaining());
@@ -135,56 +262,171 @@ pub trait TryParse: Sized {
 #[derive(Debug)]
 enum HandshakeState {
     /// Reading data from the peer.
-    Reading(ReadBuffer, AttackCheck),
+    Reading(ReadBuffer, HeadScanner),
     /// Sending data to the peer.
     Writing(Cursor<Vec<u8>>),
     /// Flushing data to ensure that all intermediately buffered contents reach their destination.
     Flushing,
 }
 
-/// Attack mitigation. Contains counters needed to prevent DoS attacks
-/// and reject valid but useless headers.
+/// Looks for the end of the head in the data read so far, scanning every byte once and
+/// counting lines on the way. The head is parsed only once it is complete.
 #[derive(Debug)]
-pub(crate) struct AttackCheck {
-    /// Number of HTTP header successful reads (TCP packets).
-    number_of_packets: usize,
-    /// Total number of bytes in HTTP header.
-    number_of_bytes: usize,
+struct HeadScanner {
+    /// Bytes before this offset do not end the head.
+    scanned: usize,
+    /// Number of lines before `scanned`.
+    lines: usize,
+    /// When the first byte of the head was received.
+    first_byte: Option<Instant>,
 }
 
-impl AttackCheck {
-    /// Initialize attack checking for incoming buffer.
+impl HeadScanner {
     fn new() -> Self {
-        Self { number_of_packets: 0, number_of_bytes: 0 }
+        Self { scanned: 0, lines: 0, first_byte: None }
     }
 
-    /// Check the size of an incoming packet. To be called immediately after `read()`
-    /// passing its returned bytes count as `size`.
-    fn check_incoming_packet_size(&mut self, size: usize) -> Result<()> {
-        self.number_of_packets += 1;
-        self.number_of_bytes += size;
+    /// Scan newly read data, returning the length of the head once it is complete.
+    fn scan(&mut self, data: &[u8], config: &HandshakeConfig) -> Result<Option<usize>> {
+        while let Some(pos) = data[self.scanned..].iter().position(|&byte| byte == b'\n') {
+            let end = self.scanned + pos;
+            // An empty line ends the head, httparse accepts it with and without `\r`.
+            let head_len = match &data[end + 1..] {
+                [] | [b'\r'] => break,
+                [b'\n', ..] => Some(end + 2),
+                [b'\r', b'\n', ..] => Some(end + 3),
+                _ => None,
+            };
+            self.scanned = end + 1;
+            self.lines += 1;
+            // The request or status line is followed by the headers.
+            if self.lines > config.max_headers + 1 {
+                return Err(Error::Capacity(CapacityError::TooManyHeaders));
+            }
+            if let Some(head_len) = head_len {
+                check_head_size(head_len, config)?;
+                return Ok(Some(head_len));
+            }
+        }
+        check_head_size(data.len(), config)?;
+        Ok(None)
+    }
+}
+
+fn check_head_size(size: usize, config: &HandshakeConfig) -> Result<()> {
+    if size > config.max_header_bytes {
+        let max_size = config.max_header_bytes;
+        return Err(Error::Capacity(CapacityError::HeaderTooLong { size, max_size }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        time::{Duration, Instant},
+    };
+
+    use super::{HandshakeConfig, HandshakeMachine, HandshakeState, RoundResult, StageResult};
+    use crate::{
+        error::{CapacityError, Error},
+        handshake::server::Request,
+        testing::{duplex, DuplexStream},
+    };
 
-        // TODO: these values are hardcoded. Instead of making them configurable,
-        // rework the way HTTP header is parsed to remove this check at all.
-        const MAX_BYTES: usize = 65536;
-        const MAX_PACKETS: usize = 512;
-        const MIN_PACKET_SIZE: usize = 128;
-        const MIN_PACKET_CHECK_THRESHOLD: usize = 64;
+    const REQUEST: &[u8] =
+        b"GET /socket HTTP/1.1\r\nHost: foo.com\r\nUpgrade: websocket\r\n\r\ntail";
 
-        if self.number_of_bytes > MAX_BYTES {
-            return Err(Error::AttackAttempt);
+    /// Read the request, writing the next `chunk` bytes of it whenever the machine would block.
+    fn read_request(
+        chunk: usize,
+        config: HandshakeConfig,
+        prepare: impl FnOnce(&mut HandshakeMachine<DuplexStream>),
+    ) -> crate::Result<(Request, Vec<u8>)> {
+        let (mut peer, stream) = duplex();
+        let mut chunks = REQUEST.chunks(chunk);
+        let mut machine = HandshakeMachine::start_read(stream);
+        machine.set_config(config);
+        prepare(&mut machine);
+        loop {
+            machine = match machine.single_round()? {
+                RoundResult::Incomplete(m) => m,
+                RoundResult::WouldBlock(m) => {
+                    peer.write_all(chunks.next().expect("request read completely")).unwrap();
+                    m
+                }
+                RoundResult::StageFinished(StageResult::DoneReading { result, tail, .. }) => {
+                    return Ok((result, tail))
+                }
+                RoundResult::StageFinished(StageResult::DoneWriting(_)) => unreachable!(),
+            }
         }
+    }
 
-        if self.number_of_packets > MAX_PACKETS {
-            return Err(Error::AttackAttempt);
+    /// Move the start of the handshake and of reading the head into the past.
+    fn started_ago(ago: Duration) -> impl FnOnce(&mut HandshakeMachine<DuplexStream>) {
+        move |machine| {
+            machine.started = Instant::now() - ago;
+            if let HandshakeState::Reading(_, scanner) = &mut machine.state {
+                scanner.first_byte = Some(machine.started);
+            }
         }
+    }
 
-        if self.number_of_packets > MIN_PACKET_CHECK_THRESHOLD
-            && self.number_of_packets * MIN_PACKET_SIZE > self.number_of_bytes
-        {
-            return Err(Error::AttackAttempt);
+    #[test]
+    fn parses_dripped_head_once_complete() {
+        for chunk in [1, 2, 3, 7, REQUEST.len()] {
+            let (request, tail) = read_request(chunk, HandshakeConfig::default(), |_| {}).unwrap();
+            assert_eq!(request.uri().path(), "/socket");
+            assert_eq!(request.headers()["Upgrade"], "websocket");
+            assert!(b"tail".starts_with(&tail), "{tail:?}");
         }
+    }
 
-        Ok(())
+    #[test]
+    fn limits_headers() {
+        let config = HandshakeConfig::default().max_headers(1);
+        let err = read_request(1, config, |_| {}).unwrap_err();
+        assert!(matches!(err, Error::Capacity(CapacityError::TooManyHeaders)), "{err:?}");
+
+        let config = HandshakeConfig::default().max_header_bytes(32);
+        let err = read_request(1, config, |_| {}).unwrap_err();
+        assert!(
+            matches!(err, Error::Capacity(CapacityError::HeaderTooLong { max_size: 32, .. })),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn deadline_and_rate() {
+        let config = HandshakeConfig::default().timeout(Some(Duration::from_secs(5)));
+        let err = read_request(1, config, started_ago(Duration::from_secs(6))).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::TimedOut), "{err:?}");
+
+        // No limit applies by default.
+        let (request, _) =
+            read_request(1, HandshakeConfig::default(), started_ago(Duration::from_secs(3600)))
+                .unwrap();
+        assert_eq!(request.uri().path(), "/socket");
+
+        let config = HandshakeConfig::default()
+            .timeout(Some(Duration::from_secs(30)))
+            .min_bytes_per_second(Some(128));
+        let err = read_request(1, config, started_ago(Duration::from_secs(31))).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::TimedOut), "{err:?}");
+        let result = read_request(1, config, started_ago(Duration::from_secs(2)));
+        assert!(matches!(result, Err(Error::AttackAttempt)));
+
+        // A peer taking its time before sending is covered by the deadline only.
+        let waiting = started_ago(Duration::from_secs(2));
+        let (request, _) = read_request(REQUEST.len(), config, |machine| {
+            waiting(machine);
+            if let HandshakeState::Reading(_, scanner) = &mut machine.state {
+                scanner.first_byte = None;
+            }
+        })
+        .unwrap();
+        assert_eq!(request.uri().path(), "/socket");
     }
 }
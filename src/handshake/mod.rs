@@ -1,8 +1,11 @@
 //! WebSocket handshake control.
 
+pub mod auth;
 pub mod client;
+pub mod h2;
 pub mod headers;
 pub mod machine;
+pub mod origin;
 pub mod server;
 
 use std::{
@@ -13,7 +16,7 @@ use std::{
 
 use sha1::{Digest, Sha1};
 
-use self::machine::{HandshakeMachine, RoundResult, StageResult, TryParse};
+use self::machine::{HandshakeConfig, HandshakeMachine, RoundResult, StageResult, TryParse};
 use crate::error::Error;
 
 /// A WebSocket handshake.
@@ -34,17 +37,29 @@ impl<Role: HandshakeRole> MidHandshake<Role> {
         &mut self.machine
     }
 
+    /// Set the limits for reading the request or response of the peer.
+    pub fn set_handshake_config(&mut self, config: HandshakeConfig) {
+        self.machine.set_config(config);
+    }
+
+    /// Set the limits for reading the request or response of the peer.
+    pub fn with_handshake_config(mut self, config: HandshakeConfig) -> Self {
+        self.set_handshake_config(config);
+        self
+    }
+
     /// Restarts the handshake process.
     pub fn handshake(mut self) -> Result<Role::FinalResult, HandshakeError<Role>> {
         let mut mach = self.machine;
         loop {
+            let (config, started) = (*mach.get_config(), mach.started());
             mach = match mach.single_round()? {
                 RoundResult::WouldBlock(m) => {
                     return Err(HandshakeError::Interrupted(MidHandshake { machine: m, ..self }))
                 }
                 RoundResult::Incomplete(m) => m,
                 RoundResult::StageFinished(s) => match self.role.stage_finished(s)? {
-                    ProcessingResult::Continue(m) => m,
+                    ProcessingResult::Continue(m) => m.continue_from(config, started),
                     ProcessingResult::Done(result) => return Ok(result),
                 },
             }
@@ -123,6 +138,12 @@ pub fn derive_accept_key(request_key: &[u8]) -> String {
     data_encoding::BASE64.encode(&sha1.finalize())
 }
 
+/// The subprotocol selected by the server in a handshake response.
+fn selected_subprotocol<T>(response: &http::Response<T>) -> Option<String> {
+    let protocol = response.headers().get("Sec-WebSocket-Protocol")?;
+    protocol.to_str().ok().map(str::to_owned)
+}
+
 #[cfg(test)]
 mod tests {
     use super::derive_accept_key;
@@ -0,0 +1,287 @@
+//! Checking the `Origin` of server handshake requests.
+//!
+//! Browsers send the origin of the page opening a WebSocket, but unlike `fetch` they do not
+//! enforce the same-origin policy: any page can connect to any server and the browser attaches
+//! the cookies of the server. An [`OriginPolicy`] rejects handshakes from origins which are not
+//! allowed, protecting servers relying on cookies from cross-site WebSocket hijacking.
+
+use std::{fmt, result::Result as StdResult, sync::Arc};
+
+use http::{StatusCode, Uri};
+use log::*;
+
+use super::server::{Callback, ErrorResponse, Request, Response};
+
+type Predicate = dyn Fn(&str) -> bool + Send + Sync;
+
+/// Allows handshakes only from listed origins.
+///
+/// An origin is allowed if it matches an exact origin, a wildcard subdomain pattern or a
+/// predicate. Other handshakes are rejected with `403 Forbidden` before the [`Callback`] of the
+/// server runs. Requests without an `Origin` header do not come from a browser and are allowed
+/// by default, see [`Self::allow_missing`].
+///
+/// The policy is a [`Callback`] itself and is chained with another callback by
+/// [`Self::with_callback`].
+///
+/// # Example
+///
+/// ```rust no_run
+/// # use std::net::TcpListener;
+/// use layer8_tungstenite::{accept_hdr_with_config, handshake::origin::OriginPolicy};
+///
+/// let policy = OriginPolicy::new()
+///     .allow("https://example.com")
+///     .allow("https://*.example.com")
+///     .allow_fn(|origin| origin.starts_with("http://localhost:"));
+///
+/// let listener = TcpListener::bind("127.0.0.1:3012").unwrap();
+/// for stream in listener.incoming() {
+///     let websocket = accept_hdr_with_config(stream.unwrap(), policy.clone(), None);
+///     // ...
+/// }
+/// ```
+#[derive(Clone)]
+pub struct OriginPolicy {
+    origins: Vec<AllowedOrigin>,
+    predicates: Vec<Arc<Predicate>>,
+    allow_missing: bool,
+}
+
+impl Default for OriginPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginPolicy {
+    /// A policy allowing no origin. Requests without an `Origin` header are allowed.
+    pub fn new() -> Self {
+        Self { origins: Vec::new(), predicates: Vec::new(), allow_missing: true }
+    }
+
+    /// Allow an origin of the form `scheme://host[:port]`. A host of the form `*.domain`
+    /// allows every subdomain of `domain`, but not `domain` itself. Schemes and hosts are
+    /// compared case-insensitively and default ports match origins without a port.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `origin` is not of the form `scheme://host[:port]`.
+    pub fn allow(mut self, origin: &str) -> Self {
+        let (pattern, subdomains) = match origin.split_once("://*.") {
+            Some((scheme, domain)) => (format!("{scheme}://{domain}"), true),
+            None => (origin.to_owned(), false),
+        };
+        let parsed = Origin::parse(&pattern)
+            .unwrap_or_else(|| panic!("invalid origin {origin:?}, expected scheme://host[:port]"));
+        self.origins.push(AllowedOrigin { origin: parsed, subdomains });
+        self
+    }
+
+    /// Allow every origin for which the predicate returns `true`. The predicate receives the
+    /// value of the `Origin` header as sent, which may be `null` for privacy-sensitive contexts.
+    pub fn allow_fn<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.push(Arc::new(predicate));
+        self
+    }
+
+    /// Set whether requests without an `Origin` header are allowed. Browsers always send it,
+    /// so only other clients are affected. The default value is `true`.
+    pub fn allow_missing(mut self, allow_missing: bool) -> Self {
+        self.allow_missing = allow_missing;
+        self
+    }
+
+    /// Whether the value of an `Origin` header, or its absence, is allowed.
+    pub fn is_allowed(&self, origin: Option<&str>) -> bool {
+        let Some(origin) = origin else {
+            return self.allow_missing;
+        };
+        if self.predicates.iter().any(|predicate| predicate(origin)) {
+            return true;
+        }
+        match Origin::parse(origin) {
+            Some(origin) => self.origins.iter().any(|allowed| allowed.matches(&origin)),
+            None => false,
+        }
+    }
+
+    /// Check the `Origin` header of the request, returning a `403 Forbidden` response if it is
+    /// not allowed. Requests with more than one `Origin` header are rejected.
+    pub fn check(&self, request: &Request) -> StdResult<(), ErrorResponse> {
+        let mut values = request.headers().get_all(http::header::ORIGIN).iter();
+        let allowed = match (values.next(), values.next()) {
+            (None, _) => self.is_allowed(None),
+            (Some(origin), None) => origin.to_str().is_ok_and(|o| self.is_allowed(Some(o))),
+            (Some(_), Some(_)) => false,
+        };
+        if allowed {
+            return Ok(());
+        }
+
+        debug!("Rejecting handshake from origin {:?}", request.headers().get(http::header::ORIGIN));
+        let mut response = ErrorResponse::new(Some("Origin not allowed".into()));
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        Err(response)
+    }
+
+    /// Chain the policy with a callback, which only runs for allowed origins.
+    pub fn with_callback<C: Callback>(self, callback: C) -> OriginCallback<C> {
+        OriginCallback { policy: self, callback }
+    }
+}
+
+impl fmt::Debug for OriginPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OriginPolicy")
+            .field("origins", &self.origins)
+            .field("predicates", &self.predicates.len())
+            .field("allow_missing", &self.allow_missing)
+            .finish()
+    }
+}
+
+impl Callback for OriginPolicy {
+    fn on_request(
+        self,
+        request: &Request,
+        response: Response,
+    ) -> StdResult<Response, ErrorResponse> {
+        self.check(request)?;
+        Ok(response)
+    }
+}
+
+/// A callback running only for origins allowed by an [`OriginPolicy`], see
+/// [`OriginPolicy::with_callback`].
+#[derive(Debug, Clone)]
+pub struct OriginCallback<C> {
+    policy: OriginPolicy,
+    callback: C,
+}
+
+impl<C: Callback> Callback for OriginCallback<C> {
+    fn on_request(
+        self,
+        request: &Request,
+        response: Response,
+    ) -> StdResult<Response, ErrorResponse> {
+        self.policy.check(request)?;
+        self.callback.on_request(request, response)
+    }
+}
+
+/// A listed origin, or a domain whose subdomains are allowed.
+#[derive(Debug, Clone)]
+struct AllowedOrigin {
+    origin: Origin,
+    subdomains: bool,
+}
+
+impl AllowedOrigin {
+    fn matches(&self, origin: &Origin) -> bool {
+        if origin.scheme != self.origin.scheme || origin.port != self.origin.port {
+            return false;
+        }
+        if !self.subdomains {
+            return origin.host == self.origin.host;
+        }
+        origin
+            .host
+            .strip_suffix(&self.origin.host)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+    }
+}
+
+/// A normalized `scheme://host:port` origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Origin {
+    scheme: String,
+    host: String,
+    port: Option<u16>,
+}
+
+impl Origin {
+    fn parse(origin: &str) -> Option<Self> {
+        let uri: Uri = origin.parse().ok()?;
+        let scheme = uri.scheme_str()?.to_ascii_lowercase();
+        let authority = uri.authority()?;
+        // Origins carry no user info, path or query.
+        if authority.as_str().contains('@') || !matches!(uri.path(), "" | "/") {
+            return None;
+        }
+        if uri.query().is_some() || authority.host().is_empty() {
+            return None;
+        }
+        let default_port = match scheme.as_str() {
+            "http" | "ws" => Some(80),
+            "https" | "wss" => Some(443),
+            _ => None,
+        };
+        let port = authority.port_u16().filter(|port| Some(*port) != default_port);
+        Some(Self { scheme, host: authority.host().to_ascii_lowercase(), port })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OriginPolicy;
+    use crate::handshake::server::Request;
+
+    fn request(origins: &[&str]) -> Request {
+        let mut builder = Request::builder().uri("/");
+        for origin in origins {
+            builder = builder.header("Origin", *origin);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn exact_origins() {
+        let policy = OriginPolicy::new().allow("https://example.com").allow("http://[::1]:8080");
+        assert!(policy.is_allowed(Some("https://example.com")));
+        assert!(policy.is_allowed(Some("HTTPS://Example.COM:443")));
+        assert!(policy.is_allowed(Some("http://[::1]:8080")));
+        assert!(!policy.is_allowed(Some("http://example.com")));
+        assert!(!policy.is_allowed(Some("https://example.com:8443")));
+        assert!(!policy.is_allowed(Some("https://example.com.evil.com")));
+        assert!(!policy.is_allowed(Some("https://sub.example.com")));
+        assert!(!policy.is_allowed(Some("null")));
+    }
+
+    #[test]
+    fn wildcard_subdomains() {
+        let policy = OriginPolicy::new().allow("https://*.example.com");
+        assert!(policy.is_allowed(Some("https://app.example.com")));
+        assert!(policy.is_allowed(Some("https://a.b.example.com")));
+        assert!(!policy.is_allowed(Some("https://example.com")));
+        assert!(!policy.is_allowed(Some("https://evilexample.com")));
+        assert!(!policy.is_allowed(Some("http://app.example.com")));
+    }
+
+    #[test]
+    fn predicates_and_missing_origins() {
+        let policy = OriginPolicy::new().allow_fn(|origin| origin == "null");
+        assert!(policy.is_allowed(Some("null")));
+        assert!(policy.is_allowed(None));
+        assert!(!policy.allow_missing(false).is_allowed(None));
+    }
+
+    #[test]
+    fn check_rejects_with_forbidden() {
+        let policy = OriginPolicy::new().allow("https://example.com");
+        assert!(policy.check(&request(&["https://example.com"])).is_ok());
+        let response = policy.check(&request(&["https://evil.com"])).unwrap_err();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+        assert!(policy.check(&request(&["https://example.com", "https://example.com"])).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid origin")]
+    fn invalid_pattern() {
+        let _ = OriginPolicy::new().allow("example.com/path");
+    }
+}
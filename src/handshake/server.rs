@@ -1,6 +1,8 @@
 //! Server handshake machine.
 
 use std::{
+    any::Any,
+    fmt,
     io::{self, Read, Write},
     marker::PhantomData,
     result::Result as StdResult,
@@ -10,13 +12,15 @@ use http::{
     response::Builder, HeaderMap, Request as HttpRequest, Response as HttpResponse, StatusCode,
 };
 use httparse::Status;
+use layer8_primitives::crypto::Jwk;
 use log::*;
 
 use super::{
+    auth::Authenticator,
     derive_accept_key,
-    headers::{FromHttparse, MAX_HEADERS},
+    headers::{header_buffer, FromHttparse},
     machine::{HandshakeMachine, StageResult, TryParse},
-    HandshakeRole, MidHandshake, ProcessingResult,
+    selected_subprotocol, HandshakeRole, MidHandshake, ProcessingResult,
 };
 use crate::{
     error::{Error, ProtocolError, Result},
@@ -111,9 +115,85 @@ pub fn write_response<T>(mut w: impl io::Write, response: &HttpResponse<T>) -> R
     Ok(())
 }
 
+/// Encode an error response with its body.
+pub(crate) fn encode_error_response(response: &ErrorResponse) -> Result<Vec<u8>> {
+    let mut output = vec![];
+    write_response(&mut output, response)?;
+    if let Some(body) = response.body() {
+        output.extend_from_slice(body.as_bytes());
+    }
+    Ok(output)
+}
+
+/// The error returned to the server after rejecting a handshake with `response`.
+fn error_response_into_error(response: ErrorResponse) -> Error {
+    let (parts, body) = response.into_parts();
+    let body = body.map(|b| b.as_bytes().to_vec());
+    Error::Http(http::Response::from_parts(parts, body))
+}
+
+/// Accept the WebSocket upgrade of a request which was already read from `stream` and parsed,
+/// `leftover` being the bytes read from the stream after the request.
+///
+/// Unlike [`ServerHandshake`] the response is written with blocking writes, a [`Jwk`] in the
+/// request extensions becomes the layer8 shared secret. Invalid upgrade requests are answered
+/// with `400 Bad Request`.
+pub(crate) fn accept_request<S: Read + Write, B, C: Callback>(
+    mut stream: S,
+    request: &HttpRequest<B>,
+    leftover: Vec<u8>,
+    callback: C,
+    config: Option<WebSocketConfig>,
+) -> Result<WebSocket<S>> {
+    let mut head = Request::new(());
+    *head.method_mut() = request.method().clone();
+    *head.uri_mut() = request.uri().clone();
+    *head.version_mut() = request.version();
+    *head.headers_mut() = request.headers().clone();
+    *head.extensions_mut() = request.extensions().clone();
+
+    let response = match create_response(&head) {
+        Ok(response) => response,
+        Err(err) => {
+            let mut response = ErrorResponse::new(Some(err.to_string()));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            stream.write_all(&encode_error_response(&response)?)?;
+            stream.flush()?;
+            debug!("Server handshake failed: {err}");
+            return Err(err);
+        }
+    };
+    match callback.on_request(&head, response) {
+        Ok(response) => {
+            let mut output = vec![];
+            write_response(&mut output, &response)?;
+            stream.write_all(&output)?;
+            stream.flush()?;
+            debug!("Server handshake done.");
+
+            let mut websocket =
+                WebSocket::from_partially_read(stream, leftover, Role::Server, config);
+            websocket.set_subprotocol(selected_subprotocol(&response));
+            if let Some(secret) = head.extensions().get::<Jwk>() {
+                websocket.set_shared_secret(secret.clone());
+            }
+            Ok(websocket)
+        }
+        Err(response) => {
+            if response.status().is_success() {
+                return Err(Error::Protocol(ProtocolError::CustomResponseSuccessful));
+            }
+            stream.write_all(&encode_error_response(&response)?)?;
+            stream.flush()?;
+            debug!("Server handshake failed.");
+            Err(error_response_into_error(response))
+        }
+    }
+}
+
 impl TryParse for Request {
     fn try_parse(buf: &[u8]) -> Result<Option<(usize, Self)>> {
-        let mut hbuffer = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut hbuffer = header_buffer(buf);
         let mut req = httparse::Request::new(&mut hbuffer);
         Ok(match req.parse(buf)? {
             Status::Partial => None,
@@ -190,10 +270,16 @@ impl Callback for NoCallback {
     }
 }
 
+type Identity = Box<dyn Any + Send + Sync>;
+type Authenticate = Box<dyn FnOnce(&Request) -> StdResult<Identity, ErrorResponse> + Send + Sync>;
+
 /// Server handshake role.
 #[allow(missing_copy_implementations)]
-#[derive(Debug)]
 pub struct ServerHandshake<S, C> {
+    /// Authenticates the client before the callback is called.
+    authenticate: Option<Authenticate>,
+    /// The identity of the authenticated client, attached to the WebSocket.
+    identity: Option<Identity>,
     /// Callback which is called whenever the server read the request from the client and is ready
     /// to reply to it. The callback returns an optional headers which will be added to the reply
     /// which the server sends to the user.
@@ -202,6 +288,8 @@ pub struct ServerHandshake<S, C> {
     config: Option<WebSocketConfig>,
     /// Error code/flag. If set, an error will be returned after sending response to the client.
     error_response: Option<ErrorResponse>,
+    /// The subprotocol selected in the response, attached to the WebSocket.
+    subprotocol: Option<String>,
     /// Internal stream type.
     _marker: PhantomData<S>,
 }
@@ -216,13 +304,54 @@ impl<S: Read + Write, C: Callback> ServerHandshake<S, C> {
         MidHandshake {
             machine: HandshakeMachine::start_read(stream),
             role: ServerHandshake {
+                authenticate: None,
+                identity: None,
                 callback: Some(callback),
                 config,
                 error_response: None,
+                subprotocol: None,
                 _marker: PhantomData,
             },
         }
     }
+
+    /// Start server handshake authenticating the client. Unauthenticated clients are rejected
+    /// with `401 Unauthorized` before `callback` is called, the identity of authenticated ones
+    /// is attached to the WebSocket, see [`WebSocket::identity`].
+    pub fn start_authenticated<A>(
+        stream: S,
+        authenticator: A,
+        callback: C,
+        config: Option<WebSocketConfig>,
+    ) -> MidHandshake<Self>
+    where
+        A: Authenticator + Send + Sync + 'static,
+    {
+        let mut handshake = Self::start(stream, callback, config);
+        handshake.role.authenticate =
+            Some(Box::new(move |request: &Request| match authenticator.authenticate(request) {
+                Ok(identity) => {
+                    let identity: Identity = Box::new(identity);
+                    Ok(identity)
+                }
+                Err(err) => {
+                    debug!("Server handshake authentication failed: {err}");
+                    Err(err.into_response(authenticator.challenge()))
+                }
+            }));
+        handshake
+    }
+}
+
+impl<S, C: fmt::Debug> fmt::Debug for ServerHandshake<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerHandshake")
+            .field("authenticate", &self.authenticate.is_some())
+            .field("callback", &self.callback)
+            .field("config", &self.config)
+            .field("error_response", &self.error_response)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
@@ -241,16 +370,24 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
                 }
 
                 let response = create_response(&result)?;
-                let callback_result = if let Some(callback) = self.callback.take() {
-                    callback.on_request(&result, response)
-                } else {
-                    Ok(response)
+                let authenticated = self.authenticate.take().map(|auth| auth(&result)).transpose();
+                let callback_result = match authenticated {
+                    Err(response) => Err(response),
+                    Ok(identity) => {
+                        self.identity = identity;
+                        if let Some(callback) = self.callback.take() {
+                            callback.on_request(&result, response)
+                        } else {
+                            Ok(response)
+                        }
+                    }
                 };
 
                 match callback_result {
                     Ok(response) => {
                         let mut output = vec![];
                         write_response(&mut output, &response)?;
+                        self.subprotocol = selected_subprotocol(&response);
                         ProcessingResult::Continue(HandshakeMachine::start_write(stream, output))
                     }
 
@@ -259,16 +396,8 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
                             return Err(Error::Protocol(ProtocolError::CustomResponseSuccessful));
                         }
 
+                        let output = encode_error_response(&resp)?;
                         self.error_response = Some(resp);
-                        let resp = self.error_response.as_ref().unwrap();
-
-                        let mut output = vec![];
-                        write_response(&mut output, resp)?;
-
-                        if let Some(body) = resp.body() {
-                            output.extend_from_slice(body.as_bytes());
-                        }
-
                         ProcessingResult::Continue(HandshakeMachine::start_write(stream, output))
                     }
                 }
@@ -277,13 +406,15 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
             StageResult::DoneWriting(stream) => {
                 if let Some(err) = self.error_response.take() {
                     debug!("Server handshake failed.");
-
-                    let (parts, body) = err.into_parts();
-                    let body = body.map(|b| b.as_bytes().to_vec());
-                    return Err(Error::Http(http::Response::from_parts(parts, body)));
+                    return Err(error_response_into_error(err));
                 } else {
                     debug!("Server handshake done.");
-                    let websocket = WebSocket::from_raw_socket(stream, Role::Server, self.config);
+                    let mut websocket =
+                        WebSocket::from_raw_socket(stream, Role::Server, self.config.clone());
+                    if let Some(identity) = self.identity.take() {
+                        websocket.set_boxed_identity(identity);
+                    }
+                    websocket.set_subprotocol(self.subprotocol.take());
                     ProcessingResult::Done(websocket)
                 }
             }
@@ -293,7 +424,34 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
 
 #[cfg(test)]
 mod tests {
-    use super::{super::machine::TryParse, create_response, Request};
+    use super::{
+        super::{machine::TryParse, HandshakeError},
+        accept_request, create_response, NoCallback, Request, Response, ServerHandshake,
+    };
+    use crate::{
+        error::{Error, ProtocolError},
+        testing::{duplex, DuplexStream},
+        Message,
+    };
+
+    fn parsed_request() -> http::Request<String> {
+        http::Request::builder()
+            .uri("/chat")
+            .header("Host", "foo.com")
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header("Sec-WebSocket-Protocol", "chat")
+            .body("ignored".to_owned())
+            .unwrap()
+    }
+
+    #[test]
+    fn handshake_error_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<HandshakeError<ServerHandshake<DuplexStream, NoCallback>>>();
+    }
 
     #[test]
     fn request_parsing() {
@@ -321,4 +479,50 @@ mod tests {
             b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".as_ref()
         );
     }
+
+    #[test]
+    fn accept_parsed_request() {
+        let (mut client, server) = duplex();
+        // The masked "Hello" frame of RFC 6455, read by the framework with the request.
+        let leftover = vec![0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let callback = |request: &Request, mut response: Response| {
+            let protocol = request.headers()["Sec-WebSocket-Protocol"].clone();
+            response.headers_mut().insert("Sec-WebSocket-Protocol", protocol);
+            Ok(response)
+        };
+
+        let mut socket =
+            accept_request(server, &parsed_request(), leftover, callback, None).unwrap();
+        let response = String::from_utf8(client.take_unread()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{response}");
+        assert!(response.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(response.contains("sec-websocket-protocol: chat\r\n"));
+        assert_eq!(socket.subprotocol(), Some("chat"));
+        assert_eq!(socket.read().unwrap(), Message::text("Hello"));
+    }
+
+    #[test]
+    fn reject_parsed_request() {
+        let (mut client, server) = duplex();
+        let callback = |_: &Request, _: Response| {
+            Err(Response::builder().status(403).body(Some("Forbidden".into())).unwrap())
+        };
+        match accept_request(server, &parsed_request(), vec![], callback, None) {
+            Err(Error::Http(response)) => assert_eq!(response.status(), 403),
+            other => panic!("unexpected result {other:?}"),
+        }
+        let response = String::from_utf8(client.take_unread()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(response.ends_with("\r\n\r\nForbidden"));
+
+        let (mut client, server) = duplex();
+        let mut request = parsed_request();
+        request.headers_mut().remove("Upgrade");
+        assert!(matches!(
+            accept_request(server, &request, vec![], NoCallback, None),
+            Err(Error::Protocol(ProtocolError::MissingUpgradeWebSocketHeader))
+        ));
+        let response = String::from_utf8(client.take_unread()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+    }
 }
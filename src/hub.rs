@@ -0,0 +1,345 @@
+//! Fan-out of messages to many WebSocket connections.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    io::{ErrorKind, Read, Write},
+};
+
+use crate::{
+    error::Error,
+    protocol::{Message, PreparedMessage, WebSocket},
+};
+
+/// What to do with a connection whose write buffer is full when a message is sent to it, see
+/// [`WebSocketConfig::max_write_buffer_size`](crate::protocol::WebSocketConfig::max_write_buffer_size).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Skip the message for this connection and keep it.
+    #[default]
+    Drop,
+    /// Remove the connection from the hub.
+    Disconnect,
+}
+
+/// A set of WebSocket connections keyed by an ID, which can join and leave topics.
+///
+/// Messages are broadcast to every connection or published to the subscribers of a topic.
+/// Text and binary messages are formatted once as a [`PreparedMessage`] and the same frame is
+/// written to every server-role connection. Connections of the client role mask every frame and
+/// connections with a layer8 shared secret encrypt it, so the message is written to them one by
+/// one.
+///
+/// Connections failing to write are removed and reported, the [`Backpressure`] policy decides
+/// about connections which are too slow to keep up.
+pub struct Hub<Id, Stream> {
+    peers: HashMap<Id, Peer<Stream>>,
+    topics: HashMap<String, HashSet<Id>>,
+    backpressure: Backpressure,
+}
+
+struct Peer<Stream> {
+    socket: WebSocket<Stream>,
+    topics: HashSet<String>,
+}
+
+/// The outcome of sending a message with a [`Hub`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct BroadcastReport<Id> {
+    /// The number of connections the message was written to. The message may still be buffered
+    /// for connections whose stream would block.
+    pub sent: usize,
+    /// Connections skipped because their write buffer is full, see [`Backpressure::Drop`].
+    pub dropped: Vec<Id>,
+    /// Connections removed from the hub with the error they failed with.
+    pub disconnected: Vec<(Id, Error)>,
+}
+
+impl<Id, Stream> Hub<Id, Stream>
+where
+    Id: Eq + Hash + Clone,
+    Stream: Read + Write,
+{
+    /// Create an empty hub dropping messages for connections with a full write buffer.
+    pub fn new() -> Self {
+        Self { peers: HashMap::new(), topics: HashMap::new(), backpressure: Backpressure::Drop }
+    }
+
+    /// Set what to do with connections whose write buffer is full.
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Add a connection, returning the connection previously added with the same ID.
+    ///
+    /// The replaced connection leaves all its topics.
+    pub fn insert(&mut self, id: Id, socket: WebSocket<Stream>) -> Option<WebSocket<Stream>> {
+        let replaced = self.remove(&id);
+        self.peers.insert(id, Peer { socket, topics: HashSet::new() });
+        replaced
+    }
+
+    /// Remove a connection, leaving all its topics.
+    pub fn remove(&mut self, id: &Id) -> Option<WebSocket<Stream>> {
+        let peer = self.peers.remove(id)?;
+        for topic in &peer.topics {
+            if let Some(subscribers) = self.topics.get_mut(topic) {
+                subscribers.remove(id);
+                if subscribers.is_empty() {
+                    self.topics.remove(topic);
+                }
+            }
+        }
+        Some(peer.socket)
+    }
+
+    /// Get a connection, e.g. to read from it.
+    pub fn get_mut(&mut self, id: &Id) -> Option<&mut WebSocket<Stream>> {
+        self.peers.get_mut(id).map(|peer| &mut peer.socket)
+    }
+
+    /// Check if a connection with the ID was added.
+    pub fn contains(&self, id: &Id) -> bool {
+        self.peers.contains_key(id)
+    }
+
+    /// The number of connections.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Check if there are no connections.
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Iterate over the IDs of the connections.
+    pub fn ids(&self) -> impl Iterator<Item = &Id> {
+        self.peers.keys()
+    }
+
+    /// Subscribe a connection to a topic. Returns `false` if there is no connection with the ID.
+    pub fn join(&mut self, id: &Id, topic: impl Into<String>) -> bool {
+        let Some(peer) = self.peers.get_mut(id) else { return false };
+        let topic = topic.into();
+        self.topics.entry(topic.clone()).or_default().insert(id.clone());
+        peer.topics.insert(topic);
+        true
+    }
+
+    /// Unsubscribe a connection from a topic. Returns `false` if it was not subscribed.
+    pub fn leave(&mut self, id: &Id, topic: &str) -> bool {
+        let Some(peer) = self.peers.get_mut(id) else { return false };
+        if !peer.topics.remove(topic) {
+            return false;
+        }
+        if let Some(subscribers) = self.topics.get_mut(topic) {
+            subscribers.remove(id);
+            if subscribers.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+        true
+    }
+
+    /// Iterate over the IDs of the connections subscribed to a topic.
+    pub fn subscribers(&self, topic: &str) -> impl Iterator<Item = &Id> {
+        self.topics.get(topic).into_iter().flatten()
+    }
+
+    /// Send a message to every connection.
+    pub fn broadcast(&mut self, message: Message) -> BroadcastReport<Id> {
+        let ids: Vec<_> = self.peers.keys().cloned().collect();
+        self.send_to(ids, message)
+    }
+
+    /// Send a message to the connections subscribed to a topic.
+    pub fn publish(&mut self, topic: &str, message: Message) -> BroadcastReport<Id> {
+        let ids: Vec<_> = self.subscribers(topic).cloned().collect();
+        self.send_to(ids, message)
+    }
+
+    fn send_to(&mut self, ids: Vec<Id>, message: Message) -> BroadcastReport<Id> {
+        let message = PreparedMessage::try_from(message);
+        let mut report = BroadcastReport { sent: 0, dropped: Vec::new(), disconnected: Vec::new() };
+        for id in ids {
+            let Some(peer) = self.peers.get_mut(&id) else { continue };
+            let written = match &message {
+                Ok(prepared) => peer.socket.write_prepared(prepared),
+                Err(message) => peer.socket.write(message.clone()),
+            };
+            match written.and_then(|()| peer.socket.flush()) {
+                Ok(()) => report.sent += 1,
+                // The message is buffered and written by the next write or flush.
+                Err(Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => report.sent += 1,
+                Err(Error::WriteBufferFull(_)) if self.backpressure == Backpressure::Drop => {
+                    report.dropped.push(id);
+                }
+                Err(err) => {
+                    self.remove(&id);
+                    report.disconnected.push((id, err));
+                }
+            }
+        }
+        report
+    }
+}
+
+impl<Id, Stream> Default for Hub<Id, Stream>
+where
+    Id: Eq + Hash + Clone,
+    Stream: Read + Write,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Id: fmt::Debug, Stream> fmt::Debug for Hub<Id, Stream> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hub")
+            .field("ids", &self.peers.keys().collect::<Vec<_>>())
+            .field("topics", &self.topics.keys().collect::<Vec<_>>())
+            .field("backpressure", &self.backpressure)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Write};
+
+    use layer8_primitives::crypto::{generate_key_pair, KeyUse};
+
+    use super::{Backpressure, Hub};
+    use crate::{
+        error::{Error, ProtocolError},
+        protocol::{Message, Role, WebSocket, WebSocketConfig},
+    };
+
+    /// A stream collecting everything written, optionally refusing to accept more.
+    #[derive(Default)]
+    struct Output {
+        written: Vec<u8>,
+        blocked: bool,
+    }
+
+    impl Read for Output {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.blocked {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn socket(role: Role) -> WebSocket<Output> {
+        WebSocket::from_raw_socket(Output::default(), role, None)
+    }
+
+    #[test]
+    fn broadcast_per_recipient_class() {
+        let mut hub = Hub::new();
+        hub.insert(1, socket(Role::Server));
+        hub.insert(2, socket(Role::Server));
+        hub.insert(3, socket(Role::Client));
+        let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+        let mut encrypted = socket(Role::Server);
+        encrypted.set_shared_secret(private_key.get_ecdh_shared_secret(&public_key).unwrap());
+        hub.insert(4, encrypted);
+
+        let report = hub.broadcast(Message::text("Hello"));
+        assert_eq!(report.sent, 4);
+        let plain = [0x81, 0x05, b'H', b'e', b'l', b'l', b'o'];
+        assert_eq!(hub.get_mut(&1).unwrap().get_ref().written, plain);
+        assert_eq!(hub.get_mut(&2).unwrap().get_ref().written, plain);
+        // Masked with a 4 byte key.
+        let masked = &hub.get_mut(&3).unwrap().get_ref().written;
+        assert_eq!((masked[0], masked[1], masked.len()), (0x81, 0x85, plain.len() + 4));
+        let encrypted = &hub.get_mut(&4).unwrap().get_ref().written;
+        assert!(!encrypted.ends_with(b"Hello"));
+    }
+
+    #[test]
+    fn topics() {
+        let mut hub = Hub::new();
+        for id in ["a", "b", "c"] {
+            hub.insert(id, socket(Role::Server));
+        }
+        assert!(hub.join(&"a", "news"));
+        assert!(hub.join(&"b", "news"));
+        assert!(!hub.join(&"unknown", "news"));
+        assert!(hub.leave(&"b", "news"));
+        assert!(!hub.leave(&"b", "news"));
+
+        let report = hub.publish("news", Message::binary(vec![1, 2, 3]));
+        assert_eq!(report.sent, 1);
+        assert_eq!(hub.get_mut(&"a").unwrap().get_ref().written, [0x82, 0x03, 1, 2, 3]);
+        assert!(hub.get_mut(&"b").unwrap().get_ref().written.is_empty());
+
+        hub.remove(&"a");
+        assert_eq!(hub.subscribers("news").count(), 0);
+        assert_eq!(hub.publish("news", Message::text("nobody")).sent, 0);
+    }
+
+    fn blocked_socket() -> WebSocket<Output> {
+        let config = WebSocketConfig::default().write_buffer_size(0).max_write_buffer_size(8);
+        let output = Output { written: Vec::new(), blocked: true };
+        WebSocket::from_raw_socket(output, Role::Server, Some(config))
+    }
+
+    #[test]
+    fn backpressure_drop() {
+        let mut hub = Hub::new();
+        hub.insert(1, blocked_socket());
+        assert_eq!(hub.broadcast(Message::text("fits")).sent, 1);
+
+        let report = hub.broadcast(Message::text("too much"));
+        assert_eq!(report.dropped, [1]);
+        assert!(hub.contains(&1));
+    }
+
+    #[test]
+    fn backpressure_disconnect() {
+        let mut hub = Hub::new().backpressure(Backpressure::Disconnect);
+        hub.insert(1, blocked_socket());
+        hub.join(&1, "news");
+        assert_eq!(hub.publish("news", Message::text("fits")).sent, 1);
+
+        let report = hub.publish("news", Message::text("too much"));
+        assert!(matches!(report.disconnected[..], [(1, Error::WriteBufferFull(_))]));
+        assert!(hub.is_empty());
+        assert_eq!(hub.subscribers("news").count(), 0);
+    }
+
+    #[test]
+    fn remove_failed_connections() {
+        let mut hub = Hub::new();
+        let mut closed = WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, None);
+        closed.close(None).unwrap();
+        hub.insert(1, closed);
+
+        // Text is written as a prepared message, which is rejected like any other write.
+        let report = hub.broadcast(Message::text("Hello"));
+        assert_eq!(report.sent, 0);
+        assert!(matches!(
+            report.disconnected[..],
+            [(1, Error::Protocol(ProtocolError::SendAfterClosing))]
+        ));
+        assert!(hub.is_empty());
+    }
+}
@@ -22,13 +22,15 @@ pub struct Layer8Streamer<Stream> {
     frame_socket: FrameSocket<Stream>,
     /// The shared secret used to encrypt and decrypt the data, if provided.
     shared_secret: Option<Jwk>,
+    /// True if a chunked message has been started but not finished yet.
+    sending_chunks: bool,
 }
 
 impl<Stream> Layer8Streamer<Stream> {
     /// Create a new Layer8Stream with the provided stream and shared secret.
     pub fn new(stream: Stream, shared_secret: Option<Jwk>) -> Self {
         let frame_socket = FrameSocket::new(stream);
-        Layer8Streamer { frame_socket, shared_secret }
+        Layer8Streamer { frame_socket, shared_secret, sending_chunks: false }
     }
 }
 
@@ -64,13 +66,15 @@ impl<Stream: Read + Write> Layer8Streamer<Stream> {
             Message::Pong(data) => Frame::pong(data),
             Message::Close(code) => Frame::close(code),
             Message::Frame(f) => f,
+            Message::Chunk(chunk) => {
+                let continuation = self.sending_chunks;
+                self.sending_chunks = !chunk.is_final;
+                chunk.into_frame(continuation)
+            }
         };
 
         // if frame requires encryption, we encrypt it
         let frame = if let Some(secret_key) = &self.shared_secret {
-            // todo: rm
-            println!("encrypting frame: {}", String::from_utf8_lossy(frame.payload()));
-
             let mut frame_buf = Vec::new();
             frame.format_into_buf(&mut frame_buf).map_err(|e| {
                 Error::new(std::io::ErrorKind::Other, format!("Failed to format frame: {}", e))
@@ -83,8 +87,6 @@ impl<Stream: Read + Write> Layer8Streamer<Stream> {
             )
             .to_json_bytes();
 
-            // todo: rm
-            println!("encrypted frame!");
             Frame::message(encrypted_payload, OpCode::Data(OpData::Binary), true)
         } else {
             frame
@@ -106,9 +108,6 @@ impl<Stream: Read + Write> Layer8Streamer<Stream> {
 
         // we expect the frame to be encrypted, unless secret is not provided
         if let Some(secret_key) = &self.shared_secret {
-            // todo: rm
-            println!("decrypting frame!");
-
             let data = RoundtripEnvelope::from_json_bytes(frame.payload())
                 .map_err(|e| {
                     Error::new(
@@ -141,9 +140,6 @@ impl<Stream: Read + Write> Layer8Streamer<Stream> {
                     ))
                 }
             };
-
-            // todo: rm
-            println!("decrypted frame: {:?}", String::from_utf8_lossy(frame.payload()));
         }
 
         Ok(Some(Message::Frame(frame)))
@@ -24,10 +24,19 @@ pub mod client;
 pub mod error;
 #[cfg(feature = "handshake")]
 pub mod handshake;
+pub mod hub;
+#[cfg(feature = "handshake")]
+pub mod listener;
 pub mod protocol;
 #[cfg(feature = "handshake")]
+pub mod proxy;
+#[cfg(feature = "handshake")]
+pub mod reconnect;
+#[cfg(feature = "handshake")]
 mod server;
 pub mod stream;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 #[cfg(all(any(feature = "native-tls", feature = "__rustls-tls"), feature = "handshake"))]
 mod tls;
 pub mod util;
@@ -44,9 +53,22 @@ pub use bytes::Bytes;
 
 #[cfg(feature = "handshake")]
 pub use crate::{
-    client::{client, connect, ClientRequestBuilder},
+    client::{
+        client, connect, connect_with_options, ClientRequestBuilder, ConnectOptions, RedirectPolicy,
+    },
     handshake::{client::ClientHandshake, server::ServerHandshake, HandshakeError},
-    server::{accept, accept_hdr, accept_hdr_with_config, accept_with_config},
+    listener::WebSocketServer,
+    reconnect::ReconnectingClient,
+    server::{
+        accept, accept_authenticated, accept_from_request, accept_hdr, accept_hdr_from_request,
+        accept_hdr_with_config, accept_with_config,
+    },
+};
+
+#[cfg(all(unix, feature = "handshake"))]
+pub use crate::{
+    client::{connect_unix, connect_unix_with_options},
+    server::{accept_unix, accept_unix_with_config},
 };
 
 #[cfg(all(any(feature = "native-tls", feature = "__rustls-tls"), feature = "handshake"))]
@@ -0,0 +1,520 @@
+//! A blocking server accepting many WebSocket connections on a pool of worker threads.
+
+use std::{
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use http::StatusCode;
+use layer8_primitives::crypto::Jwk;
+use log::*;
+
+use crate::{
+    error::{Error, Result},
+    handshake::{
+        machine::HandshakeConfig,
+        origin::OriginPolicy,
+        server::{encode_error_response, ErrorResponse, Request, Response, ServerHandshake},
+        HandshakeError,
+    },
+    protocol::{frame::coding::CloseCode, CloseFrame, Message, WebSocket, WebSocketConfig},
+};
+
+/// How often blocked accepts and reads wake up to check for a shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The longest time a rejected connection is kept open for the client to read the response.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The number of rejected connections kept open at the same time. Further ones are closed right
+/// after writing the response.
+const MAX_REJECTED: usize = 64;
+
+type Handler = dyn Fn(&mut Connection) -> Result<()> + Send + Sync;
+type SecretProvider = dyn Fn(&Request) -> Option<Jwk> + Send + Sync;
+
+/// A WebSocket server running a handler for every connection on a pool of worker threads.
+///
+/// Every accepted connection is handed to an idle worker, which does the handshake with
+/// [`accept_hdr_with_config`](crate::accept_hdr_with_config) and runs the handler until it
+/// returns. Connections above [`WebSocketServerBuilder::max_connections`] are rejected with
+/// `503 Service Unavailable`.
+///
+/// # Example
+///
+/// ```rust no_run
+/// use layer8_tungstenite::WebSocketServer;
+///
+/// let server = WebSocketServer::builder(|conn| loop {
+///     let msg = conn.read()?;
+///     if msg.is_text() || msg.is_binary() {
+///         conn.send(msg)?;
+///     }
+/// })
+/// .workers(8)
+/// .bind("127.0.0.1:3012")
+/// .unwrap();
+/// let shutdown = server.shutdown_handle();
+/// std::thread::spawn(move || server.run());
+/// // ...
+/// shutdown.shutdown();
+/// ```
+pub struct WebSocketServer {
+    listener: TcpListener,
+    workers: usize,
+    max_connections: usize,
+    shared: Arc<Shared>,
+}
+
+/// State shared by the acceptor and the workers.
+struct Shared {
+    handler: Arc<Handler>,
+    secret_provider: Option<Arc<SecretProvider>>,
+    origin_policy: Option<OriginPolicy>,
+    config: Option<WebSocketConfig>,
+    handshake_config: HandshakeConfig,
+    handshake_timeout: Option<Duration>,
+    close_timeout: Duration,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl WebSocketServer {
+    /// Start building a server running the handler for every connection.
+    ///
+    /// The connection is closed once the handler returns. An error returned by the handler is
+    /// logged.
+    pub fn builder<H>(handler: H) -> WebSocketServerBuilder
+    where
+        H: Fn(&mut Connection) -> Result<()> + Send + Sync + 'static,
+    {
+        WebSocketServerBuilder {
+            handler: Arc::new(handler),
+            workers: 16,
+            max_connections: 1024,
+            handshake_timeout: Some(Duration::from_secs(5)),
+            handshake_config: HandshakeConfig::default().min_bytes_per_second(Some(128)),
+            close_timeout: Duration::from_secs(5),
+            config: None,
+            secret_provider: None,
+            origin_policy: None,
+        }
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// A handle to shut the running server down from another thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shared.shutdown.clone())
+    }
+
+    /// Accept connections until [`ShutdownHandle::shutdown`] is called, then wait for every
+    /// connection to be closed.
+    pub fn run(self) -> Result<()> {
+        self.listener.set_nonblocking(true)?;
+        let (tx, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+        let active = Arc::new(AtomicUsize::new(0));
+        let workers: Vec<_> = (0..self.workers)
+            .map(|_| {
+                let (rx, shared, active) = (rx.clone(), self.shared.clone(), active.clone());
+                thread::spawn(move || work(&rx, &shared, &active))
+            })
+            .collect();
+        let mut rejected = Vec::new();
+
+        while !self.shared.shutdown.load(Ordering::Relaxed) {
+            linger(&mut rejected);
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                // E.g. running out of file descriptors, which may resolve itself.
+                Err(err) => {
+                    warn!("Failed to accept a connection: {err}");
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            if active.load(Ordering::Relaxed) >= self.max_connections {
+                debug!("Rejecting connection above the limit of {}", self.max_connections);
+                match reject(&stream) {
+                    Ok(()) if rejected.len() < MAX_REJECTED => {
+                        rejected.push((stream, Instant::now()))
+                    }
+                    Ok(()) => {}
+                    Err(err) => debug!("Failed to reject connection: {err}"),
+                }
+                continue;
+            }
+            // Accepted sockets inherit the non-blocking mode on some platforms.
+            if let Err(err) = stream.set_nonblocking(false) {
+                debug!("Dropping connection: {err}");
+                continue;
+            }
+            active.fetch_add(1, Ordering::Relaxed);
+            if tx.send(stream).is_err() {
+                break;
+            }
+        }
+
+        drop(tx);
+        for worker in workers {
+            let _ = worker.join();
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for WebSocketServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketServer")
+            .field("listener", &self.listener)
+            .field("workers", &self.workers)
+            .field("max_connections", &self.max_connections)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builds a [`WebSocketServer`], see [`WebSocketServer::builder`].
+pub struct WebSocketServerBuilder {
+    handler: Arc<Handler>,
+    workers: usize,
+    max_connections: usize,
+    handshake_timeout: Option<Duration>,
+    handshake_config: HandshakeConfig,
+    close_timeout: Duration,
+    config: Option<WebSocketConfig>,
+    secret_provider: Option<Arc<SecretProvider>>,
+    origin_policy: Option<OriginPolicy>,
+}
+
+impl WebSocketServerBuilder {
+    /// Set the number of worker threads, i.e. the number of connections handled at the same
+    /// time. Further connections wait for a worker. The default value is 16.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is 0.
+    pub fn workers(mut self, workers: usize) -> Self {
+        assert!(workers > 0, "the server needs at least one worker");
+        self.workers = workers;
+        self
+    }
+
+    /// Set the maximum number of connections handled or waiting for a worker. Connections above
+    /// the limit are rejected with `503 Service Unavailable`. The default value is 1024.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Set the deadline for the whole handshake. Until then a client which sends nothing keeps a
+    /// worker busy, so this bounds how long idle connections can hold the workers. The default
+    /// value is 5 seconds.
+    pub fn handshake_timeout(mut self, handshake_timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Set the limits for reading the handshake request, protecting workers from clients
+    /// sending it slowly. The earlier of [`HandshakeConfig::timeout`] and
+    /// [`Self::handshake_timeout`] applies. The default value has a minimum rate of 128 bytes per
+    /// second.
+    pub fn handshake_config(mut self, handshake_config: HandshakeConfig) -> Self {
+        self.handshake_config = handshake_config;
+        self
+    }
+
+    /// Set the time a connection waits for the client to reply to the close frame sent on
+    /// shutdown. The default value is 5 seconds.
+    pub fn close_timeout(mut self, close_timeout: Duration) -> Self {
+        self.close_timeout = close_timeout;
+        self
+    }
+
+    /// Set the configuration of the accepted connections. The default value is `None`.
+    pub fn config(mut self, config: Option<WebSocketConfig>) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the layer8 shared secret of every connection from the handshake request, leaving the
+    /// connection unencrypted if the provider returns `None`.
+    pub fn secret_provider<P>(mut self, provider: P) -> Self
+    where
+        P: Fn(&Request) -> Option<Jwk> + Send + Sync + 'static,
+    {
+        self.secret_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Reject handshakes from origins not allowed by the policy with `403 Forbidden`, before the
+    /// secret provider runs. The default value is `None`, i.e. every origin is allowed.
+    pub fn origin_policy(mut self, origin_policy: Option<OriginPolicy>) -> Self {
+        self.origin_policy = origin_policy;
+        self
+    }
+
+    /// Bind the server to the given address.
+    pub fn bind(self, addr: impl ToSocketAddrs) -> Result<WebSocketServer> {
+        Ok(WebSocketServer {
+            listener: TcpListener::bind(addr)?,
+            workers: self.workers,
+            max_connections: self.max_connections,
+            shared: Arc::new(Shared {
+                handler: self.handler,
+                secret_provider: self.secret_provider,
+                origin_policy: self.origin_policy,
+                config: self.config,
+                handshake_config: self.handshake_config,
+                handshake_timeout: self.handshake_timeout,
+                close_timeout: self.close_timeout,
+                shutdown: Arc::default(),
+            }),
+        })
+    }
+}
+
+impl fmt::Debug for WebSocketServerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketServerBuilder")
+            .field("workers", &self.workers)
+            .field("max_connections", &self.max_connections)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("handshake_config", &self.handshake_config)
+            .field("close_timeout", &self.close_timeout)
+            .field("config", &self.config)
+            .field("secret_provider", &self.secret_provider.is_some())
+            .field("origin_policy", &self.origin_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Shuts a [`WebSocketServer`] down.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    /// Stop accepting connections and close every connection with [`CloseCode::Away`].
+    ///
+    /// The close frame is sent the next time the handler calls [`Connection::read`]. Handlers
+    /// waiting in [`Connection::send`] for a client not reading give up after the close timeout.
+    /// [`WebSocketServer::run`] returns once every handler returned.
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A connection accepted by a [`WebSocketServer`].
+#[derive(Debug)]
+pub struct Connection {
+    socket: WebSocket<TcpStream>,
+    request: Request,
+    peer_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    close_timeout: Duration,
+    closing_since: Option<Instant>,
+}
+
+impl Connection {
+    /// Read a message, see [`WebSocket::read`].
+    ///
+    /// Once the server shuts down a close frame with [`CloseCode::Away`] is sent and the client
+    /// is waited for to complete the close handshake, ending with [`Error::ConnectionClosed`].
+    pub fn read(&mut self) -> Result<Message> {
+        loop {
+            if self.closing_since.is_none() && self.shutdown.load(Ordering::Relaxed) {
+                self.closing_since = Some(Instant::now());
+                let reason = "Server shutting down".into();
+                // A blocked close frame is flushed by the next read.
+                match self.socket.close(Some(CloseFrame { code: CloseCode::Away, reason })) {
+                    Err(Error::Io(err)) if is_timeout(&err) => {}
+                    result => result?,
+                }
+            }
+
+            match self.socket.read() {
+                Err(Error::Io(err)) if is_timeout(&err) => self.check_close_timeout()?,
+                result => return result,
+            }
+        }
+    }
+
+    /// Send a message, see [`WebSocket::send`].
+    ///
+    /// Waits for the client to read the message. Once the server shuts down it gives up after
+    /// the close timeout, failing with [`ErrorKind::TimedOut`].
+    pub fn send(&mut self, message: Message) -> Result<()> {
+        // A message blocked on the stream is already queued and only needs to be flushed.
+        let mut result = self.socket.send(message);
+        loop {
+            match result {
+                Err(Error::Io(err)) if is_timeout(&err) => {
+                    if self.closing_since.is_none() && self.shutdown.load(Ordering::Relaxed) {
+                        self.closing_since = Some(Instant::now());
+                    }
+                    self.check_close_timeout()?;
+                }
+                result => return result,
+            }
+            result = self.socket.flush();
+        }
+    }
+
+    fn check_close_timeout(&self) -> Result<()> {
+        match self.closing_since {
+            Some(closing_since) if closing_since.elapsed() >= self.close_timeout => {
+                Err(Error::Io(ErrorKind::TimedOut.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The handshake request of the client.
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// The address of the client.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Whether the server is shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// The underlying WebSocket.
+    ///
+    /// Its stream has short read and write timeouts, so reads and writes directly on the
+    /// WebSocket may fail with [`ErrorKind::WouldBlock`] and should be retried.
+    pub fn websocket(&mut self) -> &mut WebSocket<TcpStream> {
+        &mut self.socket
+    }
+}
+
+/// Serve connections received from the acceptor until it stops.
+fn work(rx: &Mutex<mpsc::Receiver<TcpStream>>, shared: &Shared, active: &AtomicUsize) {
+    loop {
+        let stream = match rx.lock().map(|rx| rx.recv()) {
+            Ok(Ok(stream)) => stream,
+            _ => return,
+        };
+        if !shared.shutdown.load(Ordering::Relaxed) {
+            match panic::catch_unwind(AssertUnwindSafe(|| serve(stream, shared))) {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => debug!("Connection failed: {err}"),
+                Err(_) => error!("Connection handler panicked"),
+            }
+        }
+        active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn serve(stream: TcpStream, shared: &Shared) -> Result<()> {
+    let peer_addr = stream.peer_addr()?;
+    // Wake up regularly, so that idle clients are dropped at the deadline and on shutdown.
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    stream.set_write_timeout(Some(POLL_INTERVAL))?;
+    let mut handshake_config = shared.handshake_config;
+    if let Some(timeout) = shared.handshake_timeout {
+        let timeout = handshake_config.timeout.map_or(timeout, |t| t.min(timeout));
+        handshake_config = handshake_config.timeout(Some(timeout));
+    }
+
+    let mut accepted = None;
+    let callback = |request: &Request, response: Response| {
+        if let Some(origin_policy) = &shared.origin_policy {
+            origin_policy.check(request)?;
+        }
+        let secret = shared.secret_provider.as_ref().and_then(|provider| provider(request));
+        accepted = Some((request.clone(), secret));
+        Ok(response)
+    };
+    let mut result = ServerHandshake::start(stream, callback, shared.config.clone())
+        .with_handshake_config(handshake_config)
+        .handshake();
+    let mut socket = loop {
+        match result {
+            Ok(socket) => break socket,
+            Err(HandshakeError::Failure(err)) => return Err(err),
+            Err(HandshakeError::Interrupted(_)) if shared.shutdown.load(Ordering::Relaxed) => {
+                return Err(Error::ConnectionClosed)
+            }
+            // A read or write timed out, the handshake checks its deadline when resumed.
+            Err(HandshakeError::Interrupted(mid)) => result = mid.handshake(),
+        }
+    };
+    let (request, secret) = accepted.expect("Bug: handshake finished without a request");
+    if let Some(secret) = secret {
+        socket.set_shared_secret(secret);
+    }
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    socket.get_ref().set_write_timeout(Some(POLL_INTERVAL))?;
+
+    let mut connection = Connection {
+        socket,
+        request,
+        peer_addr,
+        shutdown: shared.shutdown.clone(),
+        close_timeout: shared.close_timeout,
+        closing_since: None,
+    };
+    let result = (shared.handler)(&mut connection);
+    // Errors are expected if the handler returned because the connection is gone.
+    let _ = connection.socket.close(None);
+    let _ = connection.socket.flush();
+    result
+}
+
+/// Whether an IO error is a read or write timeout of a blocking socket.
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Answer a connection above the connection limit with `503 Service Unavailable`, without
+/// blocking or waiting for the request.
+fn reject(stream: &TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut response = ErrorResponse::new(Some("Too many connections".into()));
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    let response = encode_error_response(&response).map_err(io::Error::other)?;
+    // The response fits into the empty send buffer of the socket.
+    let mut stream = stream;
+    stream.write_all(&response)
+}
+
+/// Close rejected connections once the client closed them or [`REJECT_TIMEOUT`] passed.
+///
+/// Their requests are read meanwhile, as closing a connection with unread data resets it and
+/// may discard the response before the client read it.
+fn linger(rejected: &mut Vec<(TcpStream, Instant)>) {
+    let mut buf = [0; 1024];
+    rejected.retain(|(stream, rejected_at)| {
+        let mut stream = stream;
+        // Bounded, so that a client sending endlessly does not keep the loop busy.
+        for _ in 0..16 {
+            match stream.read(&mut buf) {
+                Ok(0) => return false,
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => return false,
+            }
+        }
+        rejected_at.elapsed() < REJECT_TIMEOUT
+    });
+}
@@ -0,0 +1,149 @@
+//! Queue of control frames sent by the WebSocket itself.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use super::frame::Frame;
+
+/// Control frames waiting to be written and pings waiting for an answer.
+///
+/// Returned by [`WebSocket::pending_control`](super::WebSocket::pending_control).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct PendingControl {
+    /// A close frame is queued.
+    pub close: bool,
+    /// The number of queued pong replies to received pings.
+    pub pongs: usize,
+    /// The number of sent pings which have not been answered by a pong yet.
+    pub unanswered_pings: usize,
+}
+
+/// Outgoing control frames queued automatically, e.g. replies to pings and close frames.
+///
+/// A queued close frame always wins: it drops the queued pongs and no pongs are queued
+/// after it. Every received ping is answered, unless the queue reaches its limit, in which
+/// case only the most recent pings are answered (RFC 6455, section 5.5.3).
+#[derive(Debug, Default)]
+pub(super) struct ControlQueue {
+    close: Option<Frame>,
+    pongs: VecDeque<Bytes>,
+    /// Payloads and send times of the pings not answered yet, oldest first.
+    pings: VecDeque<(Bytes, Instant)>,
+}
+
+impl ControlQueue {
+    /// Returns true if there is nothing to write.
+    pub(super) fn is_empty(&self) -> bool {
+        self.close.is_none() && self.pongs.is_empty()
+    }
+
+    /// Queue a reply to a ping.
+    pub(super) fn push_pong(&mut self, data: Bytes, limit: usize) {
+        if self.close.is_some() || self.pongs.contains(&data) {
+            return;
+        }
+        if self.pongs.len() >= limit {
+            self.pongs.pop_front();
+        }
+        self.pongs.push_back(data);
+    }
+
+    /// Queue a close frame, dropping the pongs.
+    pub(super) fn set_close(&mut self, close: Frame) {
+        self.pongs.clear();
+        self.close = Some(close);
+    }
+
+    /// The next frame to write, left in the queue until it is [popped](Self::pop).
+    pub(super) fn peek(&self) -> Option<Frame> {
+        self.close.clone().or_else(|| self.pongs.front().cloned().map(Frame::pong))
+    }
+
+    /// Take the next frame to write.
+    pub(super) fn pop(&mut self) -> Option<Frame> {
+        self.close.take().or_else(|| self.pongs.pop_front().map(Frame::pong))
+    }
+
+    /// Remember a sent ping until it is answered.
+    pub(super) fn ping_sent(&mut self, data: Bytes, limit: usize) {
+        if self.pings.len() >= limit {
+            self.pings.pop_front();
+        }
+        self.pings.push_back((data, Instant::now()));
+    }
+
+    /// A pong arrived, it answers the matching ping and all pings sent before it.
+    ///
+    /// Returns the round-trip time of the matching ping.
+    pub(super) fn pong_received(&mut self, data: &Bytes) -> Option<Duration> {
+        let pos = self.pings.iter().position(|(ping, _)| ping == data)?;
+        let (_, sent) = self.pings.drain(..=pos).last()?;
+        Some(sent.elapsed())
+    }
+
+    /// Summary of the queue for introspection.
+    pub(super) fn pending(&self) -> PendingControl {
+        PendingControl {
+            close: self.close.is_some(),
+            pongs: self.pongs.len(),
+            unanswered_pings: self.pings.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::frame::{
+            coding::{Control as OpCtl, OpCode},
+            Frame,
+        },
+        ControlQueue, PendingControl,
+    };
+
+    #[test]
+    fn pongs_are_kept_and_coalesced() {
+        let mut queue = ControlQueue::default();
+        queue.push_pong("a".into(), 2);
+        queue.push_pong("a".into(), 2);
+        queue.push_pong("b".into(), 2);
+        assert_eq!(queue.pending(), PendingControl { pongs: 2, ..<_>::default() });
+
+        // Over the limit only the most recent pings are answered.
+        queue.push_pong("c".into(), 2);
+        assert_eq!(queue.pop().unwrap().payload(), b"b");
+        assert_eq!(queue.pop().unwrap().payload(), b"c");
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn close_wins() {
+        let mut queue = ControlQueue::default();
+        queue.push_pong("a".into(), 4);
+        queue.set_close(Frame::close(None));
+        queue.push_pong("b".into(), 4);
+        assert_eq!(queue.pending(), PendingControl { close: true, ..<_>::default() });
+
+        let close = queue.peek().unwrap();
+        assert_eq!(close.header().opcode, OpCode::Control(OpCtl::Close));
+        assert_eq!(queue.pop(), Some(close));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn pings_are_answered() {
+        let mut queue = ControlQueue::default();
+        queue.ping_sent("1".into(), 4);
+        queue.ping_sent("2".into(), 4);
+        queue.ping_sent("3".into(), 4);
+        assert_eq!(queue.pong_received(&"unsolicited".into()), None);
+        assert_eq!(queue.pending().unanswered_pings, 3);
+        assert!(queue.pong_received(&"2".into()).is_some());
+        assert_eq!(queue.pending().unanswered_pings, 1);
+    }
+}
@@ -4,9 +4,37 @@ pub fn generate_mask() -> [u8; 4] {
     rand::random()
 }
 
+/// Buffers shorter than this are masked by [`apply_mask_fast32`] without looking for SIMD support.
+const SIMD_MIN_LEN: usize = 64;
+
 /// Mask/unmask a frame.
+///
+/// Uses AVX2 or SSE2 on x86_64 and NEON on aarch64 if the CPU supports it, detected at runtime.
 #[inline]
 pub fn apply_mask(buf: &mut [u8], mask: [u8; 4]) {
+    if buf.len() < SIMD_MIN_LEN {
+        return apply_mask_fast32(buf, mask);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is supported.
+            return unsafe { x86::apply_mask_avx2(buf, mask) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            // SAFETY: SSE2 is supported.
+            return unsafe { x86::apply_mask_sse2(buf, mask) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: NEON is supported.
+            return unsafe { neon::apply_mask_neon(buf, mask) };
+        }
+    }
+
     apply_mask_fast32(buf, mask);
 }
 
@@ -41,6 +69,65 @@ pub fn apply_mask_fast32(buf: &mut [u8], mask: [u8; 4]) {
     apply_mask_fallback(suffix, mask_u32.to_ne_bytes());
 }
 
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    /// Mask 32 bytes at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn apply_mask_avx2(buf: &mut [u8], mask: [u8; 4]) {
+        let mask_vec = _mm256_set1_epi32(i32::from_ne_bytes(mask));
+        let mut chunks = buf.chunks_exact_mut(32);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr().cast::<__m256i>();
+            _mm256_storeu_si256(ptr, _mm256_xor_si256(_mm256_loadu_si256(ptr), mask_vec));
+        }
+        // The chunks have a multiple of 4 bytes, so the mask starts over for the remainder.
+        super::apply_mask_fast32(chunks.into_remainder(), mask);
+    }
+
+    /// Mask 16 bytes at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support SSE2.
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn apply_mask_sse2(buf: &mut [u8], mask: [u8; 4]) {
+        let mask_vec = _mm_set1_epi32(i32::from_ne_bytes(mask));
+        let mut chunks = buf.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr().cast::<__m128i>();
+            _mm_storeu_si128(ptr, _mm_xor_si128(_mm_loadu_si128(ptr), mask_vec));
+        }
+        super::apply_mask_fast32(chunks.into_remainder(), mask);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    /// Mask 16 bytes at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support NEON.
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn apply_mask_neon(buf: &mut [u8], mask: [u8; 4]) {
+        let mask_vec = vreinterpretq_u8_u32(vdupq_n_u32(u32::from_ne_bytes(mask)));
+        let mut chunks = buf.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let ptr = chunk.as_mut_ptr();
+            vst1q_u8(ptr, veorq_u8(vld1q_u8(ptr), mask_vec));
+        }
+        super::apply_mask_fast32(chunks.into_remainder(), mask);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,4 +157,50 @@ mod tests {
             }
         }
     }
+
+    /// Compare the SIMD implementations supported by this CPU with the scalar one.
+    #[test]
+    fn simd_matches_scalar() {
+        let mask = rand::random();
+        let data: Vec<u8> = (0..300).map(|_| rand::random()).collect();
+        let mut expected = data.clone();
+        apply_mask_fallback(&mut expected, mask);
+
+        type Apply = fn(&mut [u8], [u8; 4]);
+        let mut implementations: Vec<(&str, Apply)> =
+            vec![("fast32", apply_mask_fast32), ("dispatch", apply_mask)];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                implementations
+                    .push(("avx2", |buf, mask| unsafe { x86::apply_mask_avx2(buf, mask) }));
+            }
+            if is_x86_feature_detected!("sse2") {
+                implementations
+                    .push(("sse2", |buf, mask| unsafe { x86::apply_mask_sse2(buf, mask) }));
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                implementations
+                    .push(("neon", |buf, mask| unsafe { neon::apply_mask_neon(buf, mask) }));
+            }
+        }
+
+        for (name, apply) in implementations {
+            for start in 0..4 {
+                for end in (start..data.len()).step_by(7) {
+                    let mut masked = data[start..end].to_vec();
+                    apply(&mut masked, mask);
+                    let mut expected = data[start..end].to_vec();
+                    apply_mask_fallback(&mut expected, mask);
+                    assert_eq!(masked, expected, "{name} masking {start}..{end}");
+                }
+            }
+            let mut masked = data.clone();
+            apply(&mut masked, mask);
+            assert_eq!(masked, expected, "{name}");
+        }
+    }
 }
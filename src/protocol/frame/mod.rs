@@ -5,25 +5,36 @@ pub mod coding;
 #[allow(clippy::module_inception)]
 mod frame;
 mod mask;
+mod out_buffer;
 mod utf8;
+pub(super) mod validate;
 
 pub use self::{
     frame::{CloseFrame, Frame, FrameHeader},
     utf8::Utf8Bytes,
 };
 
+use self::out_buffer::OutBuffer;
 use crate::{
+    buffer::BufferPool,
     error::{CapacityError, Error, ProtocolError, Result},
     protocol::frame::mask::apply_mask,
     Message,
 };
-use bytes::BytesMut;
+use bytes::{Buf, Bytes, BytesMut};
 use log::*;
-use std::io::{self, Cursor, Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::{
+    io::{self, Cursor, Read, Write},
+    mem,
+    sync::Arc,
+};
 
 /// Read buffer size used for `FrameSocket`.
 const READ_BUF_LEN: usize = 128 * 1024;
 
+/// The size of the first read into the stack while waiting for a frame without a pooled buffer.
+const POOLED_FIRST_READ_LEN: usize = 1024;
+
 /// A reader and writer for WebSocket frames.
 #[derive(Debug)]
 pub struct FrameSocket<Stream> {
@@ -44,6 +55,13 @@ impl<Stream> FrameSocket<Stream> {
         FrameSocket { stream, codec: FrameCodec::from_partially_read(part, READ_BUF_LEN) }
     }
 
+    /// Create a new frame socket borrowing its read buffer from a pool while reading a frame.
+    pub fn with_buffer_pool(stream: Stream, pool: Arc<BufferPool>) -> Self {
+        let mut codec = FrameCodec::new(0);
+        codec.set_buffer_pool(Some(pool));
+        FrameSocket { stream, codec }
+    }
+
     /// Extract a stream from the socket.
     pub fn into_inner(self) -> (Stream, BytesMut) {
         (self.stream, self.codec.in_buffer)
@@ -104,8 +122,9 @@ where
 pub(super) struct FrameCodec {
     /// Buffer to read data from the stream.
     in_buffer: BytesMut,
-    /// Buffer to send packets to the network.
-    out_buffer: Vec<u8>,
+    /// Buffer to send packets to the network, written with vectored writes so big payloads
+    /// are not copied.
+    out_buffer: OutBuffer,
     /// Capacity limit for `out_buffer`.
     max_out_buffer_len: usize,
     /// Buffer target length to reach before writing to the stream
@@ -116,6 +135,8 @@ pub(super) struct FrameCodec {
     out_buffer_write_len: usize,
     /// Header and remaining size of the incoming packet being processed.
     header: Option<(FrameHeader, u64)>,
+    /// Pool to borrow the `in_buffer` from while a frame is being assembled.
+    pool: Option<Arc<BufferPool>>,
 }
 
 impl FrameCodec {
@@ -127,6 +148,7 @@ impl FrameCodec {
             max_out_buffer_len: usize::MAX,
             out_buffer_write_len: 0,
             header: None,
+            pool: None,
         }
     }
 
@@ -140,6 +162,7 @@ impl FrameCodec {
             max_out_buffer_len: usize::MAX,
             out_buffer_write_len: 0,
             header: None,
+            pool: None,
         }
     }
 
@@ -154,6 +177,21 @@ impl FrameCodec {
         self.out_buffer_write_len = len;
     }
 
+    /// Sets the pool to borrow the `in_buffer` from, returning the current one if it is empty.
+    pub(super) fn set_buffer_pool(&mut self, pool: Option<Arc<BufferPool>>) {
+        self.pool = pool;
+        if let Some(pool) = &self.pool {
+            if self.in_buffer.is_empty() {
+                pool.put(mem::take(&mut self.in_buffer));
+            }
+        }
+    }
+
+    /// Returns true if data is buffered which has not been decoded into a frame yet.
+    pub(super) fn has_buffered_input(&self) -> bool {
+        !self.in_buffer.is_empty()
+    }
+
     /// Read a frame from the provided stream.
     pub(super) fn read_frame(
         &mut self,
@@ -186,14 +224,14 @@ impl FrameCodec {
                     }
 
                     if len <= self.in_buffer.len() {
-                        break self.in_buffer.split_to(len);
+                        break self.take_payload(len);
                     }
                 }
             }
 
             // Not enough data in buffer.
-            self.in_buffer.reserve(self.header.as_ref().map(|(_, l)| *l as usize).unwrap_or(6));
-            if self.read_in(stream)? == 0 {
+            let additional = self.header.as_ref().map(|(_, l)| *l as usize).unwrap_or(6);
+            if self.fill_in_buffer(stream, additional)? == 0 {
                 trace!("no frame received");
                 return Ok(None);
             }
@@ -221,6 +259,46 @@ impl FrameCodec {
         Ok(Some(frame))
     }
 
+    /// Split the payload of the current frame off the `in_buffer`, returning the buffer to the
+    /// pool if nothing else is buffered.
+    fn take_payload(&mut self, len: usize) -> BytesMut {
+        let Some(pool) = &self.pool else { return self.in_buffer.split_to(len) };
+
+        // Copy payloads fitting into a pooled buffer, so that the buffer can be reused.
+        let payload = if len <= pool.buffer_size() {
+            let payload = BytesMut::from(&self.in_buffer[..len]);
+            self.in_buffer.advance(len);
+            payload
+        } else {
+            self.in_buffer.split_to(len)
+        };
+        if self.in_buffer.is_empty() {
+            pool.put(mem::take(&mut self.in_buffer));
+        }
+        payload
+    }
+
+    /// Reserve room for `additional` bytes and read into it. With a pool, a buffer is only
+    /// borrowed once the first bytes of a frame arrive.
+    fn fill_in_buffer(&mut self, stream: &mut impl Read, additional: usize) -> io::Result<usize> {
+        if let Some(pool) = &self.pool {
+            if self.in_buffer.is_empty() && self.header.is_none() {
+                let mut first = [0; POOLED_FIRST_READ_LEN];
+                let len = stream.read(&mut first)?;
+                if len > 0 {
+                    self.in_buffer = pool.take();
+                    self.in_buffer.extend_from_slice(&first[..len]);
+                }
+                return Ok(len);
+            }
+            if self.in_buffer.capacity() == 0 {
+                self.in_buffer = pool.take();
+            }
+        }
+        self.in_buffer.reserve(additional);
+        self.read_in(stream)
+    }
+
     /// Read into available `in_buffer` capacity.
     fn read_in(&mut self, stream: &mut impl Read) -> io::Result<usize> {
         let len = self.in_buffer.len();
@@ -248,8 +326,57 @@ impl FrameCodec {
 
         trace!("writing frame {frame}");
 
-        self.out_buffer.reserve(frame.len());
-        frame.format_into_buf(&mut self.out_buffer).expect("Bug: can't write to vector");
+        self.out_buffer.push_frame(frame);
+
+        if self.out_buffer.len() > self.out_buffer_write_len {
+            self.write_out_buffer(stream)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns true if `len` more bytes fit into the `out_buffer`.
+    pub(super) fn has_room(&self, len: usize) -> bool {
+        len + self.out_buffer.len() <= self.max_out_buffer_len
+    }
+
+    /// Writes several frames into the `out_buffer` at once, so that either all of them or,
+    /// in case of an error, none of them are queued.
+    ///
+    /// The caller must ensure there is enough room for the frames, see [`Self::has_room`].
+    ///
+    /// May write to the stream, will **not** flush.
+    pub(super) fn buffer_frames<Stream>(
+        &mut self,
+        stream: &mut Stream,
+        frames: Vec<Frame>,
+    ) -> Result<()>
+    where
+        Stream: Write,
+    {
+        for frame in frames {
+            trace!("writing frame {frame}");
+            self.out_buffer.push_frame(frame);
+        }
+
+        if self.out_buffer.len() > self.out_buffer_write_len {
+            self.write_out_buffer(stream)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes an already formatted frame into the `out_buffer`, sharing instead of copying it
+    /// unless it is small.
+    ///
+    /// The caller must ensure there is enough room for the frame, see [`Self::has_room`].
+    ///
+    /// May write to the stream, will **not** flush.
+    pub(super) fn buffer_encoded<Stream>(&mut self, stream: &mut Stream, frame: Bytes) -> Result<()>
+    where
+        Stream: Write,
+    {
+        self.out_buffer.push(frame);
 
         if self.out_buffer.len() > self.out_buffer_write_len {
             self.write_out_buffer(stream)
@@ -265,31 +392,25 @@ impl FrameCodec {
     where
         Stream: Write,
     {
-        while !self.out_buffer.is_empty() {
-            let len = stream.write(&self.out_buffer)?;
-            if len == 0 {
-                // This is the same as "Connection reset by peer"
-                return Err(IoError::new(
-                    IoErrorKind::ConnectionReset,
-                    "Connection reset while sending",
-                )
-                .into());
-            }
-            self.out_buffer.drain(0..len);
-        }
-
-        Ok(())
+        Ok(self.out_buffer.write_to(stream)?)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::error::{CapacityError, Error};
+    use crate::{
+        buffer::BufferPool,
+        error::{CapacityError, Error},
+        testing::duplex,
+    };
 
     use super::{Frame, FrameSocket};
 
-    use std::io::Cursor;
+    use std::{
+        io::{self, Cursor, Write},
+        sync::Arc,
+    };
 
     #[test]
     fn read_frames() {
@@ -322,6 +443,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn buffer_pool() {
+        let pool = Arc::new(BufferPool::new(64, 1));
+        let (stream, mut peer) = duplex();
+        let mut sock = FrameSocket::with_buffer_pool(stream, pool.clone());
+
+        peer.write_all(&[0x82, 0x03, 0x01, 0x02, 0x03]).unwrap();
+        let small = sock.read(None).unwrap().unwrap().into_payload();
+        assert_eq!(small, &[0x01, 0x02, 0x03][..]);
+        assert_eq!(pool.idle(), 1);
+
+        // Waiting for the next frame holds no buffer.
+        let err = sock.read(None).unwrap_err();
+        assert!(matches!(err, Error::Io(err) if err.kind() == io::ErrorKind::WouldBlock));
+        assert_eq!(pool.idle(), 1);
+
+        // A frame bigger than the pooled buffers arriving in two pieces.
+        let mut head = vec![0x82, 100];
+        head.extend([0x42; 10]);
+        peer.write_all(&head).unwrap();
+        peer.write_all(&[0x42; 90]).unwrap();
+        assert_eq!(sock.read(None).unwrap().unwrap().into_payload(), &[0x42; 100][..]);
+        // The grown buffer is not kept.
+        assert_eq!(pool.idle(), 0);
+
+        peer.write_all(&[0x82, 0x01, 0x07]).unwrap();
+        assert_eq!(sock.read(None).unwrap().unwrap().into_payload(), &[0x07][..]);
+        assert_eq!(pool.idle(), 1);
+        assert_eq!(small, &[0x01, 0x02, 0x03][..]);
+    }
+
     #[test]
     fn write_frames() {
         let mut sock = FrameSocket::new(Vec::new());
@@ -0,0 +1,204 @@
+//! Buffer of formatted frames waiting to be written.
+
+use std::{
+    collections::VecDeque,
+    io::{self, IoSlice, Write},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::{mask::apply_mask, Frame};
+
+/// Payloads from this size on are kept as a segment of their own instead of being copied.
+const SHARE_MIN_LEN: usize = 4096;
+
+/// The maximum number of segments passed to a single vectored write.
+const MAX_WRITE_SEGMENTS: usize = 64;
+
+/// Formatted frames waiting to be written, kept as segments written with
+/// [`Write::write_vectored`].
+///
+/// Headers, masked payloads and small payloads are copied into a tail, big unmasked payloads
+/// and prepared frames are referenced without copying them. The tail is only split off into a
+/// segment when such a payload is queued behind it, its allocation is reused once written.
+#[derive(Debug, Default)]
+pub(super) struct OutBuffer {
+    /// Segments in write order, the front one possibly partially written.
+    segments: VecDeque<Bytes>,
+    /// The total length of `segments`.
+    segments_len: usize,
+    /// Data copied after the last segment.
+    tail: BytesMut,
+}
+
+impl OutBuffer {
+    /// The number of bytes waiting to be written.
+    pub(super) fn len(&self) -> usize {
+        self.segments_len + self.tail.len()
+    }
+
+    /// Append a frame.
+    pub(super) fn push_frame(&mut self, frame: Frame) {
+        let header = frame.header().clone();
+        let payload_len = frame.payload().len();
+        if header.mask.is_none() && payload_len >= SHARE_MIN_LEN {
+            header
+                .format(payload_len as u64, &mut (&mut self.tail).writer())
+                .expect("Bug: can't write to buffer");
+            self.push(frame.into_payload());
+        } else {
+            self.tail.reserve(frame.len());
+            header
+                .format(payload_len as u64, &mut (&mut self.tail).writer())
+                .expect("Bug: can't write to buffer");
+            let start = self.tail.len();
+            self.tail.extend_from_slice(frame.payload());
+            if let Some(mask) = header.mask {
+                apply_mask(&mut self.tail[start..], mask);
+            }
+        }
+    }
+
+    /// Append already formatted data, copying it only if it is small.
+    pub(super) fn push(&mut self, data: Bytes) {
+        if data.len() < SHARE_MIN_LEN {
+            self.tail.extend_from_slice(&data);
+        } else {
+            self.split_tail();
+            self.segments_len += data.len();
+            self.segments.push_back(data);
+        }
+    }
+
+    /// Turn the tail into a segment, without copying it.
+    fn split_tail(&mut self) {
+        if !self.tail.is_empty() {
+            let tail = self.tail.split().freeze();
+            self.segments_len += tail.len();
+            self.segments.push_back(tail);
+        }
+    }
+
+    /// Write everything to the stream, keeping what is left if writing fails.
+    ///
+    /// Does **not** flush.
+    pub(super) fn write_to(&mut self, stream: &mut impl Write) -> io::Result<()> {
+        while self.len() > 0 {
+            let mut slices = [IoSlice::new(&[]); MAX_WRITE_SEGMENTS];
+            let segments = self.segments.iter().map(|segment| &segment[..]);
+            let tail = Some(&self.tail[..]).filter(|tail| !tail.is_empty());
+            let mut count = 0;
+            for (slice, data) in slices.iter_mut().zip(segments.chain(tail)) {
+                *slice = IoSlice::new(data);
+                count += 1;
+            }
+
+            let len = stream.write_vectored(&slices[..count])?;
+            if len == 0 {
+                // This is the same as "Connection reset by peer"
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "Connection reset while sending",
+                ));
+            }
+            self.advance(len);
+        }
+        Ok(())
+    }
+
+    /// Remove `len` written bytes from the front.
+    fn advance(&mut self, mut len: usize) {
+        while len > 0 {
+            let Some(front) = self.segments.front_mut() else {
+                assert!(len <= self.tail.len(), "Bug: wrote more than buffered");
+                // Clearing the tail keeps its allocation for the next frames.
+                self.tail.advance(len);
+                return;
+            };
+            if len < front.len() {
+                front.advance(len);
+                self.segments_len -= len;
+                return;
+            }
+            len -= front.len();
+            self.segments_len -= front.len();
+            self.segments.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use bytes::Bytes;
+
+    use super::{OutBuffer, SHARE_MIN_LEN};
+    use crate::{
+        protocol::frame::{
+            coding::{Data, OpCode},
+            Frame,
+        },
+        testing::{duplex, WriteFault},
+    };
+
+    fn formatted(frame: Frame) -> Vec<u8> {
+        let mut buf = Vec::new();
+        frame.format_into_buf(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn partial_vectored_writes() {
+        let big = Frame::message(vec![7; SHARE_MIN_LEN * 2], OpCode::Data(Data::Binary), true);
+        let mut masked = Frame::message(vec![9; SHARE_MIN_LEN], OpCode::Data(Data::Binary), true);
+        masked.set_random_mask();
+        let small = Frame::message(vec![1, 2, 3], OpCode::Data(Data::Text), true);
+        let prepared = Bytes::from(vec![5; SHARE_MIN_LEN]);
+
+        let mut expected = Vec::new();
+        let mut out = OutBuffer::default();
+        for frame in [small.clone(), big, masked, small] {
+            expected.extend(formatted(frame.clone()));
+            out.push_frame(frame);
+        }
+        expected.extend_from_slice(&prepared);
+        out.push(prepared);
+        assert_eq!(out.len(), expected.len());
+
+        let (mut stream, mut peer) = duplex();
+        loop {
+            stream.inject([WriteFault::Partial(1000), WriteFault::WouldBlock]);
+            match out.write_to(&mut stream) {
+                Err(err) => assert_eq!(err.kind(), io::ErrorKind::WouldBlock),
+                Ok(()) => break,
+            }
+            assert_eq!(out.len(), expected.len() - stream.pending());
+        }
+        assert_eq!(out.len(), 0);
+        assert_eq!(peer.take_unread(), expected);
+    }
+
+    #[test]
+    fn tail_allocation_is_reused() {
+        let small = Frame::message(vec![1, 2, 3], OpCode::Data(Data::Text), true);
+        let big = Frame::message(vec![7; SHARE_MIN_LEN], OpCode::Data(Data::Binary), true);
+        let (mut stream, mut peer) = duplex();
+        let mut out = OutBuffer::default();
+
+        out.push_frame(small.clone());
+        let allocation = out.tail.as_ptr();
+        out.write_to(&mut stream).unwrap();
+        out.push_frame(small.clone());
+        assert_eq!(out.tail.as_ptr(), allocation);
+        out.write_to(&mut stream).unwrap();
+
+        // Split off in front of the big payload, reclaimed once written.
+        out.push_frame(big);
+        out.write_to(&mut stream).unwrap();
+        out.push_frame(small);
+        assert_eq!(out.tail.as_ptr(), allocation);
+        out.write_to(&mut stream).unwrap();
+        assert_eq!(peer.read(&mut [0; SHARE_MIN_LEN * 2]).unwrap(), 5 + 5 + 4 + SHARE_MIN_LEN + 5);
+    }
+}
@@ -80,7 +80,10 @@ impl TryFrom<Bytes> for Utf8Bytes {
 
     #[inline]
     fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
-        str::from_utf8(&bytes)?;
+        if !super::validate::is_utf8(&bytes) {
+            // Only the standard library can tell where the invalid data is.
+            str::from_utf8(&bytes)?;
+        }
         Ok(Self(bytes))
     }
 }
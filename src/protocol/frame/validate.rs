@@ -0,0 +1,624 @@
+//! UTF-8 validation of text payloads.
+//!
+//! Uses AVX2 or SSSE3 on x86_64 and NEON on aarch64 if the CPU supports it, detected at runtime.
+//! The SIMD implementations check a whole vector of bytes at once by looking up the error
+//! classes of every pair of adjacent bytes in three tables, see
+//! [Validating UTF-8 In Less Than One Instruction Per Byte](https://arxiv.org/abs/2010.03090).
+//! Otherwise [`str::from_utf8`] is used.
+
+use std::str;
+
+/// Inputs shorter than this are validated by [`str::from_utf8`] without looking for SIMD support.
+const SIMD_MIN_LEN: usize = 64;
+
+/// Check if the bytes are valid UTF-8, the same as `str::from_utf8(bytes).is_ok()`.
+pub(crate) fn is_utf8(bytes: &[u8]) -> bool {
+    if bytes.len() < SIMD_MIN_LEN {
+        return str::from_utf8(bytes).is_ok();
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 is supported.
+            return unsafe { x86::is_utf8_avx2(bytes) };
+        }
+        if is_x86_feature_detected!("ssse3") {
+            // SAFETY: SSSE3 is supported.
+            return unsafe { x86::is_utf8_ssse3(bytes) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: NEON is supported.
+            return unsafe { neon::is_utf8_neon(bytes) };
+        }
+    }
+
+    str::from_utf8(bytes).is_ok()
+}
+
+/// The operations on a vector of bytes the validation is built from.
+///
+/// All methods are unsafe because the CPU must support the instructions they use, they are
+/// inlined into a function enabling the target feature.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+trait Vector: Copy {
+    /// The number of bytes in the vector.
+    const LEN: usize;
+
+    /// Every byte set to `byte`.
+    unsafe fn splat(byte: u8) -> Self;
+    /// A lookup table, repeated if the vector is larger than 16 bytes.
+    unsafe fn table(table: [u8; 16]) -> Self;
+    /// Load `LEN` bytes from an unaligned pointer.
+    unsafe fn load(ptr: *const u8) -> Self;
+
+    unsafe fn and(self, other: Self) -> Self;
+    unsafe fn or(self, other: Self) -> Self;
+    unsafe fn xor(self, other: Self) -> Self;
+    unsafe fn saturating_sub(self, other: Self) -> Self;
+    /// Shift every byte right by 4 bits.
+    unsafe fn high_nibbles(self) -> Self;
+    /// Look up every index, which must be below 16, in this table.
+    unsafe fn lookup(self, indices: Self) -> Self;
+
+    /// The vector shifted by 1 byte, taking the first byte from the end of `prev`.
+    unsafe fn prev1(self, prev: Self) -> Self;
+    /// The vector shifted by 2 bytes, taking the first bytes from the end of `prev`.
+    unsafe fn prev2(self, prev: Self) -> Self;
+    /// The vector shifted by 3 bytes, taking the first bytes from the end of `prev`.
+    unsafe fn prev3(self, prev: Self) -> Self;
+
+    /// Check if all bytes are below 0x80.
+    unsafe fn is_ascii(self) -> bool;
+    /// Check if any byte is not zero.
+    unsafe fn any(self) -> bool;
+}
+
+/// Error classes of two adjacent bytes, combined by the tables below.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod class {
+    /// `11______ 0_______` or `11______ 11______`
+    pub(super) const TOO_SHORT: u8 = 1 << 0;
+    /// `0_______ 10______`
+    pub(super) const TOO_LONG: u8 = 1 << 1;
+    /// `11100000 100_____`
+    pub(super) const OVERLONG_3: u8 = 1 << 2;
+    /// `11110100 1001____`, `11110100 101_____`, `11110101 1001____`, ...
+    pub(super) const TOO_LARGE: u8 = 1 << 3;
+    /// `11101101 101_____`
+    pub(super) const SURROGATE: u8 = 1 << 4;
+    /// `1100000_ 10______`
+    pub(super) const OVERLONG_2: u8 = 1 << 5;
+    /// `11110101 1000____`, `1111011_ 1000____`, `11111___ 1000____`
+    pub(super) const TOO_LARGE_1000: u8 = 1 << 6;
+    /// `11110000 1000____`
+    pub(super) const OVERLONG_4: u8 = 1 << 6;
+    /// `10______ 10______`, valid only as 3rd or 4th byte.
+    pub(super) const TWO_CONTS: u8 = 1 << 7;
+    /// The classes not depending on the low nibble of the first byte.
+    pub(super) const CARRY: u8 = TOO_SHORT | TOO_LONG | TWO_CONTS;
+}
+
+/// The tables and constants used to check a vector.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+struct Tables<V> {
+    first_high: V,
+    first_low: V,
+    second_high: V,
+    low_nibble: V,
+    third_byte: V,
+    fourth_byte: V,
+    high_bit: V,
+    /// The largest value of each byte not starting a sequence which is cut off at the end.
+    incomplete: V,
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+impl<V: Vector> Tables<V> {
+    #[inline(always)]
+    unsafe fn new() -> Self {
+        use class::*;
+
+        let mut incomplete = [0xff; 32];
+        incomplete[V::LEN - 3..V::LEN].copy_from_slice(&[0xf0 - 1, 0xe0 - 1, 0xc0 - 1]);
+        Self {
+            first_high: V::table([
+                // 0_______ ________
+                TOO_LONG,
+                TOO_LONG,
+                TOO_LONG,
+                TOO_LONG,
+                TOO_LONG,
+                TOO_LONG,
+                TOO_LONG,
+                TOO_LONG,
+                // 10______ ________
+                TWO_CONTS,
+                TWO_CONTS,
+                TWO_CONTS,
+                TWO_CONTS,
+                // 1100____ ________
+                TOO_SHORT | OVERLONG_2,
+                // 1101____ ________
+                TOO_SHORT,
+                // 1110____ ________
+                TOO_SHORT | OVERLONG_3 | SURROGATE,
+                // 1111____ ________
+                TOO_SHORT | TOO_LARGE | TOO_LARGE_1000 | OVERLONG_4,
+            ]),
+            first_low: V::table([
+                // ____0000 ________
+                CARRY | OVERLONG_3 | OVERLONG_2 | OVERLONG_4,
+                // ____0001 ________
+                CARRY | OVERLONG_2,
+                // ____001_ ________
+                CARRY,
+                CARRY,
+                // ____0100 ________
+                CARRY | TOO_LARGE,
+                // ____0101 ________
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                // ____011_ ________
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                // ____1___ ________
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                // ____1101 ________
+                CARRY | TOO_LARGE | TOO_LARGE_1000 | SURROGATE,
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+                CARRY | TOO_LARGE | TOO_LARGE_1000,
+            ]),
+            second_high: V::table([
+                // ________ 0_______
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+                // ________ 1000____
+                TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE_1000 | OVERLONG_4,
+                // ________ 1001____
+                TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE,
+                // ________ 101_____
+                TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+                TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+                // ________ 11______
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+                TOO_SHORT,
+            ]),
+            low_nibble: V::splat(0x0f),
+            // Only bytes starting with 111 or 1111 reach 0x80.
+            third_byte: V::splat(0xe0 - 0x80),
+            fourth_byte: V::splat(0xf0 - 0x80),
+            high_bit: V::splat(0x80),
+            incomplete: V::load(incomplete.as_ptr()),
+        }
+    }
+
+    /// The errors in `input`, including sequences started in `prev`.
+    #[inline(always)]
+    unsafe fn check(&self, input: V, prev: V) -> V {
+        let prev1 = input.prev1(prev);
+        let special = self
+            .first_high
+            .lookup(prev1.high_nibbles())
+            .and(self.first_low.lookup(prev1.and(self.low_nibble)))
+            .and(self.second_high.lookup(input.high_nibbles()));
+
+        // Continuations after a 3 or 4 byte lead are expected where the tables report two
+        // continuations in a row, anything else is an error.
+        let third = input.prev2(prev).saturating_sub(self.third_byte);
+        let fourth = input.prev3(prev).saturating_sub(self.fourth_byte);
+        third.or(fourth).and(self.high_bit).xor(special)
+    }
+}
+
+/// Validate `LEN` bytes at a time.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+#[inline(always)]
+unsafe fn validate<V: Vector>(bytes: &[u8]) -> bool {
+    let tables = Tables::<V>::new();
+    let mut error = V::splat(0);
+    let mut prev = V::splat(0);
+    let mut prev_incomplete = V::splat(0);
+
+    let mut chunks = bytes.chunks_exact(V::LEN);
+    for chunk in &mut chunks {
+        let input = V::load(chunk.as_ptr());
+        if input.is_ascii() {
+            // Only a sequence cut off by the end of the previous chunk can be invalid.
+            error = error.or(prev_incomplete);
+        } else {
+            error = error.or(tables.check(input, prev));
+            prev_incomplete = input.saturating_sub(tables.incomplete);
+        }
+        prev = input;
+    }
+
+    // Pad the rest with zeros, which also reveals a sequence cut off by the end.
+    let mut last = [0; 32];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    error = error.or(tables.check(V::load(last.as_ptr()), prev));
+    !error.any()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{validate, Vector};
+
+    /// Validate 32 bytes at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn is_utf8_avx2(bytes: &[u8]) -> bool {
+        validate::<__m256i>(bytes)
+    }
+
+    /// Validate 16 bytes at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support SSSE3.
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn is_utf8_ssse3(bytes: &[u8]) -> bool {
+        validate::<__m128i>(bytes)
+    }
+
+    impl Vector for __m256i {
+        const LEN: usize = 32;
+
+        #[inline(always)]
+        unsafe fn splat(byte: u8) -> Self {
+            _mm256_set1_epi8(byte as i8)
+        }
+
+        #[inline(always)]
+        unsafe fn table(table: [u8; 16]) -> Self {
+            _mm256_broadcastsi128_si256(_mm_loadu_si128(table.as_ptr().cast()))
+        }
+
+        #[inline(always)]
+        unsafe fn load(ptr: *const u8) -> Self {
+            _mm256_loadu_si256(ptr.cast())
+        }
+
+        #[inline(always)]
+        unsafe fn and(self, other: Self) -> Self {
+            _mm256_and_si256(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn or(self, other: Self) -> Self {
+            _mm256_or_si256(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn xor(self, other: Self) -> Self {
+            _mm256_xor_si256(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn saturating_sub(self, other: Self) -> Self {
+            _mm256_subs_epu8(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn high_nibbles(self) -> Self {
+            _mm256_and_si256(_mm256_srli_epi16::<4>(self), Self::splat(0x0f))
+        }
+
+        #[inline(always)]
+        unsafe fn lookup(self, indices: Self) -> Self {
+            _mm256_shuffle_epi8(self, indices)
+        }
+
+        #[inline(always)]
+        unsafe fn prev1(self, prev: Self) -> Self {
+            _mm256_alignr_epi8::<15>(self, _mm256_permute2x128_si256::<0x21>(prev, self))
+        }
+
+        #[inline(always)]
+        unsafe fn prev2(self, prev: Self) -> Self {
+            _mm256_alignr_epi8::<14>(self, _mm256_permute2x128_si256::<0x21>(prev, self))
+        }
+
+        #[inline(always)]
+        unsafe fn prev3(self, prev: Self) -> Self {
+            _mm256_alignr_epi8::<13>(self, _mm256_permute2x128_si256::<0x21>(prev, self))
+        }
+
+        #[inline(always)]
+        unsafe fn is_ascii(self) -> bool {
+            _mm256_movemask_epi8(self) == 0
+        }
+
+        #[inline(always)]
+        unsafe fn any(self) -> bool {
+            _mm256_testz_si256(self, self) == 0
+        }
+    }
+
+    impl Vector for __m128i {
+        const LEN: usize = 16;
+
+        #[inline(always)]
+        unsafe fn splat(byte: u8) -> Self {
+            _mm_set1_epi8(byte as i8)
+        }
+
+        #[inline(always)]
+        unsafe fn table(table: [u8; 16]) -> Self {
+            _mm_loadu_si128(table.as_ptr().cast())
+        }
+
+        #[inline(always)]
+        unsafe fn load(ptr: *const u8) -> Self {
+            _mm_loadu_si128(ptr.cast())
+        }
+
+        #[inline(always)]
+        unsafe fn and(self, other: Self) -> Self {
+            _mm_and_si128(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn or(self, other: Self) -> Self {
+            _mm_or_si128(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn xor(self, other: Self) -> Self {
+            _mm_xor_si128(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn saturating_sub(self, other: Self) -> Self {
+            _mm_subs_epu8(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn high_nibbles(self) -> Self {
+            _mm_and_si128(_mm_srli_epi16::<4>(self), Self::splat(0x0f))
+        }
+
+        #[inline(always)]
+        unsafe fn lookup(self, indices: Self) -> Self {
+            _mm_shuffle_epi8(self, indices)
+        }
+
+        #[inline(always)]
+        unsafe fn prev1(self, prev: Self) -> Self {
+            _mm_alignr_epi8::<15>(self, prev)
+        }
+
+        #[inline(always)]
+        unsafe fn prev2(self, prev: Self) -> Self {
+            _mm_alignr_epi8::<14>(self, prev)
+        }
+
+        #[inline(always)]
+        unsafe fn prev3(self, prev: Self) -> Self {
+            _mm_alignr_epi8::<13>(self, prev)
+        }
+
+        #[inline(always)]
+        unsafe fn is_ascii(self) -> bool {
+            _mm_movemask_epi8(self) == 0
+        }
+
+        #[inline(always)]
+        unsafe fn any(self) -> bool {
+            _mm_movemask_epi8(_mm_cmpeq_epi8(self, _mm_setzero_si128())) != 0xffff
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::{validate, Vector};
+
+    /// Validate 16 bytes at a time.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support NEON.
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn is_utf8_neon(bytes: &[u8]) -> bool {
+        validate::<uint8x16_t>(bytes)
+    }
+
+    impl Vector for uint8x16_t {
+        const LEN: usize = 16;
+
+        #[inline(always)]
+        unsafe fn splat(byte: u8) -> Self {
+            vdupq_n_u8(byte)
+        }
+
+        #[inline(always)]
+        unsafe fn table(table: [u8; 16]) -> Self {
+            vld1q_u8(table.as_ptr())
+        }
+
+        #[inline(always)]
+        unsafe fn load(ptr: *const u8) -> Self {
+            vld1q_u8(ptr)
+        }
+
+        #[inline(always)]
+        unsafe fn and(self, other: Self) -> Self {
+            vandq_u8(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn or(self, other: Self) -> Self {
+            vorrq_u8(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn xor(self, other: Self) -> Self {
+            veorq_u8(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn saturating_sub(self, other: Self) -> Self {
+            vqsubq_u8(self, other)
+        }
+
+        #[inline(always)]
+        unsafe fn high_nibbles(self) -> Self {
+            vshrq_n_u8::<4>(self)
+        }
+
+        #[inline(always)]
+        unsafe fn lookup(self, indices: Self) -> Self {
+            vqtbl1q_u8(self, indices)
+        }
+
+        #[inline(always)]
+        unsafe fn prev1(self, prev: Self) -> Self {
+            vextq_u8::<15>(prev, self)
+        }
+
+        #[inline(always)]
+        unsafe fn prev2(self, prev: Self) -> Self {
+            vextq_u8::<14>(prev, self)
+        }
+
+        #[inline(always)]
+        unsafe fn prev3(self, prev: Self) -> Self {
+            vextq_u8::<13>(prev, self)
+        }
+
+        #[inline(always)]
+        unsafe fn is_ascii(self) -> bool {
+            vmaxvq_u8(self) < 0x80
+        }
+
+        #[inline(always)]
+        unsafe fn any(self) -> bool {
+            vmaxvq_u8(self) != 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::is_utf8;
+
+    type IsUtf8 = fn(&[u8]) -> bool;
+
+    /// The SIMD implementations supported by this CPU and the dispatching function.
+    fn implementations() -> Vec<(&'static str, IsUtf8)> {
+        let mut implementations: Vec<(_, IsUtf8)> = vec![("dispatch", is_utf8)];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                implementations.push(("avx2", |bytes| unsafe { super::x86::is_utf8_avx2(bytes) }));
+            }
+            if is_x86_feature_detected!("ssse3") {
+                implementations
+                    .push(("ssse3", |bytes| unsafe { super::x86::is_utf8_ssse3(bytes) }));
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                implementations.push(("neon", |bytes| unsafe { super::neon::is_utf8_neon(bytes) }));
+            }
+        }
+        implementations
+    }
+
+    fn check(implementations: &[(&str, IsUtf8)], bytes: &[u8]) {
+        let expected = str::from_utf8(bytes).is_ok();
+        for (name, is_utf8) in implementations {
+            assert_eq!(is_utf8(bytes), expected, "{name} validating {bytes:x?}");
+        }
+    }
+
+    /// Every pair of bytes and random sequences of up to 4 bytes starting with every byte,
+    /// across the boundaries of the SIMD vectors and at the end of the input.
+    #[test]
+    fn short_sequences() {
+        let implementations = implementations();
+        let mut rng = StdRng::seed_from_u64(41);
+        let mut bytes = [b'a'; 66];
+        for offset in [0, 14, 15, 31, 62, 63, 65] {
+            for first in 0..=255 {
+                bytes[offset] = first;
+                check(&implementations, &bytes[..offset + 1]);
+                for second in 0..=255 {
+                    if let Some(byte) = bytes.get_mut(offset + 1) {
+                        *byte = second;
+                    }
+                    check(&implementations, &bytes);
+                }
+
+                for _ in 0..64 {
+                    for byte in bytes[offset + 1..].iter_mut().take(3) {
+                        // Mostly continuation bytes, which are the interesting ones.
+                        *byte = match rng.gen_ratio(3, 4) {
+                            true => rng.gen_range(0x80..0xc0),
+                            false => rng.gen(),
+                        };
+                    }
+                    check(&implementations, &bytes);
+                    check(&implementations, &bytes[..(offset + 3).min(bytes.len())]);
+                }
+                bytes[offset..].fill(b'a');
+            }
+        }
+    }
+
+    /// Valid text, corrupted text and random bytes.
+    #[test]
+    fn matches_std() {
+        let implementations = implementations();
+        let mut rng = StdRng::seed_from_u64(41);
+        let pieces =
+            ["a", "{\"id\":1}", "é", "€", "𝄞", "日本語", " ", "\u{7f}", "\u{80}", "\u{10ffff}"];
+        for _ in 0..500 {
+            let len = rng.gen_range(0..200);
+            let text: String = (0..len).map(|_| pieces[rng.gen_range(0..pieces.len())]).collect();
+            check(&implementations, text.as_bytes());
+
+            let mut bytes = text.into_bytes();
+            for _ in 0..4 {
+                if bytes.is_empty() {
+                    break;
+                }
+                let position = rng.gen_range(0..bytes.len());
+                bytes[position] = rng.gen();
+                check(&implementations, &bytes);
+                // Cut off, possibly in the middle of a character.
+                check(&implementations, &bytes[..rng.gen_range(0..=bytes.len())]);
+            }
+
+            let random: Vec<u8> = (0..len).map(|_| rng.gen_range(0x70..=0xff)).collect();
+            check(&implementations, &random);
+        }
+    }
+}
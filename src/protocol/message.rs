@@ -1,4 +1,7 @@
-use super::frame::{CloseFrame, Frame};
+use super::frame::{
+    coding::{Data as OpData, OpCode},
+    CloseFrame, Frame,
+};
 use crate::{
     error::{CapacityError, Error, Result},
     protocol::frame::Utf8Bytes,
@@ -8,7 +11,10 @@ use std::{fmt, result::Result as StdResult, str};
 mod string_collect {
     use utf8::DecodeError;
 
-    use crate::error::{Error, Result};
+    use crate::{
+        error::{Error, Result},
+        protocol::frame::validate::is_utf8,
+    };
 
     #[derive(Debug)]
     pub struct StringCollector {
@@ -45,6 +51,11 @@ mod string_collect {
             }
 
             if !input.is_empty() {
+                if is_utf8(input) {
+                    // SAFETY: The input is valid UTF-8.
+                    self.data.push_str(unsafe { std::str::from_utf8_unchecked(input) });
+                    return Ok(());
+                }
                 match utf8::decode(input) {
                     Ok(text) => {
                         self.data.push_str(text);
@@ -65,6 +76,17 @@ mod string_collect {
             }
         }
 
+        /// Take the text decoded so far. An incomplete trailing character is kept
+        /// to be completed by the next call to [`extend`](Self::extend).
+        pub fn take(&mut self) -> String {
+            std::mem::take(&mut self.data)
+        }
+
+        /// Tell if the collected data ends in the middle of a character.
+        pub fn is_incomplete(&self) -> bool {
+            self.incomplete.is_some()
+        }
+
         pub fn into_string(self) -> Result<String> {
             if self.incomplete.is_some() {
                 Err(Error::Utf8)
@@ -75,7 +97,7 @@ mod string_collect {
     }
 }
 
-use self::string_collect::StringCollector;
+pub(super) use self::string_collect::StringCollector;
 use bytes::Bytes;
 
 /// A struct representing the incomplete message.
@@ -153,8 +175,119 @@ pub enum IncompleteMessageType {
     Binary,
 }
 
+/// A message which is handed out chunk by chunk as its frames arrive
+/// instead of being assembled in memory.
+#[derive(Debug)]
+pub struct StreamingMessage {
+    kind: MessageKind,
+    /// Decoder state carried between the chunks of a text message.
+    text: Option<StringCollector>,
+    /// Total size of the message received so far.
+    size: usize,
+}
+
+impl StreamingMessage {
+    /// Create new.
+    pub fn new(kind: MessageKind) -> Self {
+        let text = match kind {
+            MessageKind::Text => Some(StringCollector::new()),
+            MessageKind::Binary => None,
+        };
+        StreamingMessage { kind, text, size: 0 }
+    }
+
+    /// Turn the payload of the next frame into a chunk.
+    ///
+    /// The size limit applies to the whole message received so far. Text is validated
+    /// incrementally, a character split between two frames is moved to the later chunk.
+    pub fn chunk(
+        &mut self,
+        payload: Bytes,
+        is_final: bool,
+        size_limit: Option<usize>,
+    ) -> Result<MessageChunk> {
+        let max_size = size_limit.unwrap_or_else(usize::max_value);
+        let portion_size = payload.len();
+        // Be careful about integer overflows here.
+        if self.size > max_size || portion_size > max_size - self.size {
+            return Err(Error::Capacity(CapacityError::MessageTooLong {
+                size: self.size.saturating_add(portion_size),
+                max_size,
+            }));
+        }
+        self.size += portion_size;
+
+        let data = match self.text {
+            None => payload,
+            Some(ref mut collector) => {
+                let carried = collector.is_incomplete();
+                collector.extend(&payload)?;
+                if is_final && collector.is_incomplete() {
+                    return Err(Error::Utf8);
+                }
+                let text = collector.take();
+                if !carried && !collector.is_incomplete() {
+                    // The frame holds whole characters only, no need to copy it.
+                    payload
+                } else {
+                    text.into()
+                }
+            }
+        };
+
+        Ok(MessageChunk { kind: self.kind, data, is_final })
+    }
+}
+
+/// The kind of a data message.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum MessageKind {
+    /// A text message.
+    Text,
+    /// A binary message.
+    Binary,
+}
+
+impl From<MessageKind> for OpData {
+    fn from(kind: MessageKind) -> Self {
+        match kind {
+            MessageKind::Text => OpData::Text,
+            MessageKind::Binary => OpData::Binary,
+        }
+    }
+}
+
+/// A piece of a text or binary message.
+///
+/// See [`WebSocketConfig::streaming_read`](crate::protocol::WebSocketConfig::streaming_read).
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct MessageChunk {
+    /// The kind of the message this chunk belongs to.
+    pub kind: MessageKind,
+    /// The chunk payload. For text messages this is valid UTF-8,
+    /// characters are never split between chunks.
+    pub data: Bytes,
+    /// Indicates that this is the last chunk of the message.
+    pub is_final: bool,
+}
+
+impl MessageChunk {
+    /// Attempt to get a &str from the chunk payload.
+    pub fn to_text(&self) -> Result<&str> {
+        Ok(str::from_utf8(&self.data)?)
+    }
+
+    /// Convert the chunk into a data frame. `continuation` tells if other chunks
+    /// of the same message were sent before this one.
+    pub(crate) fn into_frame(self, continuation: bool) -> Frame {
+        let opcode = if continuation { OpData::Continue } else { self.kind.into() };
+        Frame::message(self.data, OpCode::Data(opcode), self.is_final)
+    }
+}
+
 /// An enum representing the various forms of a WebSocket message.
 #[derive(Debug, Eq, PartialEq, Clone)]
+#[non_exhaustive]
 pub enum Message {
     /// A text WebSocket message
     Text(Utf8Bytes),
@@ -172,6 +305,12 @@ pub enum Message {
     Close(Option<CloseFrame>),
     /// Raw frame. Note, that you're not going to get this value while reading the message.
     Frame(Frame),
+    /// A piece of a text or binary message. You only get this value while reading in streaming
+    /// mode, see [`WebSocketConfig::streaming_read`](crate::protocol::WebSocketConfig::streaming_read).
+    ///
+    /// When written, the first chunk of a message is sent as a text or binary frame and the
+    /// following ones as continuation frames, until a chunk with `is_final` set is written.
+    Chunk(MessageChunk),
 }
 
 impl Message {
@@ -216,6 +355,11 @@ impl Message {
         matches!(*self, Message::Close(_))
     }
 
+    /// Indicates whether a message is a chunk of a streamed message.
+    pub fn is_chunk(&self) -> bool {
+        matches!(*self, Message::Chunk(_))
+    }
+
     /// Get the length of the WebSocket message.
     pub fn len(&self) -> usize {
         match *self {
@@ -225,6 +369,7 @@ impl Message {
             }
             Message::Close(ref data) => data.as_ref().map(|d| d.reason.len()).unwrap_or(0),
             Message::Frame(ref frame) => frame.len(),
+            Message::Chunk(ref chunk) => chunk.data.len(),
         }
     }
 
@@ -242,6 +387,7 @@ impl Message {
             Message::Close(None) => <_>::default(),
             Message::Close(Some(frame)) => frame.reason.into(),
             Message::Frame(frame) => frame.into_payload(),
+            Message::Chunk(chunk) => chunk.data,
         }
     }
 
@@ -255,6 +401,7 @@ impl Message {
             Message::Close(None) => Ok(<_>::default()),
             Message::Close(Some(frame)) => Ok(frame.reason),
             Message::Frame(frame) => Ok(frame.into_text()?),
+            Message::Chunk(chunk) => Ok(chunk.data.try_into()?),
         }
     }
 
@@ -269,6 +416,7 @@ impl Message {
             Message::Close(None) => Ok(""),
             Message::Close(Some(ref frame)) => Ok(&frame.reason),
             Message::Frame(ref frame) => Ok(frame.to_text()?),
+            Message::Chunk(ref chunk) => chunk.to_text(),
         }
     }
 }
@@ -362,4 +510,20 @@ mod tests {
         let msg = Message::from(s);
         assert!(msg.is_text());
     }
+
+    #[test]
+    fn streaming_text_split_char() {
+        let mut msg = StreamingMessage::new(MessageKind::Text);
+        let first = msg.chunk(Bytes::from_static(b"caf\xc3"), false, None).unwrap();
+        assert_eq!(first.to_text().unwrap(), "caf");
+        let second = msg.chunk(Bytes::from_static(b"\xa9!"), true, None).unwrap();
+        assert_eq!(second.to_text().unwrap(), "\u{e9}!");
+        assert!(second.is_final);
+    }
+
+    #[test]
+    fn streaming_text_incomplete_at_end() {
+        let mut msg = StreamingMessage::new(MessageKind::Text);
+        assert!(msg.chunk(Bytes::from_static(b"caf\xc3"), true, None).is_err());
+    }
 }
@@ -2,26 +2,42 @@
 
 pub mod frame;
 
+mod control;
 mod message;
-
-pub use self::{frame::CloseFrame, message::Message};
+mod prepared;
+mod rtt;
+mod writer;
+
+pub use self::{
+    control::PendingControl,
+    frame::CloseFrame,
+    message::{Message, MessageChunk, MessageKind},
+    prepared::PreparedMessage,
+    rtt::RttHistogram,
+    writer::MessageWriter,
+};
 
 use self::{
+    control::ControlQueue,
     frame::{
         coding::{CloseCode, Control as OpCtl, Data as OpData, OpCode},
-        Frame, FrameCodec,
+        Frame, FrameCodec, FrameHeader,
     },
-    message::{IncompleteMessage, IncompleteMessageType},
+    message::{IncompleteMessage, IncompleteMessageType, StreamingMessage},
 };
 use crate::{
+    buffer::BufferPool,
     error::{CapacityError, Error, ProtocolError, Result},
     protocol::frame::Utf8Bytes,
 };
 use layer8_primitives::{crypto::Jwk, types::RoundtripEnvelope};
 use log::*;
 use std::{
+    any::Any,
     io::{self, Read, Write},
-    mem::replace,
+    mem::{replace, swap},
+    sync::Arc,
+    time::Duration,
 };
 
 /// Indicates a Client or Server role of the websocket
@@ -42,7 +58,7 @@ pub enum Role {
 ///     .read_buffer_size(256 * 1024)
 ///     .write_buffer_size(256 * 1024);
 /// ```
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct WebSocketConfig {
     /// Read buffer capacity. The default value is 128 KiB.
@@ -82,6 +98,44 @@ pub struct WebSocketConfig {
     /// some popular libraries that are sending unmasked frames, ignoring the RFC.
     /// By default this option is set to `false`, i.e. according to RFC 6455.
     pub accept_unmasked_frames: bool,
+    /// When set to `true`, text and binary messages are not assembled in memory. Instead
+    /// [`WebSocket::read`] returns a [`Message::Chunk`] for every data frame as soon as it
+    /// arrives, so memory usage no longer grows with the message size.
+    /// [`max_message_size`](Self::max_message_size) is enforced as the chunks arrive and text
+    /// is still validated to be UTF-8. The default value is `false`.
+    ///
+    /// With a layer8 shared secret every encrypted envelope is still assembled in memory, since
+    /// it can only be decrypted as a whole. Only the frames inside the envelopes are returned as
+    /// chunks, so streaming only bounds memory usage if the peer fragments big messages across
+    /// envelopes, e.g. with a [`MessageWriter`].
+    pub streaming_read: bool,
+    /// The maximum size of an outgoing message. `None` means no size limit. The limit applies
+    /// to the message payload and, with layer8 encryption, also to the encrypted envelope sent
+    /// on the wire. Messages exceeding it are rejected with [`Error::OutgoingCapacity`].
+    /// The default value is `None`.
+    pub max_outgoing_message_size: Option<usize>,
+    /// The maximum payload size of an outgoing frame. `None` means no size limit. Bigger text
+    /// and binary messages, including encrypted layer8 envelopes, are split into fragments of
+    /// at most this size; bigger control frames are rejected with [`Error::OutgoingCapacity`].
+    /// A [`MessageWriter`] sends fragments of this size, or of 64 KiB without a limit.
+    /// Must not be zero. The default value is `None`.
+    pub max_outgoing_frame_size: Option<usize>,
+    /// The maximum number of pong replies to received pings waiting to be written, and of
+    /// sent pings waiting for a pong. When more pings arrive before the replies are written
+    /// only the most recent ones are answered. Must be greater than zero. The default value is 16.
+    pub max_pending_control_frames: usize,
+    /// When set to `true`, a protocol violation of the peer fails the connection as described
+    /// in [RFC 6455, section 7.1.7](https://tools.ietf.org/html/rfc6455#section-7.1.7):
+    /// before [`WebSocket::read`] returns the error, a close frame with the matching
+    /// [`CloseCode`] (`Protocol`, `Invalid`, `Size` or `Policy`) is queued and flushed if
+    /// possible. By default this option is set to `false`, i.e. the error is just returned.
+    pub close_on_error: bool,
+    /// A pool lending read buffers to many connections. When set, a connection borrows a
+    /// buffer of [`BufferPool::buffer_size`] bytes only while a frame is being assembled and
+    /// [`read_buffer_size`](Self::read_buffer_size) is ignored. Payloads fitting into the
+    /// buffer are copied out of it so that it can be reused. The default value is `None`, i.e.
+    /// every connection keeps its own read buffer.
+    pub buffer_pool: Option<Arc<BufferPool>>,
 }
 
 impl Default for WebSocketConfig {
@@ -93,6 +147,12 @@ impl Default for WebSocketConfig {
             max_message_size: Some(64 << 20),
             max_frame_size: Some(16 << 20),
             accept_unmasked_frames: false,
+            streaming_read: false,
+            max_outgoing_message_size: None,
+            max_outgoing_frame_size: None,
+            max_pending_control_frames: 16,
+            close_on_error: false,
+            buffer_pool: None,
         }
     }
 }
@@ -134,6 +194,50 @@ impl WebSocketConfig {
         self
     }
 
+    /// Set [`Self::streaming_read`], see there for how it applies to layer8 envelopes.
+    pub fn streaming_read(mut self, streaming_read: bool) -> Self {
+        self.streaming_read = streaming_read;
+        self
+    }
+
+    /// Set [`Self::max_outgoing_message_size`].
+    pub fn max_outgoing_message_size(mut self, max_outgoing_message_size: Option<usize>) -> Self {
+        self.max_outgoing_message_size = max_outgoing_message_size;
+        self
+    }
+
+    /// Set [`Self::max_outgoing_frame_size`].
+    pub fn max_outgoing_frame_size(mut self, max_outgoing_frame_size: Option<usize>) -> Self {
+        self.max_outgoing_frame_size = max_outgoing_frame_size;
+        self
+    }
+
+    /// Set [`Self::max_pending_control_frames`].
+    pub fn max_pending_control_frames(mut self, max_pending_control_frames: usize) -> Self {
+        self.max_pending_control_frames = max_pending_control_frames;
+        self
+    }
+
+    /// Set [`Self::close_on_error`].
+    pub fn close_on_error(mut self, close_on_error: bool) -> Self {
+        self.close_on_error = close_on_error;
+        self
+    }
+
+    /// Set [`Self::buffer_pool`].
+    pub fn buffer_pool(mut self, buffer_pool: Arc<BufferPool>) -> Self {
+        self.buffer_pool = Some(buffer_pool);
+        self
+    }
+
+    /// The capacity of the read buffer allocated up front.
+    fn in_buffer_len(&self) -> usize {
+        match self.buffer_pool {
+            Some(_) => 0,
+            None => self.read_buffer_size,
+        }
+    }
+
     /// Panic if values are invalid.
     pub(crate) fn assert_valid(&self) {
         assert!(
@@ -141,6 +245,14 @@ impl WebSocketConfig {
             "WebSocketConfig::max_write_buffer_size must be greater than write_buffer_size, \
             see WebSocketConfig docs`"
         );
+        assert!(
+            self.max_outgoing_frame_size != Some(0),
+            "WebSocketConfig::max_outgoing_frame_size must be greater than zero"
+        );
+        assert!(
+            self.max_pending_control_frames > 0,
+            "WebSocketConfig::max_pending_control_frames must be greater than zero"
+        );
     }
 }
 
@@ -176,6 +288,34 @@ impl<Stream> WebSocket<Stream> {
         self.context.shared_secret = Some(shared_secret)
     }
 
+    /// Attach the identity of the peer, replacing a previous one.
+    ///
+    /// Servers accepting a handshake with an
+    /// [`Authenticator`](crate::handshake::auth::Authenticator) attach the identity it returned.
+    pub fn set_identity<T: Any + Send + Sync>(&mut self, identity: T) {
+        self.context.set_identity(identity);
+    }
+
+    /// The identity of the peer, if one of type `T` is attached.
+    pub fn identity<T: Any>(&self) -> Option<&T> {
+        self.context.identity()
+    }
+
+    pub(crate) fn set_boxed_identity(&mut self, identity: Box<dyn Any + Send + Sync>) {
+        self.context.identity = Some(identity);
+    }
+
+    /// The subprotocol selected in the handshake, i.e. the `Sec-WebSocket-Protocol` header of
+    /// the response. `None` if no subprotocol was selected or the WebSocket was created without
+    /// a handshake of this crate.
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.context.subprotocol.as_deref()
+    }
+
+    pub(crate) fn set_subprotocol(&mut self, subprotocol: Option<String>) {
+        self.context.subprotocol = subprotocol;
+    }
+
     /// Convert a raw socket into a WebSocket without performing a handshake.
     ///
     /// Call this function if you're using Tungstenite as a part of a web framework
@@ -232,6 +372,21 @@ impl<Stream> WebSocket<Stream> {
     pub fn can_write(&self) -> bool {
         self.context.can_write()
     }
+
+    /// Get the control frames queued automatically and the pings not answered yet.
+    pub fn pending_control(&self) -> PendingControl {
+        self.context.pending_control()
+    }
+
+    /// The round-trip time of the most recently answered ping.
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.context.rtt().last()
+    }
+
+    /// The round-trip times of all answered pings.
+    pub fn rtt(&self) -> &RttHistogram {
+        self.context.rtt()
+    }
 }
 
 impl<Stream: Read + Write> WebSocket<Stream> {
@@ -278,10 +433,8 @@ impl<Stream: Read + Write> WebSocket<Stream> {
     /// will write & flush the pong reply. This means you should not respond to ping frames manually.
     ///
     /// You can however send pong frames manually in order to indicate a unidirectional heartbeat
-    /// as described in [RFC 6455](https://tools.ietf.org/html/rfc6455#section-5.5.3). Note that
-    /// if [`read`](Self::read) returns a ping, you should [`flush`](Self::flush) before passing
-    /// a custom pong to [`write`](Self::write), otherwise the automatic queued response to the
-    /// ping will not be sent as it will be replaced by your custom pong message.
+    /// as described in [RFC 6455](https://tools.ietf.org/html/rfc6455#section-5.5.3). Such pongs
+    /// are sent in addition to the automatic replies, see [`pending_control`](Self::pending_control).
     ///
     /// # Errors
     /// - If the WebSocket's write buffer is full, [`Error::WriteBufferFull`] will be returned
@@ -292,11 +445,28 @@ impl<Stream: Read + Write> WebSocket<Stream> {
     ///   error on your part.
     /// - [`Error::Io`] is returned if the underlying connection returns an error
     ///   (consider these fatal except for WouldBlock).
-    /// - [`Error::Capacity`] if your message size is bigger than the configured max message size.
+    /// - [`Error::OutgoingCapacity`] if your message is bigger than the configured
+    ///   [`WebSocketConfig::max_outgoing_message_size`].
     pub fn write(&mut self, message: Message) -> Result<()> {
         self.context.write(&mut self.socket, message)
     }
 
+    /// Write a message formatted once for many connections, see [`PreparedMessage`].
+    ///
+    /// Behaves like [`write`](Self::write), handing the message back with
+    /// [`Error::WriteBufferFull`] if the write buffer is full.
+    pub fn write_prepared(&mut self, message: &PreparedMessage) -> Result<()> {
+        self.context.write_prepared(&mut self.socket, message)
+    }
+
+    /// Writes and immediately flushes a prepared message.
+    /// Equivalent to calling [`write_prepared`](Self::write_prepared) then
+    /// [`flush`](Self::flush).
+    pub fn send_prepared(&mut self, message: &PreparedMessage) -> Result<()> {
+        self.write_prepared(message)?;
+        self.flush()
+    }
+
     /// Flush writes.
     ///
     /// Ensures all messages previously passed to [`write`](Self::write) and automatic
@@ -305,6 +475,26 @@ impl<Stream: Read + Write> WebSocket<Stream> {
         self.context.flush(&mut self.socket)
     }
 
+    /// Send a ping with a unique payload and flush it, returning its id.
+    ///
+    /// When the matching pong is read, its round-trip time is available from
+    /// [`last_rtt`](Self::last_rtt) and [`rtt`](Self::rtt). The payload is the id as 8 bytes
+    /// in big-endian order.
+    pub fn ping_with_id(&mut self) -> Result<u64> {
+        let id = self.context.next_ping_id();
+        self.send(Message::Ping(id.to_be_bytes().to_vec().into()))?;
+        Ok(id)
+    }
+
+    /// Start sending a message of the given kind in fragments.
+    ///
+    /// The returned [`MessageWriter`] implements [`Write`], splitting the data into frames of
+    /// [`WebSocketConfig::max_outgoing_frame_size`] bytes, 64 KiB without a limit. Until [`MessageWriter::finish`] is called no
+    /// other text or binary message may be written, while control messages still can be.
+    pub fn message_writer(&mut self, kind: MessageKind) -> MessageWriter<'_, Stream> {
+        MessageWriter::new(self, kind)
+    }
+
     /// Close the connection.
     ///
     /// This function guarantees that the close frame will be queued.
@@ -364,8 +554,20 @@ pub struct WebSocketContext {
     state: WebSocketState,
     /// Receive: an incomplete message being processed.
     incomplete: Option<IncompleteMessage>,
+    /// Receive: an incomplete message fragmented across layer8 envelopes.
+    layer8_incomplete: Option<IncompleteMessage>,
+    /// Receive: a message being handed out chunk by chunk in streaming mode.
+    streaming: Option<StreamingMessage>,
+    /// Send: true if a chunked message has been started but not finished yet.
+    sending_chunks: bool,
+    /// Send: the payload size of the chunks of the current chunked message sent so far.
+    sent_chunks_size: usize,
     /// Send in addition to regular messages E.g. "pong" or "close".
-    additional_send: Option<Frame>,
+    control: ControlQueue,
+    /// Round-trip times of answered pings.
+    rtt: RttHistogram,
+    /// The id of the next ping sent by [`WebSocket::ping_with_id`].
+    next_ping_id: u64,
     /// True indicates there is an additional message (like a pong)
     /// that failed to flush previously and we should try again.
     unflushed_additional: bool,
@@ -374,6 +576,10 @@ pub struct WebSocketContext {
     /// The shared secret for layer8 encryption. If provided, it implicitly
     /// assumes we're using custom encryption for the layer8 logic.
     shared_secret: Option<Jwk>,
+    /// The identity of the peer established during the handshake.
+    identity: Option<Box<dyn Any + Send + Sync>>,
+    /// The subprotocol selected during the handshake.
+    subprotocol: Option<String>,
 }
 
 impl WebSocketContext {
@@ -383,7 +589,7 @@ impl WebSocketContext {
     /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
     pub fn new(role: Role, config: Option<WebSocketConfig>) -> Self {
         let conf = config.unwrap_or_default();
-        Self::_new(role, FrameCodec::new(conf.read_buffer_size), conf)
+        Self::_new(role, FrameCodec::new(conf.in_buffer_len()), conf)
     }
 
     /// Set the shared secret for layer8 encryption.
@@ -398,25 +604,44 @@ impl WebSocketContext {
     /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
     pub fn from_partially_read(part: Vec<u8>, role: Role, config: Option<WebSocketConfig>) -> Self {
         let conf = config.unwrap_or_default();
-        Self::_new(role, FrameCodec::from_partially_read(part, conf.read_buffer_size), conf)
+        Self::_new(role, FrameCodec::from_partially_read(part, conf.in_buffer_len()), conf)
     }
 
     fn _new(role: Role, mut frame: FrameCodec, config: WebSocketConfig) -> Self {
         config.assert_valid();
         frame.set_max_out_buffer_len(config.max_write_buffer_size);
         frame.set_out_buffer_write_len(config.write_buffer_size);
+        frame.set_buffer_pool(config.buffer_pool.clone());
         Self {
             role,
             frame,
             state: WebSocketState::Active,
             incomplete: None,
-            additional_send: None,
+            layer8_incomplete: None,
+            streaming: None,
+            sending_chunks: false,
+            sent_chunks_size: 0,
+            control: ControlQueue::default(),
+            rtt: RttHistogram::default(),
+            next_ping_id: 0,
             unflushed_additional: false,
             config,
             shared_secret: None,
+            identity: None,
+            subprotocol: None,
         }
     }
 
+    /// Attach the identity of the peer, replacing a previous one.
+    pub fn set_identity<T: Any + Send + Sync>(&mut self, identity: T) {
+        self.identity = Some(Box::new(identity));
+    }
+
+    /// The identity of the peer, if one of type `T` is attached.
+    pub fn identity<T: Any>(&self) -> Option<&T> {
+        self.identity.as_deref()?.downcast_ref()
+    }
+
     /// Change the configuration.
     ///
     /// # Panics
@@ -426,6 +651,7 @@ impl WebSocketContext {
         self.config.assert_valid();
         self.frame.set_max_out_buffer_len(self.config.max_write_buffer_size);
         self.frame.set_out_buffer_write_len(self.config.write_buffer_size);
+        self.frame.set_buffer_pool(self.config.buffer_pool.clone());
     }
 
     /// Read the configuration.
@@ -448,11 +674,28 @@ impl WebSocketContext {
         self.state.is_active()
     }
 
+    /// Get the control frames queued automatically and the pings not answered yet.
+    pub fn pending_control(&self) -> PendingControl {
+        self.control.pending()
+    }
+
+    /// The round-trip times of all answered pings.
+    pub fn rtt(&self) -> &RttHistogram {
+        &self.rtt
+    }
+
     /// Read a message from the provided stream, if possible.
     ///
     /// This function sends pong and close responses automatically.
     /// However, it never blocks on write.
     pub fn read<Stream>(&mut self, stream: &mut Stream) -> Result<Message>
+    where
+        Stream: Read + Write,
+    {
+        self._read(stream).map_err(|err| self.fail_connection(stream, err))
+    }
+
+    fn _read<Stream>(&mut self, stream: &mut Stream) -> Result<Message>
     where
         Stream: Read + Write,
     {
@@ -460,7 +703,7 @@ impl WebSocketContext {
         self.state.check_not_terminated()?;
 
         loop {
-            if self.additional_send.is_some() || self.unflushed_additional {
+            if !self.control.is_empty() || self.unflushed_additional {
                 // Since we may get ping or close, we need to reply to the messages even during read.
                 match self.flush(stream) {
                     Ok(_) => {}
@@ -477,7 +720,9 @@ impl WebSocketContext {
 
             // If we get here, either write blocks or we have nothing to write.
             // Thus if read blocks, just let it return WouldBlock.
-            if let Some(message) = self.read_message_frame(stream)? {
+            // Layer8 envelopes are always assembled, only their contents may be streamed.
+            let streaming = self.config.streaming_read && self.shared_secret.is_none();
+            if let Some(message) = self.read_message_frame(stream, streaming)? {
                 trace!("Received message {message}");
 
                 // we bumped into an encrypted payload, let's unwrap it
@@ -506,10 +751,10 @@ impl WebSocketContext {
                                 )
                             })?;
 
-                        println!("data decrypted: {:?}", String::from_utf8_lossy(&data_decrypted));
-                        match self.read_message_frame(&mut std::io::Cursor::new(data_decrypted))? {
+                        match self.read_layer8_frame(data_decrypted)? {
                             Some(message) => return Ok(message),
-                            None => unimplemented!("Report Bug to Layer8 team!"),
+                            // A fragment of a bigger message.
+                            None => continue,
                         }
                     }
 
@@ -534,64 +779,153 @@ impl WebSocketContext {
     ///
     /// If the write buffer would exceed the configured [`WebSocketConfig::max_write_buffer_size`]
     /// [`Err(WriteBufferFull(msg_frame))`](Error::WriteBufferFull) is returned.
+    ///
+    /// If the message exceeds the configured [`WebSocketConfig::max_outgoing_message_size`]
+    /// [`Err(OutgoingCapacity(msg, err))`](Error::OutgoingCapacity) is returned.
     pub fn write<Stream>(&mut self, stream: &mut Stream, message: Message) -> Result<()>
     where
         Stream: Read + Write,
     {
-        // When terminated, return AlreadyClosed.
-        self.state.check_not_terminated()?;
-
-        // Do not write after sending a close frame.
-        if !self.state.is_active() {
-            return Err(Error::Protocol(ProtocolError::SendAfterClosing));
-        }
+        // Check the size of the message itself, the encrypted envelope is checked below.
+        let size = match &message {
+            Message::Chunk(chunk) => self.sent_chunks_size + chunk.data.len(),
+            message => message.len(),
+        };
+        self.check_writable(&message, size)?;
+        let mut sending_chunks = self.sending_chunks;
+        let sent_chunks_size = match &message {
+            Message::Chunk(chunk) if !chunk.is_final => size,
+            Message::Chunk(_) => 0,
+            _ => self.sent_chunks_size,
+        };
+        // Kept to hand the message back if it cannot be sent once encrypted or split into
+        // fragments. Other frames are handed back by the codec, so they are not cloned.
+        let fragmenting =
+            matches!(self.config.max_outgoing_frame_size, Some(max) if message.len() > max);
+        let original = (self.shared_secret.is_some() || fragmenting).then(|| message.clone());
+
+        let ping = match &message {
+            Message::Ping(data) => Some(data.clone()),
+            _ => None,
+        };
 
-        let mut frame = match message {
+        let frame = match message {
             Message::Text(data) => Frame::message(data, OpCode::Data(OpData::Text), true),
             Message::Binary(data) => Frame::message(data, OpCode::Data(OpData::Binary), true),
             Message::Ping(data) => Frame::ping(data),
-            Message::Pong(data) => {
-                // experimental changes
-                let pong = Frame::pong(data);
-                self.set_additional(pong.clone());
-                pong
-                // Note: user pongs can be user flushed so no need to flush here
-                // return self._write(stream, None).map(|_| ());
-            }
+            Message::Pong(data) => Frame::pong(data),
             Message::Close(code) => {
                 // experimental changes
                 Frame::close(code)
                 // return self.close(stream, code)
             }
             Message::Frame(f) => f,
+            Message::Chunk(chunk) => {
+                sending_chunks = !chunk.is_final;
+                chunk.into_frame(self.sending_chunks)
+            }
         };
 
-        if let Some(shared_secret) = &self.shared_secret {
-            frame = {
-                let mut business_payload = Vec::new();
-                frame.format_into_buf(&mut business_payload)?;
-
-                let payload = RoundtripEnvelope::encode(
-                    &shared_secret.symmetric_encrypt(&business_payload).map_err(|e| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("Failed to encrypt data: {}", e),
-                        )
-                    })?,
-                )
-                .to_json_bytes();
-
-                Layer8Frame::message(payload, OpCode::Data(OpData::Binary), true)
-            };
+        let frame = self.encrypt_frame(frame)?;
+        if self.shared_secret.is_some() {
+            let size = frame.payload().len();
+            if let Err(err) = check_outgoing_size(size, self.config.max_outgoing_message_size) {
+                let original = original.unwrap_or_else(|| Message::Frame(frame));
+                return Err(Error::OutgoingCapacity(original, err));
+            }
         }
 
-        let should_flush = self._write(stream, Some(frame))?;
+        let result = match self.config.max_outgoing_frame_size {
+            Some(max_size) if frame.payload().len() > max_size => {
+                // The size of close and raw frames is only known once they are formatted.
+                let original = original.unwrap_or_else(|| Message::Frame(frame.clone()));
+                if let OpCode::Control(_) = frame.header().opcode {
+                    let err =
+                        CapacityError::MessageTooLong { size: frame.payload().len(), max_size };
+                    return Err(Error::OutgoingCapacity(original, err));
+                }
+                self.buffer_fragments(stream, frame, max_size, original)
+                    .and_then(|()| self._write(stream, None))
+            }
+            _ => self._write(stream, Some(frame)),
+        };
+        let should_flush = match result {
+            // The frame was not queued, so it has to be sent again.
+            Err(err @ Error::WriteBufferFull(_)) => return Err(err),
+            result => {
+                self.sending_chunks = sending_chunks;
+                self.sent_chunks_size = sent_chunks_size;
+                if let Some(ping) = ping {
+                    self.control.ping_sent(ping, self.config.max_pending_control_frames);
+                }
+                result?
+            }
+        };
         if should_flush {
             self.flush(stream)?;
         }
         Ok(())
     }
 
+    /// Write a message formatted once for many connections, see [`PreparedMessage`].
+    ///
+    /// Falls back to [`write`](Self::write) where the prepared frame cannot be used: clients
+    /// mask every frame, layer8 encrypts per connection and frames bigger than
+    /// [`WebSocketConfig::max_outgoing_frame_size`] are fragmented.
+    pub fn write_prepared<Stream>(
+        &mut self,
+        stream: &mut Stream,
+        prepared: &PreparedMessage,
+    ) -> Result<()>
+    where
+        Stream: Read + Write,
+    {
+        let message = prepared.message();
+        let usable = self.role == Role::Server
+            && self.shared_secret.is_none()
+            && !matches!(self.config.max_outgoing_frame_size, Some(max) if message.len() > max);
+        if !usable {
+            return self.write(stream, message.clone());
+        }
+
+        self.check_writable(message, message.len())?;
+        if !self.frame.has_room(prepared.len()) {
+            return Err(Error::WriteBufferFull(message.clone()));
+        }
+
+        self.frame
+            .buffer_encoded(stream, prepared.frame().clone())
+            .check_connection_reset(self.state)?;
+        if self._write(stream, None)? {
+            self.flush(stream)?;
+        }
+        Ok(())
+    }
+
+    /// Check that `message` of `size` bytes may be written now.
+    ///
+    /// Fails if the connection is closing or closed, if a data message would interrupt a
+    /// fragmented one, or if `size` exceeds [`WebSocketConfig::max_outgoing_message_size`].
+    fn check_writable(&self, message: &Message, size: usize) -> Result<()> {
+        // When terminated, return AlreadyClosed.
+        self.state.check_not_terminated()?;
+
+        // Do not write after sending a close frame.
+        if !self.state.is_active() {
+            return Err(Error::Protocol(ProtocolError::SendAfterClosing));
+        }
+
+        // Data messages cannot be sent in the middle of a fragmented one.
+        if self.sending_chunks && matches!(message, Message::Text(_) | Message::Binary(_)) {
+            return Err(Error::Protocol(ProtocolError::FragmentedMessageInProgress));
+        }
+
+        if let Err(err) = check_outgoing_size(size, self.config.max_outgoing_message_size) {
+            return Err(Error::OutgoingCapacity(message.clone(), err));
+        }
+        Ok(())
+    }
+
     /// Flush writes.
     ///
     /// Ensures all messages previously passed to [`write`](Self::write) and automatically
@@ -608,7 +942,7 @@ impl WebSocketContext {
         Ok(())
     }
 
-    /// Writes any data in the out_buffer, `additional_send` and given `data`.
+    /// Writes any data in the out_buffer, the queued control frames and given `data`.
     ///
     /// Does **not** flush.
     ///
@@ -624,22 +958,24 @@ impl WebSocketContext {
         // Upon receipt of a Ping frame, an endpoint MUST send a Pong frame in
         // response, unless it already received a Close frame. It SHOULD
         // respond with Pong frame as soon as is practical. (RFC 6455)
-        let should_flush = if let Some(msg) = self.additional_send.take() {
+        let mut should_flush = self.unflushed_additional;
+        while let Some(msg) = self.control.peek() {
             trace!("Sending pong/close");
-            match self.buffer_frame(stream, msg) {
-                Err(Error::WriteBufferFull(Message::Frame(msg))) => {
-                    // if an system message would exceed the buffer put it back in
-                    // `additional_send` for retry. Otherwise returning this error
-                    // may not make sense to the user, e.g. calling `flush`.
-                    self.set_additional(msg);
-                    false
+            // The frame stays queued if it cannot be encrypted or buffered.
+            let frame = self.encrypt_frame(msg)?;
+            match self.buffer_frame(stream, frame) {
+                // if an system message would exceed the buffer leave it in
+                // the queue for retry. Otherwise returning this error
+                // may not make sense to the user, e.g. calling `flush`.
+                Err(Error::WriteBufferFull(_)) => break,
+                result => {
+                    // Any other error happened writing the buffer the frame was added to.
+                    self.control.pop();
+                    result?;
+                    should_flush = true;
                 }
-                Err(err) => return Err(err),
-                Ok(_) => true,
             }
-        } else {
-            self.unflushed_additional
-        };
+        }
 
         // If we're closing and there is nothing to send anymore, we should close the connection.
         if self.role == Role::Server && !self.state.can_read() {
@@ -674,8 +1010,48 @@ impl WebSocketContext {
         self.flush(stream)
     }
 
+    /// Fail the connection because of a protocol violation of the peer by sending a close
+    /// frame, if enabled by [`WebSocketConfig::close_on_error`]. Returns the error.
+    fn fail_connection<Stream>(&mut self, stream: &mut Stream, err: Error) -> Error
+    where
+        Stream: Read + Write,
+    {
+        if !self.config.close_on_error || !self.state.is_active() {
+            return err;
+        }
+        let code = match err {
+            Error::Protocol(_) => CloseCode::Protocol,
+            Error::Utf8 => CloseCode::Invalid,
+            Error::Capacity(_) => CloseCode::Size,
+            Error::AttackAttempt => CloseCode::Policy,
+            _ => return err,
+        };
+
+        // The reason of a close frame is limited to 123 bytes.
+        let mut reason = err.to_string();
+        let mut len = reason.len().min(123);
+        while !reason.is_char_boundary(len) {
+            len -= 1;
+        }
+        reason.truncate(len);
+
+        debug!("Failing the connection with {code}: {reason}");
+        self.state = WebSocketState::ClosedByUs;
+        self.control.set_close(Frame::close(Some(CloseFrame { code, reason: reason.into() })));
+        if let Err(flush_err) = self.flush(stream) {
+            debug!("Failed to send close frame: {flush_err}");
+        }
+        err
+    }
+
     /// Try to decode one message frame. May return None.
-    fn read_message_frame(&mut self, stream: &mut impl Read) -> Result<Option<Message>> {
+    ///
+    /// If `streaming` is set, data frames are returned as [`Message::Chunk`].
+    fn read_message_frame(
+        &mut self,
+        stream: &mut impl Read,
+        streaming: bool,
+    ) -> Result<Option<Message>> {
         if let Some(frame) = self
             .frame
             .read_frame(
@@ -686,108 +1062,171 @@ impl WebSocketContext {
             )
             .check_connection_reset(self.state)?
         {
-            if !self.state.can_read() {
-                return Err(Error::Protocol(ProtocolError::ReceivedAfterClosing));
+            if self.role == Role::Client && frame.is_masked() {
+                // A client MUST close a connection if it detects a masked frame. (RFC 6455)
+                return Err(Error::Protocol(ProtocolError::MaskedFrameFromServer));
             }
-            // MUST be 0 unless an extension is negotiated that defines meanings
-            // for non-zero values.  If a nonzero value is received and none of
-            // the negotiated extensions defines the meaning of such a nonzero
-            // value, the receiving endpoint MUST _Fail the WebSocket
-            // Connection_.
-            {
-                let hdr = frame.header();
-                if hdr.rsv1 || hdr.rsv2 || hdr.rsv3 {
-                    return Err(Error::Protocol(ProtocolError::NonZeroReservedBits));
+
+            self.handle_frame(frame, streaming)
+        } else {
+            // Connection closed by peer
+            match replace(&mut self.state, WebSocketState::Terminated) {
+                WebSocketState::ClosedByPeer | WebSocketState::CloseAcknowledged => {
+                    Err(Error::ConnectionClosed)
                 }
+                _ => Err(Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)),
             }
+        }
+    }
 
-            if self.role == Role::Client && frame.is_masked() {
-                // A client MUST close a connection if it detects a masked frame. (RFC 6455)
-                return Err(Error::Protocol(ProtocolError::MaskedFrameFromServer));
+    /// Decode the frame taken out of a decrypted layer8 envelope.
+    ///
+    /// The frame is parsed apart from the stream's codec, so buffered stream data is left
+    /// alone. Its masking is not checked since only the envelope itself is masked. An envelope
+    /// holds exactly one frame, trailing data is a protocol violation.
+    fn read_layer8_frame(&mut self, data: Vec<u8>) -> Result<Option<Message>> {
+        let mut codec = FrameCodec::from_partially_read(data, 0);
+        match codec.read_frame(&mut io::empty(), self.config.max_frame_size, true, true)? {
+            Some(_) if codec.has_buffered_input() => {
+                Err(Error::Protocol(ProtocolError::TrailingEnvelopeData))
+            }
+            Some(frame) => {
+                // Fragments inside envelopes are assembled apart from the envelopes.
+                swap(&mut self.incomplete, &mut self.layer8_incomplete);
+                let message = self.handle_frame(frame, self.config.streaming_read);
+                swap(&mut self.incomplete, &mut self.layer8_incomplete);
+                message
             }
+            None => Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Incomplete frame in layer8 envelope",
+            ))),
+        }
+    }
 
-            match frame.header().opcode {
-                OpCode::Control(ctl) => {
-                    match ctl {
-                        // All control frames MUST have a payload length of 125 bytes or less
-                        // and MUST NOT be fragmented. (RFC 6455)
-                        _ if !frame.header().is_final => {
-                            Err(Error::Protocol(ProtocolError::FragmentedControlFrame))
-                        }
-                        _ if frame.payload().len() > 125 => {
-                            Err(Error::Protocol(ProtocolError::ControlFrameTooBig))
-                        }
-                        OpCtl::Close => Ok(self.do_close(frame.into_close()?).map(Message::Close)),
-                        OpCtl::Reserved(i) => {
-                            Err(Error::Protocol(ProtocolError::UnknownControlFrameType(i)))
+    /// Process a received frame, returns a message if one is complete.
+    ///
+    /// If `streaming` is set, data frames are returned as [`Message::Chunk`].
+    fn handle_frame(&mut self, frame: Frame, streaming: bool) -> Result<Option<Message>> {
+        if !self.state.can_read() {
+            return Err(Error::Protocol(ProtocolError::ReceivedAfterClosing));
+        }
+        // MUST be 0 unless an extension is negotiated that defines meanings
+        // for non-zero values.  If a nonzero value is received and none of
+        // the negotiated extensions defines the meaning of such a nonzero
+        // value, the receiving endpoint MUST _Fail the WebSocket
+        // Connection_.
+        {
+            let hdr = frame.header();
+            if hdr.rsv1 || hdr.rsv2 || hdr.rsv3 {
+                return Err(Error::Protocol(ProtocolError::NonZeroReservedBits));
+            }
+        }
+
+        match frame.header().opcode {
+            OpCode::Control(ctl) => {
+                match ctl {
+                    // All control frames MUST have a payload length of 125 bytes or less
+                    // and MUST NOT be fragmented. (RFC 6455)
+                    _ if !frame.header().is_final => {
+                        Err(Error::Protocol(ProtocolError::FragmentedControlFrame))
+                    }
+                    _ if frame.payload().len() > 125 => {
+                        Err(Error::Protocol(ProtocolError::ControlFrameTooBig))
+                    }
+                    OpCtl::Close => Ok(self.do_close(frame.into_close()?).map(Message::Close)),
+                    OpCtl::Reserved(i) => {
+                        Err(Error::Protocol(ProtocolError::UnknownControlFrameType(i)))
+                    }
+                    OpCtl::Ping => {
+                        let data = frame.into_payload();
+                        // No ping processing after we sent a close frame.
+                        if self.state.is_active() {
+                            let limit = self.config.max_pending_control_frames;
+                            self.control.push_pong(data.clone(), limit);
                         }
-                        OpCtl::Ping => {
-                            let data = frame.into_payload();
-                            // No ping processing after we sent a close frame.
-                            if self.state.is_active() {
-                                self.set_additional(Frame::pong(data.clone()));
-                            }
-                            Ok(Some(Message::Ping(data)))
+                        Ok(Some(Message::Ping(data)))
+                    }
+                    OpCtl::Pong => {
+                        let data = frame.into_payload();
+                        if let Some(rtt) = self.control.pong_received(&data) {
+                            self.rtt.record(rtt);
                         }
-                        OpCtl::Pong => Ok(Some(Message::Pong(frame.into_payload()))),
+                        Ok(Some(Message::Pong(data)))
                     }
                 }
+            }
 
-                OpCode::Data(data) => {
-                    let fin = frame.header().is_final;
-                    match data {
-                        OpData::Continue => {
-                            if let Some(ref mut msg) = self.incomplete {
-                                msg.extend(frame.into_payload(), self.config.max_message_size)?;
-                            } else {
-                                return Err(Error::Protocol(
-                                    ProtocolError::UnexpectedContinueFrame,
-                                ));
-                            }
-                            if fin {
-                                Ok(Some(self.incomplete.take().unwrap().complete()?))
-                            } else {
-                                Ok(None)
-                            }
-                        }
-                        c if self.incomplete.is_some() => {
-                            Err(Error::Protocol(ProtocolError::ExpectedFragment(c)))
-                        }
-                        OpData::Text if fin => {
-                            check_max_size(frame.payload().len(), self.config.max_message_size)?;
-                            Ok(Some(Message::Text(frame.into_text()?)))
-                        }
-                        OpData::Binary if fin => {
-                            check_max_size(frame.payload().len(), self.config.max_message_size)?;
-                            Ok(Some(Message::Binary(frame.into_payload())))
+            OpCode::Data(data) if streaming => self.stream_data_frame(data, frame),
+
+            OpCode::Data(data) => {
+                let fin = frame.header().is_final;
+                match data {
+                    OpData::Continue => {
+                        if let Some(ref mut msg) = self.incomplete {
+                            msg.extend(frame.into_payload(), self.config.max_message_size)?;
+                        } else {
+                            return Err(Error::Protocol(ProtocolError::UnexpectedContinueFrame));
                         }
-                        OpData::Text | OpData::Binary => {
-                            let message_type = match data {
-                                OpData::Text => IncompleteMessageType::Text,
-                                OpData::Binary => IncompleteMessageType::Binary,
-                                _ => panic!("Bug: message is not text nor binary"),
-                            };
-                            let mut incomplete = IncompleteMessage::new(message_type);
-                            incomplete
-                                .extend(frame.into_payload(), self.config.max_message_size)?;
-                            self.incomplete = Some(incomplete);
+                        if fin {
+                            Ok(Some(self.incomplete.take().unwrap().complete()?))
+                        } else {
                             Ok(None)
                         }
-                        OpData::Reserved(i) => {
-                            Err(Error::Protocol(ProtocolError::UnknownDataFrameType(i)))
-                        }
+                    }
+                    c if self.incomplete.is_some() => {
+                        Err(Error::Protocol(ProtocolError::ExpectedFragment(c)))
+                    }
+                    OpData::Text if fin => {
+                        check_max_size(frame.payload().len(), self.config.max_message_size)?;
+                        Ok(Some(Message::Text(frame.into_text()?)))
+                    }
+                    OpData::Binary if fin => {
+                        check_max_size(frame.payload().len(), self.config.max_message_size)?;
+                        Ok(Some(Message::Binary(frame.into_payload())))
+                    }
+                    OpData::Text | OpData::Binary => {
+                        let message_type = match data {
+                            OpData::Text => IncompleteMessageType::Text,
+                            OpData::Binary => IncompleteMessageType::Binary,
+                            _ => panic!("Bug: message is not text nor binary"),
+                        };
+                        let mut incomplete = IncompleteMessage::new(message_type);
+                        incomplete.extend(frame.into_payload(), self.config.max_message_size)?;
+                        self.incomplete = Some(incomplete);
+                        Ok(None)
+                    }
+                    OpData::Reserved(i) => {
+                        Err(Error::Protocol(ProtocolError::UnknownDataFrameType(i)))
                     }
                 }
-            } // match opcode
-        } else {
-            // Connection closed by peer
-            match replace(&mut self.state, WebSocketState::Terminated) {
-                WebSocketState::ClosedByPeer | WebSocketState::CloseAcknowledged => {
-                    Err(Error::ConnectionClosed)
-                }
-                _ => Err(Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)),
             }
+        } // match opcode
+    }
+
+    /// Hand out a data frame as a [`MessageChunk`] without assembling the message.
+    fn stream_data_frame(&mut self, data: OpData, frame: Frame) -> Result<Option<Message>> {
+        let fin = frame.header().is_final;
+        let mut message = match data {
+            OpData::Continue => self
+                .streaming
+                .take()
+                .ok_or(Error::Protocol(ProtocolError::UnexpectedContinueFrame))?,
+            c if self.streaming.is_some() => {
+                return Err(Error::Protocol(ProtocolError::ExpectedFragment(c)))
+            }
+            OpData::Text => StreamingMessage::new(MessageKind::Text),
+            OpData::Binary => StreamingMessage::new(MessageKind::Binary),
+            OpData::Reserved(i) => {
+                return Err(Error::Protocol(ProtocolError::UnknownDataFrameType(i)))
+            }
+        };
+
+        let chunk = message.chunk(frame.into_payload(), fin, self.config.max_message_size)?;
+        if !fin {
+            self.streaming = Some(message);
         }
+        Ok(Some(Message::Chunk(chunk)))
     }
 
     /// Received a close frame. Tells if we need to return a close frame to the user.
@@ -811,7 +1250,7 @@ impl WebSocketContext {
 
                 let reply = Frame::close(close.clone());
                 debug!("Replying to close with {reply:?}");
-                self.set_additional(reply);
+                self.control.set_close(reply);
 
                 Some(close)
             }
@@ -846,15 +1285,76 @@ impl WebSocketContext {
         self.frame.buffer_frame(stream, frame).check_connection_reset(self.state)
     }
 
-    /// Replace `additional_send` if it is currently a `Pong` message.
-    fn set_additional(&mut self, add: Frame) {
-        let empty_or_pong = self
-            .additional_send
-            .as_ref()
-            .map_or(true, |f| f.header().opcode == OpCode::Control(OpCtl::Pong));
-        if empty_or_pong {
-            self.additional_send.replace(add);
+    /// Split a data frame into fragments of at most `max_size` bytes and write all of them
+    /// into the write-buffer, or none of them if they do not fit.
+    fn buffer_fragments<Stream>(
+        &mut self,
+        stream: &mut Stream,
+        frame: Frame,
+        max_size: usize,
+        message: Message,
+    ) -> Result<()>
+    where
+        Stream: Read + Write,
+    {
+        let mut header = frame.header().clone();
+        let is_final = header.is_final;
+        let payload = frame.into_payload();
+
+        let mut fragments = Vec::with_capacity(payload.len().div_ceil(max_size));
+        let mut offset = 0;
+        while offset < payload.len() {
+            let end = payload.len().min(offset + max_size);
+            header.is_final = is_final && end == payload.len();
+            let mut fragment = Frame::from_payload(header.clone(), payload.slice(offset..end));
+            if self.role == Role::Client {
+                fragment.set_random_mask();
+            }
+            fragments.push(fragment);
+            header =
+                FrameHeader { opcode: OpCode::Data(OpData::Continue), ..FrameHeader::default() };
+            offset = end;
+        }
+
+        trace!("Sending {} fragments of {} bytes", fragments.len(), payload.len());
+        let len = fragments.iter().map(Frame::len).sum();
+        if !self.frame.has_room(len) {
+            // Make room by writing out the queued frames first.
+            self.frame.write_out_buffer(stream).check_connection_reset(self.state)?;
+            if !self.frame.has_room(len) {
+                return Err(Error::WriteBufferFull(message));
+            }
         }
+        self.frame.buffer_frames(stream, fragments).check_connection_reset(self.state)
+    }
+
+    /// Allocate a ping id.
+    fn next_ping_id(&mut self) -> u64 {
+        let id = self.next_ping_id;
+        self.next_ping_id = id.wrapping_add(1);
+        id
+    }
+
+    /// Wrap a frame into an encrypted layer8 envelope if a shared secret is set.
+    fn encrypt_frame(&self, frame: Frame) -> Result<Frame> {
+        let Some(shared_secret) = &self.shared_secret else {
+            return Ok(frame);
+        };
+
+        let mut business_payload = Vec::new();
+        frame.format_into_buf(&mut business_payload)?;
+
+        let payload = RoundtripEnvelope::encode(
+            &shared_secret.symmetric_encrypt(&business_payload).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Failed to encrypt data: {}", e),
+                )
+            })?,
+        )
+        .to_json_bytes();
+
+        Ok(Layer8Frame::message(payload, OpCode::Data(OpData::Binary), true))
     }
 }
 
@@ -924,38 +1424,43 @@ impl<T> CheckConnectionReset for Result<T> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{Message, Role, WebSocket, WebSocketConfig};
-    use crate::error::{CapacityError, Error};
-
-    use std::{io, io::Cursor};
-
-    struct WriteMoc<Stream>(Stream);
-
-    impl<Stream> io::Write for WriteMoc<Stream> {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            Ok(buf.len())
-        }
-        fn flush(&mut self) -> io::Result<()> {
-            Ok(())
-        }
+/// Check the size of an outgoing message against the configured limit.
+fn check_outgoing_size(size: usize, max_size: Option<usize>) -> Result<(), CapacityError> {
+    match max_size {
+        Some(max_size) if size > max_size => Err(CapacityError::MessageTooLong { size, max_size }),
+        _ => Ok(()),
     }
+}
 
-    impl<Stream: io::Read> io::Read for WriteMoc<Stream> {
-        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-            self.0.read(buf)
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use super::{
+        frame::{
+            coding::{CloseCode, Data as OpData, OpCode},
+            Frame,
+        },
+        Message, MessageChunk, MessageKind, PendingControl, PreparedMessage, Role, WebSocket,
+        WebSocketConfig,
+    };
+    use crate::{
+        error::{CapacityError, Error, ProtocolError},
+        testing::{duplex, DuplexStream},
+    };
+
+    use layer8_primitives::{
+        crypto::{generate_key_pair, Jwk, KeyUse},
+        types::RoundtripEnvelope,
+    };
+    use std::io::{Cursor, Write};
 
     #[test]
     fn receive_messages() {
-        let incoming = Cursor::new(vec![
+        let incoming = [
             0x89, 0x02, 0x01, 0x02, 0x8a, 0x01, 0x03, 0x01, 0x07, 0x48, 0x65, 0x6c, 0x6c, 0x6f,
             0x2c, 0x20, 0x80, 0x06, 0x57, 0x6f, 0x72, 0x6c, 0x64, 0x21, 0x82, 0x03, 0x01, 0x02,
             0x03,
-        ]);
-        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+        ];
+        let (mut socket, _peer) = with_input(&incoming, Role::Client, None);
         assert_eq!(socket.read().unwrap(), Message::Ping(vec![1, 2].into()));
         assert_eq!(socket.read().unwrap(), Message::Pong(vec![3].into()));
         assert_eq!(socket.read().unwrap(), Message::Text("Hello, World!".into()));
@@ -964,12 +1469,12 @@ mod tests {
 
     #[test]
     fn size_limiting_text_fragmented() {
-        let incoming = Cursor::new(vec![
+        let incoming = [
             0x01, 0x07, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x80, 0x06, 0x57, 0x6f, 0x72,
             0x6c, 0x64, 0x21,
-        ]);
+        ];
         let limit = WebSocketConfig { max_message_size: Some(10), ..WebSocketConfig::default() };
-        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, Some(limit));
+        let (mut socket, _peer) = with_input(&incoming, Role::Client, Some(limit));
 
         assert!(matches!(
             socket.read(),
@@ -977,15 +1482,250 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn streaming_read_fragmented() {
+        let incoming = [
+            0x01, 0x07, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x80, 0x06, 0x57, 0x6f, 0x72,
+            0x6c, 0x64, 0x21, 0x82, 0x03, 0x01, 0x02, 0x03,
+        ];
+        let config = WebSocketConfig::default().streaming_read(true);
+        let (mut socket, _peer) = with_input(&incoming, Role::Client, Some(config));
+
+        let chunk = |kind, data: &'static [u8], is_final| {
+            Message::Chunk(MessageChunk { kind, data: data.into(), is_final })
+        };
+        assert_eq!(socket.read().unwrap(), chunk(MessageKind::Text, b"Hello, ", false));
+        assert_eq!(socket.read().unwrap(), chunk(MessageKind::Text, b"World!", true));
+        assert_eq!(socket.read().unwrap(), chunk(MessageKind::Binary, &[0x01, 0x02, 0x03], true));
+    }
+
+    #[test]
+    fn streaming_read_size_limiting() {
+        let incoming = [
+            0x01, 0x07, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x80, 0x06, 0x57, 0x6f, 0x72,
+            0x6c, 0x64, 0x21,
+        ];
+        let limit = WebSocketConfig::default().max_message_size(Some(10)).streaming_read(true);
+        let (mut socket, _peer) = with_input(&incoming, Role::Client, Some(limit));
+
+        assert!(socket.read().unwrap().is_chunk());
+        assert!(matches!(
+            socket.read(),
+            Err(Error::Capacity(CapacityError::MessageTooLong { size: 13, max_size: 10 }))
+        ));
+    }
+
     #[test]
     fn size_limiting_binary() {
-        let incoming = Cursor::new(vec![0x82, 0x03, 0x01, 0x02, 0x03]);
+        let incoming = [0x82, 0x03, 0x01, 0x02, 0x03];
         let limit = WebSocketConfig { max_message_size: Some(2), ..WebSocketConfig::default() };
-        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, Some(limit));
+        let (mut socket, _peer) = with_input(&incoming, Role::Client, Some(limit));
 
         assert!(matches!(
             socket.read(),
             Err(Error::Capacity(CapacityError::MessageTooLong { size: 3, max_size: 2 }))
         ));
     }
+
+    #[test]
+    fn outgoing_size_limiting() {
+        let limit = WebSocketConfig::default().max_outgoing_message_size(Some(4));
+        let mut socket =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, Some(limit));
+
+        match socket.write(Message::Binary(vec![1, 2, 3, 4, 5].into())) {
+            Err(Error::OutgoingCapacity(
+                msg,
+                CapacityError::MessageTooLong { size: 5, max_size: 4 },
+            )) => {
+                assert_eq!(msg, Message::Binary(vec![1, 2, 3, 4, 5].into()));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        let chunk = |data: &'static [u8], is_final| {
+            Message::Chunk(MessageChunk { kind: MessageKind::Text, data: data.into(), is_final })
+        };
+        socket.write(chunk(b"abc", false)).unwrap();
+        assert!(matches!(
+            socket.write(chunk(b"de", true)),
+            Err(Error::OutgoingCapacity(_, CapacityError::MessageTooLong { size: 5, max_size: 4 }))
+        ));
+        socket.write(chunk(b"d", true)).unwrap();
+        socket.send(Message::Text("next".into())).unwrap();
+        assert!(socket.get_ref().get_ref().ends_with(b"\x81\x04next"));
+    }
+
+    #[test]
+    fn prepared_checks_like_write() {
+        let prepared = PreparedMessage::text("Hello");
+        let mut socket = WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, None);
+        let chunk = Message::Chunk(MessageChunk {
+            kind: MessageKind::Text,
+            data: b"abc"[..].into(),
+            is_final: false,
+        });
+        socket.write(chunk).unwrap();
+        assert!(matches!(
+            socket.write_prepared(&prepared),
+            Err(Error::Protocol(ProtocolError::FragmentedMessageInProgress))
+        ));
+
+        let mut socket = WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, None);
+        socket.send_prepared(&prepared).unwrap();
+        socket.close(None).unwrap();
+        assert!(matches!(
+            socket.send_prepared(&prepared),
+            Err(Error::Protocol(ProtocolError::SendAfterClosing))
+        ));
+        assert!(socket.get_ref().get_ref().starts_with(prepared.frame()));
+    }
+
+    #[test]
+    fn outgoing_frame_size_fragmenting() {
+        let limit = WebSocketConfig::default().max_outgoing_frame_size(Some(3));
+        let mut socket =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, Some(limit));
+
+        socket.send(Message::Text("Hello!!".into())).unwrap();
+        socket.send(Message::Binary(vec![1, 2, 3].into())).unwrap();
+        assert!(matches!(
+            socket.send(Message::Ping(b"ping"[..].into())),
+            Err(Error::OutgoingCapacity(Message::Ping(_), _))
+        ));
+        assert_eq!(
+            socket.get_ref().get_ref(),
+            &[
+                0x01, 0x03, b'H', b'e', b'l', 0x00, 0x03, b'l', b'o', b'!', 0x80, 0x01, b'!', 0x82,
+                0x03, 0x01, 0x02, 0x03,
+            ]
+        );
+    }
+
+    /// A socket reading `input`, the returned end of the connection receives what it writes.
+    fn with_input(
+        input: &[u8],
+        role: Role,
+        config: Option<WebSocketConfig>,
+    ) -> (WebSocket<DuplexStream>, DuplexStream) {
+        let (stream, mut peer) = duplex();
+        peer.write_all(input).unwrap();
+        (WebSocket::from_raw_socket(stream, role, config), peer)
+    }
+
+    #[test]
+    fn pong_every_ping() {
+        let (mut socket, mut peer) = with_input(&[], Role::Server, None);
+
+        // Several pings arrive before the replies are written.
+        for ping in [[1], [2], [1]] {
+            let message = socket.context.handle_frame(Frame::ping(ping.to_vec()), false).unwrap();
+            assert!(message.unwrap().is_ping());
+        }
+        assert_eq!(socket.pending_control(), PendingControl { pongs: 2, ..<_>::default() });
+
+        // A user pong does not replace the automatic replies.
+        socket.send(Message::Pong(vec![9].into())).unwrap();
+        assert_eq!(socket.pending_control(), PendingControl::default());
+        assert_eq!(peer.take_unread(), [0x8a, 0x01, 0x09, 0x8a, 0x01, 0x01, 0x8a, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn unanswered_pings() {
+        let (mut socket, _peer) = with_input(&[0x8a, 0x01, 0x02], Role::Client, None);
+        socket.send(Message::Ping(vec![1].into())).unwrap();
+        socket.send(Message::Ping(vec![2].into())).unwrap();
+        socket.send(Message::Ping(vec![3].into())).unwrap();
+        assert_eq!(socket.pending_control().unanswered_pings, 3);
+
+        assert_eq!(socket.read().unwrap(), Message::Pong(vec![2].into()));
+        assert_eq!(socket.pending_control().unanswered_pings, 1);
+        assert_eq!(socket.rtt().count(), 1);
+        assert!(socket.last_rtt().is_some());
+    }
+
+    #[test]
+    fn ping_with_id() {
+        let input = [0x8a, 0x08, 0, 0, 0, 0, 0, 0, 0, 1];
+        let (mut socket, mut peer) = with_input(&input, Role::Client, None);
+        assert_eq!(socket.ping_with_id().unwrap(), 0);
+        assert_eq!(socket.ping_with_id().unwrap(), 1);
+        assert_eq!(peer.take_unread()[..2], [0x89, 0x88]);
+
+        assert_eq!(socket.last_rtt(), None);
+        assert!(socket.read().unwrap().is_pong());
+        assert!(socket.last_rtt().is_some());
+        assert_eq!(socket.pending_control().unanswered_pings, 0);
+    }
+
+    #[test]
+    fn close_on_error() {
+        let mut input = vec![0x89, 0x7e, 0x00, 0x7e];
+        input.resize(4 + 126, 0);
+        let config = WebSocketConfig::default().close_on_error(true);
+        let (mut socket, mut peer) = with_input(&input, Role::Client, Some(config));
+
+        assert!(matches!(socket.read(), Err(Error::Protocol(ProtocolError::ControlFrameTooBig))));
+        assert!(!socket.can_write());
+        let output = peer.take_unread();
+        assert_eq!(output[0], 0x88);
+        // The payload is masked, unmask the close code.
+        let code = [output[2] ^ output[6], output[3] ^ output[7]];
+        assert_eq!(u16::from_be_bytes(code), u16::from(CloseCode::Protocol));
+    }
+
+    #[test]
+    fn no_close_on_error_by_default() {
+        let (mut socket, mut peer) = with_input(&[0x81, 0x02, 0xc3, 0x28], Role::Client, None);
+
+        assert!(matches!(socket.read(), Err(Error::Utf8)));
+        assert!(socket.can_write());
+        assert!(peer.take_unread().is_empty());
+    }
+
+    fn layer8_pair() -> (WebSocket<DuplexStream>, WebSocket<DuplexStream>, Jwk) {
+        let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+        let secret = private_key.get_ecdh_shared_secret(&public_key).unwrap();
+        let (client, server) = duplex();
+        let mut client = WebSocket::from_raw_socket(client, Role::Client, None);
+        let mut server = WebSocket::from_raw_socket(server, Role::Server, None);
+        client.set_shared_secret(secret.clone());
+        server.set_shared_secret(secret.clone());
+        (client, server, secret)
+    }
+
+    #[test]
+    fn layer8_fragments_across_envelopes() {
+        let (mut client, mut server, _) = layer8_pair();
+        let chunk = |data: &'static [u8], is_final| {
+            Message::Chunk(MessageChunk { kind: MessageKind::Text, data: data.into(), is_final })
+        };
+        // Every fragment is an envelope of its own, with a ping in between.
+        client.send(chunk(b"Hello, ", false)).unwrap();
+        client.send(Message::Ping(vec![1].into())).unwrap();
+        client.send(chunk(b"World!", true)).unwrap();
+
+        assert_eq!(server.read().unwrap(), Message::Ping(vec![1].into()));
+        assert_eq!(server.read().unwrap(), Message::text("Hello, World!"));
+        assert_eq!(client.read().unwrap(), Message::Pong(vec![1].into()));
+    }
+
+    #[test]
+    fn layer8_trailing_data() {
+        let (mut client, mut server, secret) = layer8_pair();
+        let mut inner = Vec::new();
+        Frame::message(&b"Hi"[..], OpCode::Data(OpData::Text), true)
+            .format_into_buf(&mut inner)
+            .unwrap();
+        inner.extend_from_slice(b"trailing");
+        let envelope =
+            RoundtripEnvelope::encode(&secret.symmetric_encrypt(&inner).unwrap()).to_json_bytes();
+        let mut raw = Vec::new();
+        Frame::message(envelope, OpCode::Data(OpData::Binary), true)
+            .format_into_buf(&mut raw)
+            .unwrap();
+        server.get_mut().write_all(&raw).unwrap();
+
+        assert!(matches!(client.read(), Err(Error::Protocol(ProtocolError::TrailingEnvelopeData))));
+    }
 }
@@ -0,0 +1,114 @@
+//! Messages formatted once to be written to many connections.
+
+use bytes::Bytes;
+
+use super::{
+    frame::{
+        coding::{Data as OpData, OpCode},
+        Frame, Utf8Bytes,
+    },
+    Message,
+};
+
+/// A text or binary message formatted once as an unmasked frame, to be written to many
+/// server-role connections with [`WebSocket::write_prepared`](super::WebSocket::write_prepared).
+///
+/// Formatting a message for every connection is the main cost of broadcasting it. The header
+/// and payload of a prepared message are formatted once and shared, cloning it is cheap.
+///
+/// Connections of the client role mask every frame and connections with a layer8 shared
+/// secret encrypt it, so they fall back to formatting the message themselves. The same holds
+/// for connections fragmenting the message because of
+/// [`WebSocketConfig::max_outgoing_frame_size`](super::WebSocketConfig::max_outgoing_frame_size).
+/// This crate implements no compression extension, so there is no compressed form to prepare.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedMessage {
+    message: Message,
+    frame: Bytes,
+}
+
+impl PreparedMessage {
+    /// Prepare a text message.
+    pub fn text(text: impl Into<Utf8Bytes>) -> Self {
+        let text = text.into();
+        Self::format(Message::Text(text.clone()), text.into(), OpData::Text)
+    }
+
+    /// Prepare a binary message.
+    pub fn binary(data: impl Into<Bytes>) -> Self {
+        let data = data.into();
+        Self::format(Message::Binary(data.clone()), data, OpData::Binary)
+    }
+
+    /// Prepare a text or binary message, other messages are handed back.
+    fn prepare(message: Message) -> Result<Self, Message> {
+        let (data, opdata) = match &message {
+            Message::Text(data) => (data.clone().into(), OpData::Text),
+            Message::Binary(data) => (data.clone(), OpData::Binary),
+            _ => return Err(message),
+        };
+        Ok(Self::format(message, data, opdata))
+    }
+
+    fn format(message: Message, data: Bytes, opdata: OpData) -> Self {
+        let frame = Frame::message(data, OpCode::Data(opdata), true);
+        let mut buf = Vec::with_capacity(frame.len());
+        frame.format_into_buf(&mut buf).expect("Bug: can't write to vector");
+        Self { message, frame: buf.into() }
+    }
+
+    /// The prepared message.
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// The formatted frame, i.e. the header followed by the payload.
+    pub fn frame(&self) -> &Bytes {
+        &self.frame
+    }
+
+    /// The size of the formatted frame in bytes.
+    pub fn len(&self) -> usize {
+        self.frame.len()
+    }
+
+    /// Always `false`, the formatted frame includes at least the header.
+    pub fn is_empty(&self) -> bool {
+        self.frame.is_empty()
+    }
+}
+
+/// Prepare text and binary messages, other messages are handed back.
+impl TryFrom<Message> for PreparedMessage {
+    type Error = Message;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        Self::prepare(message)
+    }
+}
+
+impl From<PreparedMessage> for Message {
+    fn from(prepared: PreparedMessage) -> Self {
+        prepared.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreparedMessage;
+    use crate::protocol::Message;
+
+    #[test]
+    fn prepare() {
+        let prepared = PreparedMessage::text("Hello");
+        assert_eq!(prepared.frame()[..], [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+        assert_eq!(prepared.message(), &Message::text("Hello"));
+
+        let prepared = PreparedMessage::binary(vec![0; 300]);
+        assert_eq!(prepared.frame()[..4], [0x82, 126, 0x01, 0x2c]);
+        assert_eq!(prepared.len(), 304);
+
+        let ping = Message::Ping(vec![1].into());
+        assert_eq!(PreparedMessage::try_from(ping.clone()), Err(ping));
+    }
+}
@@ -0,0 +1,117 @@
+//! Round-trip time statistics of pings.
+
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets in milliseconds, the last bucket has no upper bound.
+const BUCKET_BOUNDS_MS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/// A histogram of the round-trip times of pings answered by a pong.
+///
+/// The round-trip time is measured from writing a ping, e.g. with
+/// [`WebSocket::ping_with_id`](super::WebSocket::ping_with_id), until the matching pong is read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RttHistogram {
+    buckets: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    count: u64,
+    sum: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    last: Option<Duration>,
+}
+
+impl RttHistogram {
+    /// Add a measured round-trip time.
+    pub(super) fn record(&mut self, rtt: Duration) {
+        let ms = rtt.as_millis();
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| ms < u128::from(bound))
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += rtt;
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+        self.last = Some(rtt);
+    }
+
+    /// The number of measured round-trip times.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The most recently measured round-trip time.
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+
+    /// The smallest measured round-trip time.
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// The biggest measured round-trip time.
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// The average of the measured round-trip times.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count).ok().filter(|&count| count > 0)?;
+        Some(self.sum / count)
+    }
+
+    /// Iterate over the buckets of the histogram as pairs of the exclusive upper bound and the
+    /// number of round-trip times within the bucket. The last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        let bounds = BUCKET_BOUNDS_MS.iter().map(|&ms| Some(Duration::from_millis(ms)));
+        bounds.chain([None]).zip(self.buckets.iter().copied())
+    }
+
+    /// Estimate the given quantile, e.g. `0.99`, as the upper bound of the bucket it falls into.
+    ///
+    /// Returns the biggest measured round-trip time if the quantile falls into the last bucket,
+    /// and `None` if nothing was measured yet.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let rank = (quantile.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bound, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                return bound.map(|bound| bound.min(self.max.unwrap_or(bound))).or(self.max);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RttHistogram;
+
+    #[test]
+    fn histogram() {
+        let mut rtt = RttHistogram::default();
+        assert_eq!(rtt.mean(), None);
+        assert_eq!(rtt.quantile(0.5), None);
+
+        for ms in [3, 4, 40, 7000] {
+            rtt.record(Duration::from_millis(ms));
+        }
+        assert_eq!(rtt.count(), 4);
+        assert_eq!(rtt.last(), Some(Duration::from_millis(7000)));
+        assert_eq!(rtt.min(), Some(Duration::from_millis(3)));
+        assert_eq!(rtt.mean(), Some(Duration::from_micros(1_761_750)));
+        assert_eq!(rtt.quantile(0.5), Some(Duration::from_millis(5)));
+        assert_eq!(rtt.quantile(0.75), Some(Duration::from_millis(50)));
+        assert_eq!(rtt.quantile(1.0), Some(Duration::from_millis(7000)));
+
+        let buckets: Vec<_> = rtt.buckets().filter(|(_, count)| *count > 0).collect();
+        assert_eq!(
+            buckets,
+            [(Some(Duration::from_millis(5)), 2), (Some(Duration::from_millis(50)), 1), (None, 1)]
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Sending large messages fragment by fragment.
+
+use std::io::{self, Read, Write};
+
+use bytes::{Bytes, BytesMut};
+use log::*;
+
+use super::{
+    frame::{coding::CloseCode, CloseFrame},
+    message::{MessageChunk, MessageKind, StringCollector},
+    Message, WebSocket,
+};
+use crate::error::{Error, ProtocolError, Result};
+
+/// The payload size of the fragments sent by a [`MessageWriter`] without a frame size limit.
+const DEFAULT_FRAGMENT_SIZE: usize = 64 * 1024;
+
+/// A writer which sends a text or binary message as a sequence of fragments.
+///
+/// Obtained from [`WebSocket::message_writer`]. Data passed to [`Write::write`] is buffered
+/// until [`WebSocketConfig::max_outgoing_frame_size`](super::WebSocketConfig::max_outgoing_frame_size)
+/// bytes, or 64 KiB without a limit, are collected and then sent as a single
+/// frame: the first one as a text or binary frame, the
+/// following ones as continuation frames. [`finish`](Self::finish) sends the last frame, so
+/// the whole message never has to be held in memory.
+///
+/// Control messages may be sent between the fragments with [`write_control`](Self::write_control).
+///
+/// Dropping the writer without calling [`finish`](Self::finish) discards the buffered data. If
+/// fragments of the message were sent already, the peer can never receive the message, so the
+/// connection is closed with [`CloseCode::Error`].
+#[derive(Debug)]
+pub struct MessageWriter<'a, Stream: Read + Write> {
+    socket: &'a mut WebSocket<Stream>,
+    kind: MessageKind,
+    /// Data not sent yet.
+    buffer: BytesMut,
+    /// Payload size of the fragments to send.
+    fragment_size: usize,
+    /// Validates the data of a text message.
+    text: Option<StringCollector>,
+}
+
+impl<'a, Stream: Read + Write> MessageWriter<'a, Stream> {
+    pub(super) fn new(socket: &'a mut WebSocket<Stream>, kind: MessageKind) -> Self {
+        let fragment_size =
+            socket.get_config().max_outgoing_frame_size.unwrap_or(DEFAULT_FRAGMENT_SIZE);
+        let text = match kind {
+            MessageKind::Text => Some(StringCollector::new()),
+            MessageKind::Binary => None,
+        };
+        MessageWriter { socket, kind, buffer: BytesMut::new(), fragment_size, text }
+    }
+
+    /// Send a ping, pong or close message between two fragments of the message.
+    pub fn write_control(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Ping(_) | Message::Pong(_) | Message::Close(_) => self.socket.write(message),
+            _ => Err(Error::Protocol(ProtocolError::FragmentedMessageInProgress)),
+        }
+    }
+
+    /// Send the buffered data as the final fragment of the message and flush the stream.
+    ///
+    /// Returns [`Error::Utf8`] if a text message ends in the middle of a character.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(text) = self.text.take() {
+            text.into_string()?;
+        }
+        self.send_fragment(self.buffer.len(), true)?;
+        self.socket.flush()
+    }
+
+    /// Send the first `len` buffered bytes as a fragment.
+    fn send_fragment(&mut self, len: usize, is_final: bool) -> Result<()> {
+        let data = self.buffer.split_to(len).freeze();
+        let chunk = MessageChunk { kind: self.kind, data: data.clone(), is_final };
+        match self.socket.write(Message::Chunk(chunk)) {
+            Ok(()) => Ok(()),
+            // The fragment is queued and will be written on the next flush.
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(err) => {
+                if let Error::WriteBufferFull(_) = err {
+                    // Keep the data so that the fragment can be sent again.
+                    self.buffer = restore(data, &self.buffer);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<Stream: Read + Write> Write for MessageWriter<'_, Stream> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() >= self.fragment_size {
+            // The previous fragment did not fit into the write buffer, try again.
+            self.send_fragment(self.fragment_size, false).map_err(into_io_error)?;
+        }
+
+        let len = buf.len().min(self.fragment_size - self.buffer.len());
+        if let Some(ref mut text) = self.text {
+            text.extend(&buf[..len])
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, Error::Utf8))?;
+            text.take();
+        }
+        self.buffer.extend_from_slice(&buf[..len]);
+
+        if self.buffer.len() >= self.fragment_size {
+            match self.send_fragment(self.fragment_size, false) {
+                // The data is accepted, the fragment is sent on the next call.
+                Ok(()) | Err(Error::WriteBufferFull(_)) => {}
+                Err(err) => return Err(into_io_error(err)),
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.send_fragment(self.buffer.len(), false).map_err(into_io_error)?;
+        }
+        self.socket.flush().map_err(into_io_error)
+    }
+}
+
+impl<Stream: Read + Write> Drop for MessageWriter<'_, Stream> {
+    fn drop(&mut self) {
+        if !self.socket.context.sending_chunks {
+            return;
+        }
+        let reason = "Unfinished fragmented message";
+        debug!("Message writer dropped, closing the connection");
+        let frame = CloseFrame { code: CloseCode::Error, reason: reason.into() };
+        if let Err(err) = self.socket.close(Some(frame)) {
+            debug!("Failed to close the connection: {err}");
+        }
+    }
+}
+
+/// Put data taken out of the buffer back in front of it.
+fn restore(data: Bytes, buffer: &[u8]) -> BytesMut {
+    let mut restored = BytesMut::with_capacity(data.len() + buffer.len());
+    restored.extend_from_slice(&data);
+    restored.extend_from_slice(buffer);
+    restored
+}
+
+fn into_io_error(err: Error) -> io::Error {
+    match err {
+        Error::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::super::{
+        frame::{
+            coding::{CloseCode, Control, Data, OpCode},
+            FrameSocket,
+        },
+        Message, MessageKind, Role, WebSocket, WebSocketConfig,
+    };
+    use crate::error::{Error, ProtocolError};
+
+    fn written_frames(socket: WebSocket<Cursor<Vec<u8>>>) -> Vec<(OpCode, bool, Vec<u8>)> {
+        let mut frames = FrameSocket::new(Cursor::new(socket.get_ref().get_ref().clone()));
+        let mut result = Vec::new();
+        while let Some(frame) = frames.read(None).unwrap() {
+            let hdr = frame.header().clone();
+            result.push((hdr.opcode, hdr.is_final, frame.into_payload().to_vec()));
+        }
+        result
+    }
+
+    #[test]
+    fn fragmented_text() {
+        let config = WebSocketConfig::default().max_outgoing_frame_size(Some(5));
+        let mut socket =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, Some(config));
+
+        let mut writer = socket.message_writer(MessageKind::Text);
+        writer.write_all(b"Hello, ").unwrap();
+        writer.write_control(Message::Ping(b"ping"[..].into())).unwrap();
+        writer.write_all(b"World!").unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        assert_eq!(
+            written_frames(socket),
+            vec![
+                (OpCode::Data(Data::Text), false, b"Hello".to_vec()),
+                (OpCode::Control(Control::Ping), true, b"ping".to_vec()),
+                (OpCode::Data(Data::Continue), false, b", Wor".to_vec()),
+                (OpCode::Data(Data::Continue), true, b"ld!".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_data_message_while_fragmenting() {
+        let config = WebSocketConfig::default().max_outgoing_frame_size(Some(2));
+        let mut socket =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, Some(config));
+
+        let mut writer = socket.message_writer(MessageKind::Binary);
+        writer.write_all(&[1, 2, 3]).unwrap();
+        assert!(matches!(
+            writer.write_control(Message::Binary(vec![4].into())),
+            Err(Error::Protocol(ProtocolError::FragmentedMessageInProgress))
+        ));
+        assert!(matches!(
+            writer.socket.write(Message::Text("interleaved".into())),
+            Err(Error::Protocol(ProtocolError::FragmentedMessageInProgress))
+        ));
+        writer.finish().unwrap();
+        drop(writer);
+
+        socket.send(Message::Text("ok".into())).unwrap();
+        assert_eq!(
+            written_frames(socket),
+            vec![
+                (OpCode::Data(Data::Binary), false, vec![1, 2]),
+                (OpCode::Data(Data::Continue), true, vec![3]),
+                (OpCode::Data(Data::Text), true, b"ok".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_unfinished() {
+        let config = WebSocketConfig::default().max_outgoing_frame_size(Some(2));
+        let mut socket =
+            WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, Some(config));
+
+        // Nothing was sent yet, the message is just discarded.
+        socket.message_writer(MessageKind::Binary).write_all(&[1]).unwrap();
+        socket.send(Message::Text("ok".into())).unwrap();
+
+        socket.message_writer(MessageKind::Binary).write_all(&[1, 2, 3]).unwrap();
+        assert!(matches!(
+            socket.write(Message::Text("after".into())),
+            Err(Error::Protocol(ProtocolError::SendAfterClosing))
+        ));
+        let frames = written_frames(socket);
+        assert_eq!(frames[1], (OpCode::Data(Data::Binary), false, vec![1, 2]));
+        assert_eq!(frames[2].0, OpCode::Control(Control::Close));
+        assert_eq!(frames[2].2[..2], u16::from(CloseCode::Error).to_be_bytes());
+    }
+
+    #[test]
+    fn invalid_text() {
+        let mut socket = WebSocket::from_raw_socket(Cursor::new(Vec::new()), Role::Server, None);
+        let mut writer = socket.message_writer(MessageKind::Text);
+        assert!(writer.write_all(b"caf\xc3(").is_err());
+        drop(writer);
+
+        let mut writer = socket.message_writer(MessageKind::Text);
+        writer.write_all(b"caf\xc3").unwrap();
+        assert!(matches!(writer.finish(), Err(Error::Utf8)));
+    }
+}
@@ -0,0 +1,591 @@
+//! A client which reconnects automatically when the connection is lost.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use http::HeaderValue;
+use layer8_primitives::crypto::Jwk;
+use log::*;
+
+use crate::{
+    client::{connect_with_config, IntoClientRequest},
+    error::{Error, ProtocolError, Result},
+    handshake::client::{generate_key, Request, Response},
+    protocol::{frame::coding::CloseCode, CloseFrame, Message, WebSocket, WebSocketConfig},
+    stream::MaybeTlsStream,
+};
+
+/// Opens the connections of a [`ReconnectingClient`].
+///
+/// It is implemented by [`ConfigConnector`] and by closures returning a new connection, which
+/// makes it possible to test the reconnect behaviour without a network.
+pub trait Connect {
+    /// The stream of the opened connections.
+    type Stream: Read + Write;
+
+    /// Open a new connection.
+    fn connect(&mut self) -> Result<(WebSocket<Self::Stream>, Response)>;
+}
+
+impl<F, Stream> Connect for F
+where
+    F: FnMut() -> Result<(WebSocket<Stream>, Response)>,
+    Stream: Read + Write,
+{
+    type Stream = Stream;
+
+    fn connect(&mut self) -> Result<(WebSocket<Stream>, Response)> {
+        self()
+    }
+}
+
+/// Opens connections with [`connect_with_config`].
+#[derive(Debug)]
+pub struct ConfigConnector {
+    request: Request,
+    config: Option<WebSocketConfig>,
+    max_redirects: u8,
+}
+
+impl ConfigConnector {
+    /// Create a connector for the given request, see [`connect_with_config`].
+    pub fn new<Req: IntoClientRequest>(
+        request: Req,
+        config: Option<WebSocketConfig>,
+        max_redirects: u8,
+    ) -> Result<Self> {
+        Ok(ConfigConnector { request: request.into_client_request()?, config, max_redirects })
+    }
+}
+
+impl Connect for ConfigConnector {
+    type Stream = MaybeTlsStream<TcpStream>;
+
+    fn connect(&mut self) -> Result<(WebSocket<Self::Stream>, Response)> {
+        let mut builder = Request::builder()
+            .uri(self.request.uri().clone())
+            .method(self.request.method().clone())
+            .version(self.request.version());
+        let headers = builder.headers_mut().expect("Failed to create `Request`");
+        *headers = self.request.headers().clone();
+        // Every handshake needs a new key.
+        if headers.contains_key("Sec-WebSocket-Key") {
+            headers.insert("Sec-WebSocket-Key", HeaderValue::from_str(&generate_key())?);
+        }
+        let request = builder.body(()).expect("Failed to create `Request`");
+
+        connect_with_config(request, self.config.clone(), self.max_redirects)
+    }
+}
+
+/// How a [`ReconnectingClient`] waits between connection attempts.
+///
+/// The delay before the `n`th attempt in a row is `initial_delay * multiplier^n`, but at most
+/// `max_delay`. Up to `jitter` of it are randomly cut off, so that many clients do not
+/// reconnect to a restarted server at the same time.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct ReconnectPolicy {
+    /// The delay before the first attempt to reconnect. The default value is 500 milliseconds.
+    pub initial_delay: Duration,
+    /// The maximum delay between two attempts. The default value is 30 seconds.
+    pub max_delay: Duration,
+    /// The factor the delay grows by with every failed attempt. The default value is 2.
+    pub multiplier: u32,
+    /// The fraction of the delay which is randomized, between 0 and 1. The default value is 0.5.
+    pub jitter: f64,
+    /// The maximum number of attempts in a row before giving up. `None` means no limit.
+    /// The default value is `None`.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Set [`Self::initial_delay`].
+    pub fn initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    /// Set [`Self::max_delay`].
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Set [`Self::multiplier`].
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Set [`Self::jitter`].
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set [`Self::max_attempts`].
+    pub fn max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// The delay before the given attempt, counted from zero. `random` is a value between
+    /// 0 and 1 which selects the cut off part of the delay.
+    pub fn delay(&self, attempt: u32, random: f64) -> Duration {
+        let factor = self.multiplier.checked_pow(attempt).unwrap_or(u32::MAX);
+        let delay = self.initial_delay.saturating_mul(factor).min(self.max_delay);
+        delay.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0))
+    }
+}
+
+/// Called on every new connection of a [`ReconnectingClient`] after the first one.
+type OnReconnected<Stream> = Box<dyn FnMut(&mut WebSocket<Stream>, &Response) -> Result<()> + Send>;
+
+/// Returns the layer8 shared secret of every connection of a [`ReconnectingClient`].
+type SecretProvider = Box<dyn FnMut(&Response) -> Result<Option<Jwk>> + Send>;
+
+/// A client which reconnects automatically when the connection is lost.
+///
+/// The connection is opened on the first call to [`read`](Self::read) or [`send`](Self::send).
+/// When it is lost, or the server closes it with [`CloseCode::Restart`] or [`CloseCode::Again`],
+/// a new connection is opened according to the [`ReconnectPolicy`]. On every new connection the
+/// layer8 shared secret is taken from the [`secret_provider`](Self::secret_provider), the
+/// [`resend`](Self::resend) messages are sent and the [`on_reconnected`](Self::on_reconnected)
+/// hook is run.
+///
+/// A connection closed by the server with another close code, or by [`close`](Self::close),
+/// is not reopened.
+///
+/// ```no_run
+/// use layer8_tungstenite::{reconnect::ReconnectingClient, Message};
+///
+/// let mut client = ReconnectingClient::new("ws://localhost:3012/socket", None)?
+///     .resend(vec![Message::text("subscribe")]);
+/// loop {
+///     let msg = client.read()?;
+///     println!("Received: {msg}");
+/// }
+/// # Ok::<(), layer8_tungstenite::Error>(())
+/// ```
+pub struct ReconnectingClient<C: Connect> {
+    connector: C,
+    policy: ReconnectPolicy,
+    socket: Option<WebSocket<C::Stream>>,
+    /// Messages sent on every new connection after the first one.
+    resend: Vec<Message>,
+    on_reconnected: Option<OnReconnected<C::Stream>>,
+    secret_provider: Option<SecretProvider>,
+    /// Waits between connection attempts, replaceable for tests.
+    sleep: fn(Duration),
+    /// The number of failed connection attempts in a row.
+    failures: u32,
+    /// True once a connection has been opened.
+    connected_before: bool,
+    /// True if the user closed the connection.
+    closed: bool,
+}
+
+impl ReconnectingClient<ConfigConnector> {
+    /// Create a client connecting with [`connect_with_config`], following up to 3 redirects.
+    pub fn new<Req: IntoClientRequest>(
+        request: Req,
+        config: Option<WebSocketConfig>,
+    ) -> Result<Self> {
+        Ok(Self::with_connector(ConfigConnector::new(request, config, 3)?))
+    }
+}
+
+impl<C: Connect> ReconnectingClient<C> {
+    /// Create a client using the given connector.
+    pub fn with_connector(connector: C) -> Self {
+        ReconnectingClient {
+            connector,
+            policy: ReconnectPolicy::default(),
+            socket: None,
+            resend: Vec::new(),
+            on_reconnected: None,
+            secret_provider: None,
+            sleep: thread::sleep,
+            failures: 0,
+            connected_before: false,
+            closed: false,
+        }
+    }
+
+    /// Set the policy for waiting between connection attempts.
+    pub fn policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set the messages to send again on every new connection, e.g. subscriptions.
+    pub fn resend(mut self, messages: Vec<Message>) -> Self {
+        self.resend = messages;
+        self
+    }
+
+    /// Set a hook run on every new connection after the first one, after the
+    /// [`resend`](Self::resend) messages are sent. An error fails the connection attempt.
+    pub fn on_reconnected(
+        mut self,
+        hook: impl FnMut(&mut WebSocket<C::Stream>, &Response) -> Result<()> + Send + 'static,
+    ) -> Self {
+        self.on_reconnected = Some(Box::new(hook));
+        self
+    }
+
+    /// Set the layer8 shared secret of every connection from the handshake response, e.g. by
+    /// completing a new key exchange, leaving the connection unencrypted if the provider returns
+    /// `None`. An error fails the connection attempt.
+    pub fn secret_provider<P>(mut self, provider: P) -> Self
+    where
+        P: FnMut(&Response) -> Result<Option<Jwk>> + Send + 'static,
+    {
+        self.secret_provider = Some(Box::new(provider));
+        self
+    }
+
+    /// Encrypt every connection with the same layer8 shared secret.
+    ///
+    /// A key that outlives a connection is exposed for longer and all connections are readable
+    /// once it leaks, so prefer a [`secret_provider`](Self::secret_provider) doing a new key
+    /// exchange for every connection.
+    pub fn reuse_shared_secret(self, shared_secret: Jwk) -> Self {
+        self.secret_provider(move |_| Ok(Some(shared_secret.clone())))
+    }
+
+    /// Replace the function waiting between connection attempts, [`thread::sleep`] by default.
+    pub fn sleep_with(mut self, sleep: fn(Duration)) -> Self {
+        self.sleep = sleep;
+        self
+    }
+
+    /// Returns the current connection, if any.
+    pub fn get_ref(&self) -> Option<&WebSocket<C::Stream>> {
+        self.socket.as_ref()
+    }
+
+    /// Returns the current connection mutably, if any.
+    pub fn get_mut(&mut self) -> Option<&mut WebSocket<C::Stream>> {
+        self.socket.as_mut()
+    }
+
+    /// Read a message, reconnecting if the connection is lost.
+    ///
+    /// See [`WebSocket::read`].
+    pub fn read(&mut self) -> Result<Message> {
+        loop {
+            let closed = self.closed;
+            let socket = self.socket()?;
+            match socket.read() {
+                Ok(Message::Close(Some(CloseFrame { code, reason })))
+                    if !closed && matches!(code, CloseCode::Restart | CloseCode::Again) =>
+                {
+                    debug!("Server closed the connection with {code} ({reason}), reconnecting");
+                    // Try to send the close reply, the connection is dropped anyway.
+                    let _ = socket.flush();
+                    self.socket = None;
+                    if code == CloseCode::Restart {
+                        self.failures = 0;
+                    } else {
+                        self.failures = self.failures.saturating_add(1);
+                    }
+                    self.reconnect()?;
+                }
+                Ok(message) => {
+                    self.failures = 0;
+                    return Ok(message);
+                }
+                Err(err) if !closed && is_connection_lost(&err) => {
+                    debug!("Connection lost: {err}, reconnecting");
+                    self.socket = None;
+                    self.reconnect()?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Write and flush a message, reconnecting and trying again once if the connection is lost.
+    ///
+    /// See [`WebSocket::send`].
+    pub fn send(&mut self, message: Message) -> Result<()> {
+        match self.socket()?.send(message.clone()) {
+            Err(err) if !self.closed && is_connection_lost(&err) => {
+                debug!("Connection lost: {err}, reconnecting");
+                self.socket = None;
+                self.reconnect()?;
+                self.socket()?.send(message)
+            }
+            result => result,
+        }
+    }
+
+    /// Close the connection without reconnecting.
+    ///
+    /// See [`WebSocket::close`].
+    pub fn close(&mut self, code: Option<CloseFrame>) -> Result<()> {
+        self.closed = true;
+        match self.socket.as_mut() {
+            Some(socket) => socket.close(code),
+            None => Err(Error::AlreadyClosed),
+        }
+    }
+
+    /// Returns the current connection, opening one if there is none.
+    fn socket(&mut self) -> Result<&mut WebSocket<C::Stream>> {
+        if self.socket.is_none() {
+            if self.closed {
+                return Err(Error::AlreadyClosed);
+            }
+            if self.connected_before {
+                self.reconnect()?;
+            } else {
+                self.connect()?;
+            }
+        }
+        Ok(self.socket.as_mut().expect("Bug: no connection after connecting"))
+    }
+
+    /// Open a new connection, waiting before every attempt according to the policy.
+    fn reconnect(&mut self) -> Result<()> {
+        loop {
+            let delay = self.policy.delay(self.failures, rand::random());
+            debug!("Reconnecting in {delay:?}");
+            (self.sleep)(delay);
+            match self.connect() {
+                Ok(()) => return Ok(()),
+                Err(err) if self.policy.max_attempts.is_some_and(|max| self.failures >= max) => {
+                    return Err(err)
+                }
+                Err(err) => debug!("Failed to reconnect: {err}"),
+            }
+        }
+    }
+
+    /// Make a single connection attempt.
+    fn connect(&mut self) -> Result<()> {
+        match self.try_connect() {
+            Ok(socket) => {
+                self.socket = Some(socket);
+                self.connected_before = true;
+                Ok(())
+            }
+            Err(err) => {
+                self.failures = self.failures.saturating_add(1);
+                Err(err)
+            }
+        }
+    }
+
+    fn try_connect(&mut self) -> Result<WebSocket<C::Stream>> {
+        let (mut socket, response) = self.connector.connect()?;
+        if let Some(provider) = &mut self.secret_provider {
+            if let Some(shared_secret) = provider(&response)? {
+                socket.set_shared_secret(shared_secret);
+            }
+        }
+        if self.connected_before {
+            for message in &self.resend {
+                socket.write(message.clone())?;
+            }
+            socket.flush()?;
+            if let Some(hook) = &mut self.on_reconnected {
+                hook(&mut socket, &response)?;
+            }
+        }
+        Ok(socket)
+    }
+}
+
+impl<C: Connect + fmt::Debug> fmt::Debug for ReconnectingClient<C>
+where
+    C::Stream: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingClient")
+            .field("connector", &self.connector)
+            .field("policy", &self.policy)
+            .field("socket", &self.socket)
+            .field("resend", &self.resend)
+            .field("failures", &self.failures)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns true if the error means the connection is gone, but a new one may work.
+fn is_connection_lost(err: &Error) -> bool {
+    match err {
+        Error::Io(err) => err.kind() != io::ErrorKind::WouldBlock,
+        Error::Protocol(ProtocolError::ResetWithoutClosingHandshake) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use layer8_primitives::crypto::{generate_key_pair, KeyUse};
+
+    use super::{ReconnectPolicy, ReconnectingClient};
+    use crate::{
+        error::Error,
+        handshake::client::Response,
+        protocol::{frame::coding::CloseCode, CloseFrame, Message, Role, WebSocket},
+        testing::{duplex, DuplexStream, ScriptedPeer, Step},
+    };
+
+    /// A connection to a scripted server, returned with the client's end.
+    fn connection() -> (DuplexStream, ScriptedPeer) {
+        let (stream, peer) = duplex();
+        (stream, ScriptedPeer::new(peer, Role::Server))
+    }
+
+    fn close(code: CloseCode) -> Step {
+        Step::Close(Some(CloseFrame { code, reason: "".into() }))
+    }
+
+    /// A connector handing out the given connections, or failing if there is `None`.
+    fn mock_connector(
+        mut connections: Vec<Option<DuplexStream>>,
+    ) -> impl FnMut() -> crate::Result<(WebSocket<DuplexStream>, Response)> {
+        connections.reverse();
+        move || {
+            let stream = connections.pop().expect("no more connections").ok_or_else(|| {
+                Error::Io(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+            })?;
+            let socket = WebSocket::from_raw_socket(stream, Role::Client, None);
+            Ok((socket, Response::new(None)))
+        }
+    }
+
+    fn no_sleep(_: Duration) {}
+
+    #[test]
+    fn policy_delay() {
+        let policy = ReconnectPolicy::default()
+            .initial_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(10))
+            .jitter(0.5);
+        assert_eq!(policy.delay(0, 0.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_secs(4));
+        assert_eq!(policy.delay(2, 1.0), Duration::from_secs(2));
+        assert_eq!(policy.delay(5, 0.0), Duration::from_secs(10));
+        assert_eq!(policy.delay(100, 0.0), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn reconnect_after_restart() {
+        let (restart, first) = connection();
+        let mut first =
+            first.step(close(CloseCode::Restart)).step(Step::ExpectClose(Some(CloseCode::Restart)));
+        let (hello, second) = connection();
+        let mut second = second
+            .step(Step::Send(Message::text("hello")))
+            .step(Step::Expect(Message::text("subscribe")));
+        assert!(!first.run().unwrap());
+        assert!(!second.run().unwrap());
+        let connector = mock_connector(vec![Some(restart), None, Some(hello)]);
+
+        let reconnected = Arc::new(AtomicUsize::new(0));
+        let hook_count = reconnected.clone();
+        let mut client = ReconnectingClient::with_connector(connector)
+            .sleep_with(no_sleep)
+            .resend(vec![Message::text("subscribe")])
+            .on_reconnected(move |_, _| {
+                hook_count.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            });
+
+        assert_eq!(client.read().unwrap(), Message::text("hello"));
+        assert_eq!(reconnected.load(Ordering::Relaxed), 1);
+        // The close reply to the first connection, then the resent message.
+        assert!(first.run().unwrap());
+        assert!(second.run().unwrap());
+    }
+
+    #[test]
+    fn no_reconnect_after_normal_close() {
+        let (normal, peer) = connection();
+        let mut peer =
+            peer.step(close(CloseCode::Normal)).step(Step::ExpectClose(Some(CloseCode::Normal)));
+        assert!(!peer.run().unwrap());
+        let mut client = ReconnectingClient::with_connector(mock_connector(vec![Some(normal)]))
+            .sleep_with(no_sleep);
+
+        assert!(matches!(client.read().unwrap(), Message::Close(Some(_))));
+        // The server closes the connection once it received the close reply.
+        client.get_mut().unwrap().flush().unwrap();
+        assert!(peer.run().unwrap());
+        drop(peer);
+        assert!(matches!(client.read(), Err(Error::ConnectionClosed)));
+    }
+
+    #[test]
+    fn give_up_after_max_attempts() {
+        // The first connection is lost right away.
+        let (lost, _) = duplex();
+        let connector = mock_connector(vec![Some(lost), None, None]);
+        let mut client = ReconnectingClient::with_connector(connector)
+            .sleep_with(no_sleep)
+            .policy(ReconnectPolicy::default().max_attempts(Some(2)));
+
+        match client.read() {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn secret_per_connection() {
+        let secret = || {
+            let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+            private_key.get_ecdh_shared_secret(&public_key).unwrap()
+        };
+        let secrets = vec![secret(), secret()];
+        let (first, first_peer) = connection();
+        let (second, second_peer) = connection();
+        let mut first_peer =
+            first_peer.shared_secret(secrets[0].clone()).step(close(CloseCode::Restart));
+        let mut second_peer =
+            second_peer.shared_secret(secrets[1].clone()).step(Step::Send(Message::text("hello")));
+        assert!(first_peer.run().unwrap());
+        assert!(second_peer.run().unwrap());
+
+        let mut provided = secrets.into_iter();
+        let mut client =
+            ReconnectingClient::with_connector(mock_connector(vec![Some(first), Some(second)]))
+                .sleep_with(no_sleep)
+                .secret_provider(move |_| Ok(provided.next()));
+
+        // The restart is only understood with the first secret, the text with the second one.
+        assert_eq!(client.read().unwrap(), Message::text("hello"));
+    }
+}
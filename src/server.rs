@@ -3,13 +3,16 @@
 pub use crate::handshake::server::ServerHandshake;
 
 use crate::handshake::{
-    server::{Callback, NoCallback},
+    auth::Authenticator,
+    server::{self, Callback, NoCallback},
     HandshakeError,
 };
 
 use crate::protocol::{WebSocket, WebSocketConfig};
 
 use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
 
 /// Accept the given Stream as a WebSocket.
 ///
@@ -55,6 +58,24 @@ pub fn accept_hdr_with_config<S: Read + Write, C: Callback>(
     ServerHandshake::start(stream, callback, config).handshake()
 }
 
+/// Accept the given Stream as a WebSocket, authenticating the client.
+///
+/// This function does the same as `accept_hdr_with_config()` but authenticates the client
+/// before the callback is called. Clients failing authentication are rejected with
+/// `401 Unauthorized`, the identity of authenticated clients is available from
+/// [`WebSocket::identity`].
+pub fn accept_authenticated<S: Read + Write, A, C: Callback>(
+    stream: S,
+    authenticator: A,
+    callback: C,
+    config: Option<WebSocketConfig>,
+) -> Result<WebSocket<S>, HandshakeError<ServerHandshake<S, C>>>
+where
+    A: Authenticator + Send + Sync + 'static,
+{
+    ServerHandshake::start_authenticated(stream, authenticator, callback, config).handshake()
+}
+
 /// Accept the given Stream as a WebSocket.
 ///
 /// This function does the same as `accept()` but accepts an extra callback
@@ -66,3 +87,60 @@ pub fn accept_hdr<S: Read + Write, C: Callback>(
 ) -> Result<WebSocket<S>, HandshakeError<ServerHandshake<S, C>>> {
     accept_hdr_with_config(stream, callback, None)
 }
+
+/// Accept a WebSocket upgrade of a request already read from the stream and parsed, e.g. by an
+/// HTTP server framework.
+///
+/// `leftover` are the bytes the framework read from the stream after the request, they are
+/// read as the first frames. The request is validated and the `101 Switching Protocols` response
+/// is written to the stream without reading from it. If the request extensions contain a
+/// [`Jwk`](layer8_primitives::crypto::Jwk) it is set as the layer8 shared secret of the
+/// WebSocket.
+pub fn accept_from_request<S: Read + Write, B>(
+    stream: S,
+    request: &http::Request<B>,
+    leftover: Vec<u8>,
+    config: Option<WebSocketConfig>,
+) -> crate::Result<WebSocket<S>> {
+    accept_hdr_from_request(stream, request, leftover, NoCallback, config)
+}
+
+/// Accept a WebSocket upgrade of a request already read from the stream and parsed.
+///
+/// This function does the same as `accept_from_request()` but accepts an extra callback
+/// for header processing, e.g. to select a subprotocol or extensions. Requests rejected by the
+/// callback are answered with its error response and returned as
+/// [`Error::Http`](crate::Error::Http).
+pub fn accept_hdr_from_request<S: Read + Write, B, C: Callback>(
+    stream: S,
+    request: &http::Request<B>,
+    leftover: Vec<u8>,
+    callback: C,
+    config: Option<WebSocketConfig>,
+) -> crate::Result<WebSocket<S>> {
+    server::accept_request(stream, request, leftover, callback, config)
+}
+
+/// Accept the next connection of the Unix domain socket listener as a WebSocket.
+///
+/// Uses a configuration provided as an argument. Calling it with `None` will use the default one
+/// used by `accept_unix()`.
+#[cfg(unix)]
+pub fn accept_unix_with_config(
+    listener: &UnixListener,
+    config: Option<WebSocketConfig>,
+) -> Result<WebSocket<UnixStream>, HandshakeError<ServerHandshake<UnixStream, NoCallback>>> {
+    let (stream, _) = listener.accept().map_err(|e| HandshakeError::Failure(e.into()))?;
+    accept_with_config(stream, config)
+}
+
+/// Accept the next connection of the Unix domain socket listener as a WebSocket.
+///
+/// Clients connect to it with [`connect_unix`](crate::connect_unix) and
+/// `ws+unix:///path/to.sock:/resource` URLs.
+#[cfg(unix)]
+pub fn accept_unix(
+    listener: &UnixListener,
+) -> Result<WebSocket<UnixStream>, HandshakeError<ServerHandshake<UnixStream, NoCallback>>> {
+    accept_unix_with_config(listener, None)
+}
@@ -8,10 +8,12 @@
 use std::ops::Deref;
 use std::{
     fmt::{self, Debug},
-    io::{Read, Result as IoResult, Write},
+    io::{IoSlice, Read, Result as IoResult, Write},
 };
 
 use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 
 #[cfg(feature = "native-tls")]
 use native_tls_crate::TlsStream;
@@ -39,6 +41,14 @@ impl NoDelay for TcpStream {
     }
 }
 
+/// Unix domain sockets do not buffer small writes, so this does nothing.
+#[cfg(unix)]
+impl NoDelay for UnixStream {
+    fn set_nodelay(&mut self, _nodelay: bool) -> IoResult<()> {
+        Ok(())
+    }
+}
+
 #[cfg(feature = "native-tls")]
 impl<S: Read + Write + NoDelay> NoDelay for TlsStream<S> {
     fn set_nodelay(&mut self, nodelay: bool) -> IoResult<()> {
@@ -122,6 +132,16 @@ impl<S: Read + Write> Write for MaybeTlsStream<S> {
         }
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> IoResult<usize> {
+        match *self {
+            MaybeTlsStream::Plain(ref mut s) => s.write_vectored(bufs),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(ref mut s) => s.write_vectored(bufs),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::Rustls(ref mut s) => s.write_vectored(bufs),
+        }
+    }
+
     fn flush(&mut self) -> IoResult<()> {
         match *self {
             MaybeTlsStream::Plain(ref mut s) => s.flush(),
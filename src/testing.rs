@@ -0,0 +1,602 @@
+//! Helpers to test WebSocket applications without a network.
+//!
+//! [`duplex`] creates a pair of connected in-memory streams which can inject `WouldBlock`
+//! errors, partial writes and connection resets. A [`ScriptedPeer`] drives one end of the pair
+//! with a list of [`Step`]s, sending messages and malformed data and expecting messages from
+//! the application at the other end.
+//!
+//! ```
+//! use layer8_tungstenite::{
+//!     protocol::{frame::{coding::CloseCode, CloseFrame}, Role},
+//!     testing::{duplex, ScriptedPeer, Step},
+//!     Message, WebSocket,
+//! };
+//!
+//! let (client, server) = duplex();
+//! let mut peer = ScriptedPeer::new(client, Role::Client)
+//!     .step(Step::Send(Message::text("Hello")))
+//!     .step(Step::Expect(Message::text("Hello")))
+//!     .step(Step::ExpectClose(Some(CloseCode::Away)));
+//! let mut ws = WebSocket::from_raw_socket(server, Role::Server, None);
+//!
+//! assert!(!peer.run().unwrap());
+//! let message = ws.read().unwrap();
+//! ws.send(message).unwrap();
+//! ws.close(Some(CloseFrame { code: CloseCode::Away, reason: "".into() })).unwrap();
+//! assert!(peer.run().unwrap());
+//! ```
+//!
+//! Available with the `testing` feature.
+
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use layer8_primitives::crypto::Jwk;
+
+use crate::{
+    error::Error,
+    protocol::{
+        frame::{coding::CloseCode, CloseFrame, Frame},
+        Role, WebSocket, WebSocketConfig,
+    },
+    Message,
+};
+
+/// Create a pair of connected in-memory streams.
+///
+/// What is written to one stream is read from the other. Both streams are non-blocking: reading
+/// without data available fails with [`ErrorKind::WouldBlock`], see
+/// [`DuplexStream::set_nonblocking`]. Dropping a stream closes the connection, the other stream
+/// then reads the end of the stream and fails to write with [`ErrorKind::BrokenPipe`].
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    let a_to_b = Arc::new(Pipe::default());
+    let b_to_a = Arc::new(Pipe::default());
+    (DuplexStream::new(b_to_a.clone(), a_to_b.clone()), DuplexStream::new(a_to_b, b_to_a))
+}
+
+/// A fault injected into a write to a [`DuplexStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteFault {
+    /// Fail with [`ErrorKind::WouldBlock`] without writing anything.
+    WouldBlock,
+    /// Write at most this many bytes.
+    Partial(usize),
+    /// Reset the connection, failing every following read and write of both streams with
+    /// [`ErrorKind::ConnectionReset`].
+    Reset,
+}
+
+/// The number of calls to the methods of a [`DuplexStream`], including failed calls.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Calls {
+    /// Calls to [`Read::read`].
+    pub reads: usize,
+    /// Calls to [`Write::write`].
+    pub writes: usize,
+    /// Calls to [`Write::flush`].
+    pub flushes: usize,
+}
+
+/// One end of an in-memory connection created by [`duplex`].
+#[derive(Debug)]
+pub struct DuplexStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    faults: VecDeque<WriteFault>,
+    blocked_flushes: usize,
+    calls: Calls,
+    nonblocking: bool,
+}
+
+impl DuplexStream {
+    fn new(incoming: Arc<Pipe>, outgoing: Arc<Pipe>) -> Self {
+        Self {
+            incoming,
+            outgoing,
+            faults: VecDeque::new(),
+            blocked_flushes: 0,
+            calls: Calls::default(),
+            nonblocking: true,
+        }
+    }
+
+    /// Make reads and writes wait for the other stream instead of failing with
+    /// [`ErrorKind::WouldBlock`], e.g. to run the other stream on another thread.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Limit the number of bytes written by this stream and not read by the other stream yet.
+    /// Writes beyond the limit fail with [`ErrorKind::WouldBlock`], or wait in blocking mode.
+    /// `None` means no limit, which is the default.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.outgoing.lock().capacity = capacity;
+        self.outgoing.ready.notify_all();
+    }
+
+    /// Add faults for the next writes, one per write.
+    pub fn inject(&mut self, faults: impl IntoIterator<Item = WriteFault>) {
+        self.faults.extend(faults);
+    }
+
+    /// Fail the next `count` flushes with [`ErrorKind::WouldBlock`]. Written data can be read by
+    /// the other stream without a flush.
+    pub fn block_flushes(&mut self, count: usize) {
+        self.blocked_flushes = count;
+    }
+
+    /// The number of reads, writes and flushes of this stream so far.
+    pub fn calls(&self) -> Calls {
+        self.calls
+    }
+
+    /// The number of bytes written by this stream and not read by the other stream yet.
+    pub fn pending(&self) -> usize {
+        self.outgoing.lock().data.len()
+    }
+
+    /// Take the bytes written by the other stream and not read yet, e.g. to check what the
+    /// application wrote without parsing it.
+    pub fn take_unread(&mut self) -> Vec<u8> {
+        let data = mem::take(&mut self.incoming.lock().data);
+        self.incoming.ready.notify_all();
+        data.into()
+    }
+
+    /// Reset the connection, see [`WriteFault::Reset`].
+    pub fn reset(&mut self) {
+        for pipe in [&self.incoming, &self.outgoing] {
+            pipe.lock().reset = true;
+            pipe.ready.notify_all();
+        }
+    }
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.calls.reads += 1;
+        let mut pipe = self.incoming.lock();
+        loop {
+            if pipe.reset {
+                return Err(ErrorKind::ConnectionReset.into());
+            }
+            if !pipe.data.is_empty() || pipe.closed || buf.is_empty() {
+                break;
+            }
+            if self.nonblocking {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            pipe = self.incoming.wait(pipe);
+        }
+
+        let len = pipe.data.len().min(buf.len());
+        for (byte, data) in buf.iter_mut().zip(pipe.data.drain(..len)) {
+            *byte = data;
+        }
+        self.incoming.ready.notify_all();
+        Ok(len)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.calls.writes += 1;
+        let mut max_len = buf.len();
+        match self.faults.pop_front() {
+            Some(WriteFault::WouldBlock) => return Err(ErrorKind::WouldBlock.into()),
+            Some(WriteFault::Partial(len)) => max_len = max_len.min(len),
+            Some(WriteFault::Reset) => {
+                self.reset();
+                return Err(ErrorKind::ConnectionReset.into());
+            }
+            None => {}
+        }
+
+        let mut pipe = self.outgoing.lock();
+        let room = loop {
+            if pipe.reset {
+                return Err(ErrorKind::ConnectionReset.into());
+            }
+            if pipe.closed {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+            let room = pipe
+                .capacity
+                .map_or(usize::MAX, |capacity| capacity.saturating_sub(pipe.data.len()));
+            if room > 0 || buf.is_empty() {
+                break room;
+            }
+            if self.nonblocking {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            pipe = self.outgoing.wait(pipe);
+        };
+
+        let len = max_len.min(room);
+        pipe.data.extend(&buf[..len]);
+        self.outgoing.ready.notify_all();
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.calls.flushes += 1;
+        if self.blocked_flushes > 0 {
+            self.blocked_flushes -= 1;
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        for pipe in [&self.incoming, &self.outgoing] {
+            pipe.lock().closed = true;
+            pipe.ready.notify_all();
+        }
+    }
+}
+
+/// The data sent in one direction of a [`duplex`] connection.
+#[derive(Debug, Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PipeState {
+    data: VecDeque<u8>,
+    capacity: Option<usize>,
+    closed: bool,
+    reset: bool,
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, PipeState>) -> MutexGuard<'a, PipeState> {
+        self.ready.wait(state).unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A step of a [`ScriptedPeer`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Step {
+    /// Send a message, encrypted if the peer has a layer8 shared secret.
+    Send(Message),
+    /// Send a frame with its opcode, flags and payload as they are, e.g. a fragment or a control
+    /// frame with an invalid payload. It goes through the peer's [`WebSocket::write`] like a
+    /// message: with a layer8 shared secret, the formatted frame is encrypted and sent as the
+    /// payload of a binary envelope frame. The frame on the wire, or the envelope, is split into
+    /// fragments if its payload exceeds the peer's [`WebSocketConfig::max_outgoing_frame_size`]
+    /// (control frames fail instead) and is masked if the peer is a client.
+    SendFrame(Frame),
+    /// Write bytes to the stream as they are, e.g. a malformed frame.
+    SendRaw(Vec<u8>),
+    /// Start the close handshake.
+    Close(Option<CloseFrame>),
+    /// Expect to receive this message.
+    Expect(Message),
+    /// Expect to receive a close frame, with this code if it is not `None`. The close frame
+    /// is answered automatically.
+    ExpectClose(Option<CloseCode>),
+    /// Expect the other side to close the connection, i.e. the end of the stream after the close
+    /// handshake.
+    ExpectClosed,
+}
+
+/// An error of a [`ScriptedPeer`].
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ScriptError {
+    /// A different message than expected was received.
+    #[error("Step {step}: expected {expected}, received {received:?}")]
+    Unexpected {
+        /// The index of the failed step.
+        step: usize,
+        /// The expected message.
+        expected: String,
+        /// The received message.
+        received: Message,
+    },
+    /// Sending or receiving failed.
+    #[error("Step {step}: {source}")]
+    WebSocket {
+        /// The index of the failed step.
+        step: usize,
+        /// The error.
+        source: Error,
+    },
+}
+
+/// The other side of a connection for testing a WebSocket application, running a list of
+/// [`Step`]s.
+///
+/// The peer is a [`WebSocket`] of the given role on one end of a [`duplex`] pair, so it masks
+/// frames as a client, answers pings and close frames and can use layer8 encryption.
+#[derive(Debug)]
+pub struct ScriptedPeer {
+    socket: WebSocket<DuplexStream>,
+    steps: VecDeque<Step>,
+    done: usize,
+}
+
+impl ScriptedPeer {
+    /// Create a peer of the given role.
+    pub fn new(stream: DuplexStream, role: Role) -> Self {
+        Self::with_config(stream, role, WebSocketConfig::default())
+    }
+
+    /// Create a peer of the given role with a configuration, e.g. to accept big messages.
+    pub fn with_config(stream: DuplexStream, role: Role, config: WebSocketConfig) -> Self {
+        let socket = WebSocket::from_raw_socket(stream, role, Some(config));
+        Self { socket, steps: VecDeque::new(), done: 0 }
+    }
+
+    /// Encrypt and decrypt messages with a layer8 shared secret.
+    pub fn shared_secret(mut self, shared_secret: Jwk) -> Self {
+        self.socket.set_shared_secret(shared_secret);
+        self
+    }
+
+    /// Add a step to the end of the script.
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push_back(step);
+        self
+    }
+
+    /// Add steps to the end of the script.
+    pub fn extend(&mut self, steps: impl IntoIterator<Item = Step>) {
+        self.steps.extend(steps);
+    }
+
+    /// Run the steps until the script is done or a step has to wait for the application.
+    ///
+    /// Returns `true` once all steps are done. With a non-blocking stream, `false` means the
+    /// peer waits for a message from the application.
+    pub fn run(&mut self) -> Result<bool, ScriptError> {
+        while let Some(step) = self.steps.front() {
+            let step = step.clone();
+            let result = match self.run_step(&step) {
+                Ok(true) => Ok(()),
+                Ok(false) => break,
+                Err(err) => Err(err),
+            };
+            result.map_err(|err| self.error(err))?;
+            self.steps.pop_front();
+            self.done += 1;
+        }
+        // Write buffered messages and replies.
+        match self.socket.flush() {
+            Ok(()) | Err(Error::ConnectionClosed | Error::AlreadyClosed) => {}
+            Err(Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => return Err(ScriptError::WebSocket { step: self.done, source: err }),
+        }
+        Ok(self.steps.is_empty())
+    }
+
+    /// Check if all steps are done.
+    pub fn is_done(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The WebSocket of the peer, e.g. to inject faults into its stream.
+    pub fn websocket(&mut self) -> &mut WebSocket<DuplexStream> {
+        &mut self.socket
+    }
+
+    /// Run a step, returning `false` if it has to wait for data.
+    fn run_step(&mut self, step: &Step) -> Result<bool, StepError> {
+        match step {
+            Step::Send(message) => self.socket.write(message.clone())?,
+            Step::SendFrame(frame) => self.socket.write(Message::Frame(frame.clone()))?,
+            Step::SendRaw(data) => {
+                self.flush()?;
+                self.socket.get_mut().write_all(data).map_err(Error::Io)?;
+            }
+            Step::Close(frame) => self.socket.close(frame.clone())?,
+            Step::Expect(expected) => {
+                let Some(received) = self.receive()? else { return Ok(false) };
+                if received != *expected {
+                    return Err(StepError::Unexpected(format!("{expected:?}"), received));
+                }
+            }
+            Step::ExpectClose(code) => {
+                let Some(received) = self.receive()? else { return Ok(false) };
+                match (&received, code) {
+                    (Message::Close(_), None) => {}
+                    (Message::Close(Some(frame)), Some(code)) if frame.code == *code => {}
+                    _ => {
+                        let expected = match code {
+                            Some(code) => format!("a close frame with code {code}"),
+                            None => "a close frame".to_owned(),
+                        };
+                        return Err(StepError::Unexpected(expected, received));
+                    }
+                }
+            }
+            Step::ExpectClosed => match self.flush().and_then(|()| self.socket.read()) {
+                Err(Error::ConnectionClosed | Error::AlreadyClosed) => {}
+                Err(Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err.into()),
+                Ok(received) => {
+                    return Err(StepError::Unexpected("the connection closed".into(), received))
+                }
+            },
+        }
+        Ok(true)
+    }
+
+    /// Write buffered messages and receive a message, `None` if it has not arrived yet.
+    fn receive(&mut self) -> Result<Option<Message>, Error> {
+        self.flush()?;
+        match self.socket.read() {
+            Ok(message) => Ok(Some(message)),
+            Err(Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Write buffered messages, leaving them buffered if the stream would block.
+    fn flush(&mut self) -> Result<(), Error> {
+        match self.socket.flush() {
+            Err(Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+
+    fn error(&mut self, err: StepError) -> ScriptError {
+        let step = self.done;
+        match err {
+            StepError::Unexpected(expected, received) => {
+                ScriptError::Unexpected { step, expected, received }
+            }
+            StepError::WebSocket(source) => ScriptError::WebSocket { step, source },
+        }
+    }
+}
+
+/// The error of a step, before knowing which one it was.
+enum StepError {
+    Unexpected(String, Message),
+    WebSocket(Error),
+}
+
+impl From<Error> for StepError {
+    fn from(err: Error) -> Self {
+        Self::WebSocket(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, thread};
+
+    use layer8_primitives::crypto::{generate_key_pair, KeyUse};
+
+    use super::{duplex, ScriptError, ScriptedPeer, Step, WriteFault};
+    use crate::{
+        error::Error,
+        protocol::{
+            frame::{coding::CloseCode, CloseFrame},
+            Role, WebSocketConfig,
+        },
+        Message, WebSocket,
+    };
+
+    #[test]
+    fn close_handshake() {
+        let (client, server) = duplex();
+        let mut peer = ScriptedPeer::new(client, Role::Client)
+            .step(Step::Send(Message::text("bye")))
+            .step(Step::Close(Some(CloseFrame { code: CloseCode::Away, reason: "".into() })))
+            .step(Step::ExpectClose(Some(CloseCode::Away)))
+            .step(Step::ExpectClosed);
+        let mut ws = WebSocket::from_raw_socket(server, Role::Server, None);
+
+        assert!(!peer.run().unwrap());
+        assert_eq!(ws.read().unwrap(), Message::text("bye"));
+        assert!(
+            matches!(ws.read().unwrap(), Message::Close(Some(frame)) if frame.code == CloseCode::Away)
+        );
+        // The reply to the close frame is written by the next call.
+        assert!(matches!(ws.read(), Err(Error::ConnectionClosed)));
+        drop(ws);
+        assert!(peer.run().unwrap());
+    }
+
+    #[test]
+    fn malformed_frame() {
+        let (client, server) = duplex();
+        // A ping with a payload of 126 bytes, which is too big for a control frame.
+        let mut ping = vec![0x89, 0xfe, 0, 126, 0, 0, 0, 0];
+        ping.extend([0; 126]);
+        let mut peer = ScriptedPeer::new(client, Role::Client)
+            .step(Step::SendRaw(ping))
+            .step(Step::ExpectClose(Some(CloseCode::Protocol)));
+        let config = WebSocketConfig::default().close_on_error(true);
+        let mut ws = WebSocket::from_raw_socket(server, Role::Server, Some(config));
+
+        peer.run().unwrap();
+        assert!(matches!(ws.read(), Err(Error::Protocol(_))));
+        assert!(peer.run().unwrap());
+    }
+
+    #[test]
+    fn unexpected_message() {
+        let (client, server) = duplex();
+        let mut peer =
+            ScriptedPeer::new(client, Role::Client).step(Step::Expect(Message::text("a")));
+        let mut ws = WebSocket::from_raw_socket(server, Role::Server, None);
+        ws.send(Message::text("b")).unwrap();
+
+        let err = peer.run().unwrap_err();
+        assert!(
+            matches!(err, ScriptError::Unexpected { step: 0, received, .. } if received == Message::text("b"))
+        );
+    }
+
+    #[test]
+    fn backpressure_and_faults() {
+        let (client, mut server) = duplex();
+        server.set_capacity(Some(4));
+        server.inject([WriteFault::WouldBlock, WriteFault::Partial(1)]);
+        let mut peer =
+            ScriptedPeer::new(client, Role::Client).step(Step::Expect(Message::text("Hello")));
+        let config = WebSocketConfig::default().write_buffer_size(0).max_write_buffer_size(8);
+        let mut ws = WebSocket::from_raw_socket(server, Role::Server, Some(config));
+
+        // 7 bytes are buffered, 1 and then 3 are written.
+        let is_would_block =
+            |result| matches!(result, Err(Error::Io(err)) if err.kind() == ErrorKind::WouldBlock);
+        assert!(is_would_block(ws.send(Message::text("Hello"))));
+        assert!(is_would_block(ws.flush()));
+        assert!(is_would_block(ws.flush()));
+        assert_eq!(ws.get_ref().pending(), 4);
+        assert!(matches!(ws.send(Message::text("full")), Err(Error::WriteBufferFull(_))));
+
+        while !peer.run().unwrap() {
+            let _ = ws.flush();
+        }
+
+        ws.get_mut().inject([WriteFault::Reset]);
+        assert!(
+            matches!(ws.send(Message::text("reset")), Err(Error::Io(err)) if err.kind() == ErrorKind::ConnectionReset)
+        );
+        let err = peer.step(Step::Expect(Message::text("reset"))).run().unwrap_err();
+        assert!(matches!(err, ScriptError::WebSocket { step: 1, source: Error::Io(_) }));
+    }
+
+    #[test]
+    fn layer8_and_threads() {
+        let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+        let secret = private_key.get_ecdh_shared_secret(&public_key).unwrap();
+        let (mut client, mut server) = duplex();
+        client.set_nonblocking(false);
+        server.set_nonblocking(false);
+
+        let mut peer = ScriptedPeer::new(client, Role::Client)
+            .shared_secret(secret.clone())
+            .step(Step::Send(Message::text("secret")))
+            .step(Step::Expect(Message::text("secret")));
+        let peer = thread::spawn(move || peer.run());
+
+        let mut ws = WebSocket::from_raw_socket(server, Role::Server, None);
+        ws.set_shared_secret(secret);
+        let message = ws.read().unwrap();
+        assert_eq!(message, Message::text("secret"));
+        ws.send(message).unwrap();
+        assert!(peer.join().unwrap().unwrap());
+        // The other stream is dropped with the peer.
+        assert!(matches!(ws.read(), Err(Error::Protocol(_))));
+    }
+}
@@ -1,6 +1,8 @@
 //! Connection helper.
 use std::io::{Read, Write};
 
+use http::Uri;
+
 use crate::{
     client::{client_with_config, uri_mode, IntoClientRequest},
     error::UrlError,
@@ -31,7 +33,7 @@ mod encryption {
     pub mod native_tls {
         use native_tls_crate::{HandshakeError as TlsHandshakeError, TlsConnector};
 
-        use std::io::{Read, Write};
+        use std::io::{self, Read, Write};
 
         use crate::{
             error::TlsError,
@@ -57,9 +59,11 @@ mod encryption {
                     match connected {
                         Err(e) => match e {
                             TlsHandshakeError::Failure(f) => Err(Error::Tls(f.into())),
-                            TlsHandshakeError::WouldBlock(_) => {
-                                panic!("Bug: TLS handshake not blocked")
-                            }
+                            // A blocking socket with a read or write timeout.
+                            TlsHandshakeError::WouldBlock(_) => Err(Error::Io(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "TLS handshake timed out",
+                            ))),
                         },
                         Ok(s) => Ok(MaybeTlsStream::NativeTls(s)),
                     }
@@ -202,16 +206,28 @@ where
     S: Read + Write,
 {
     let request = request.into_client_request()?;
+    let stream = wrap_stream(stream, request.uri(), connector)?;
+    client_with_config(request, stream, config)
+}
 
+/// Upgrade the stream to TLS if the URI requires it.
+pub(crate) fn wrap_stream<S>(
+    stream: S,
+    uri: &Uri,
+    connector: Option<Connector>,
+) -> Result<MaybeTlsStream<S>>
+where
+    S: Read + Write,
+{
     #[cfg(any(feature = "native-tls", feature = "__rustls-tls"))]
-    let domain = match request.uri().host() {
+    let domain = match uri.host() {
         Some(d) => Ok(d.to_string()),
         None => Err(Error::Url(UrlError::NoHostName)),
     }?;
 
-    let mode = uri_mode(request.uri())?;
+    let mode = uri_mode(uri)?;
 
-    let stream = match connector {
+    match connector {
         Some(conn) => match conn {
             #[cfg(feature = "native-tls")]
             Connector::NativeTls(conn) => {
@@ -237,7 +253,5 @@ where
                 self::encryption::plain::wrap_stream(stream, mode)
             }
         }
-    }?;
-
-    client_with_config(request, stream, config)
+    }
 }
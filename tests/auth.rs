@@ -0,0 +1,72 @@
+//! Verifies authenticating clients during the server handshake.
+
+#![cfg(feature = "handshake")]
+
+use std::{net::TcpListener, thread};
+
+use layer8_tungstenite::{
+    accept_authenticated, connect,
+    handshake::{
+        auth::{CredentialAuthenticator, CredentialSource, Credentials},
+        server::NoCallback,
+    },
+    ClientRequestBuilder, Error, Message,
+};
+
+/// Accept a single connection, echoing the identity of the client as text.
+fn server() -> (http::Uri, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("ws://{}/socket", listener.local_addr().unwrap()).parse().unwrap();
+    let authenticator = CredentialAuthenticator::new(
+        [
+            CredentialSource::Bearer,
+            CredentialSource::Basic,
+            CredentialSource::Query("access_token".into()),
+            CredentialSource::Cookie("session".into()),
+        ],
+        |credentials: &Credentials| match credentials {
+            Credentials::Token(token) if token == "s3cret token" => Some("token".to_owned()),
+            Credentials::Basic { username, password } if password == "pw" => Some(username.clone()),
+            _ => None,
+        },
+    );
+    let server = thread::spawn(move || {
+        for _ in 0..5 {
+            let (stream, _) = listener.accept().unwrap();
+            if let Ok(mut socket) =
+                accept_authenticated(stream, authenticator.clone(), NoCallback, None)
+            {
+                let identity: &String = socket.identity().unwrap();
+                socket.send(Message::text(identity.as_str())).unwrap();
+            }
+        }
+    });
+    (uri, server)
+}
+
+#[test]
+fn client_credentials() {
+    let (uri, server) = server();
+    let builder = || ClientRequestBuilder::new(uri.clone());
+
+    let requests = [
+        (builder().with_bearer_token("s3cret token"), "token"),
+        (builder().with_basic_auth("alice", "pw"), "alice"),
+        (builder().with_query_token("access_token", "s3cret token"), "token"),
+        (builder().with_cookie("theme", "dark").with_cookie("session", "s3cret token"), "token"),
+    ];
+    for (request, identity) in requests {
+        let (mut socket, _) = connect(request).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::text(identity));
+    }
+
+    match connect(builder().with_bearer_token("wrong")) {
+        Err(Error::Http(response)) => {
+            assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+            assert_eq!(response.body().as_deref(), Some(&b"Invalid credentials"[..]));
+        }
+        other => panic!("unexpected result {other:?}"),
+    }
+    server.join().unwrap();
+}
@@ -0,0 +1,1272 @@
+//! A self-contained port of the Autobahn test suite.
+//!
+//! A raw peer plays the part of the Autobahn fuzzer against a `WebSocket` echoing every message,
+//! over loopback and in both roles. Each case is judged like Autobahn does and compared to
+//! `autobahn/expected-results.json`, with and without layer8 encryption. With layer8 the peer
+//! wraps every frame it sends into an encrypted envelope, so the cases run against the frames
+//! inside the envelopes.
+//!
+//! Verdicts do not depend on timing: the implementation reports when it has consumed all data
+//! sent so far and waits for more, and the time limits are only reached if it misbehaves.
+
+#![cfg(feature = "handshake")]
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use layer8_primitives::{
+    crypto::{generate_key_pair, Jwk, KeyUse},
+    types::RoundtripEnvelope,
+};
+use layer8_tungstenite::{
+    accept, client, handshake::derive_accept_key, protocol::Role, Message, WebSocket,
+};
+
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CONTINUATION: u8 = 0x0;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+/// How long to wait for the implementation before giving up on it, e.g. for a connection that is
+/// never closed. Cases passing return as soon as the awaited event happens.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A frame as sent by the peer, possibly violating the protocol.
+#[derive(Debug, Clone)]
+struct RawFrame {
+    fin: bool,
+    rsv: u8,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+fn frame(opcode: u8, payload: impl Into<Vec<u8>>) -> RawFrame {
+    RawFrame { fin: true, rsv: 0, opcode, payload: payload.into() }
+}
+
+fn fragment(opcode: u8, payload: impl Into<Vec<u8>>, fin: bool) -> RawFrame {
+    RawFrame { fin, ..frame(opcode, payload) }
+}
+
+fn with_rsv(rsv: u8, frame: RawFrame) -> RawFrame {
+    RawFrame { rsv, ..frame }
+}
+
+fn close(code: u16, reason: &[u8]) -> RawFrame {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason);
+    frame(CLOSE, payload)
+}
+
+impl RawFrame {
+    fn encode(&self, mask: bool) -> Vec<u8> {
+        let mut out = vec![(self.fin as u8) << 7 | self.rsv << 4 | self.opcode];
+        let mask_bit = if mask { 0x80 } else { 0 };
+        match self.payload.len() {
+            len @ 0..=125 => out.push(mask_bit | len as u8),
+            len @ 126..=0xffff => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        if mask {
+            let key = [0x3d, 0x2a, 0xf1, 0x07];
+            out.extend_from_slice(&key);
+            out.extend(self.payload.iter().zip(key.iter().cycle()).map(|(byte, key)| byte ^ key));
+        } else {
+            out.extend_from_slice(&self.payload);
+        }
+        out
+    }
+}
+
+/// An action of the peer.
+#[derive(Debug, Clone)]
+enum Step {
+    /// Send frames in a single write.
+    Send(Vec<RawFrame>),
+    /// Send frames with a write per frame.
+    FrameWise(Vec<RawFrame>),
+    /// Send frames in writes of at most this many bytes.
+    Chopped(Vec<RawFrame>, usize),
+    /// Send a frame split at these offsets of its payload, checking whether the connection
+    /// failed before sending the last part.
+    Split(RawFrame, Vec<usize>),
+    /// Check whether the connection failed on the data sent so far, once the implementation
+    /// consumed it.
+    Probe,
+    /// Wait a bit.
+    Pause(Duration),
+    /// Wait until this many messages were received.
+    WaitFor(usize),
+}
+
+/// What the implementation should do.
+#[derive(Debug, Clone)]
+enum Expect {
+    /// Send these messages and complete the closing handshake.
+    Close(Vec<Message>),
+    /// Send these messages and fail the connection.
+    Fail(Vec<Message>),
+    /// No judgement, the behavior is informational.
+    Informational,
+    /// Decline permessage-deflate.
+    Unimplemented,
+}
+
+#[derive(Debug)]
+struct Case {
+    id: String,
+    steps: Vec<Step>,
+    expect: Expect,
+}
+
+fn case(id: impl Into<String>, steps: Vec<Step>, expect: Expect) -> Case {
+    Case { id: id.into(), steps, expect }
+}
+
+impl Case {
+    /// The number of payload bytes sent by the peer.
+    fn payload_len(&self) -> usize {
+        let frames = self.steps.iter().flat_map(|step| match step {
+            Step::Send(frames) | Step::FrameWise(frames) | Step::Chopped(frames, _) => {
+                frames.as_slice()
+            }
+            Step::Split(frame, _) => std::slice::from_ref(frame),
+            Step::Probe | Step::Pause(_) | Step::WaitFor(_) => &[],
+        });
+        frames.map(|frame| frame.payload.len()).sum()
+    }
+}
+
+/// Echo a single message.
+fn echo(id: impl Into<String>, opcode: u8, payload: Vec<u8>, chop: Option<usize>) -> Case {
+    let message = match opcode {
+        TEXT => Message::text(String::from_utf8(payload.clone()).unwrap()),
+        _ => Message::binary(payload.clone()),
+    };
+    let frames = vec![frame(opcode, payload)];
+    let step = match chop {
+        Some(chop) => Step::Chopped(frames, chop),
+        None => Step::Send(frames),
+    };
+    case(id, vec![step], Expect::Close(vec![message]))
+}
+
+/// Send a text message with this payload, which is echoed if it is valid UTF-8.
+fn utf8(id: impl Into<String>, frames: Vec<RawFrame>) -> Case {
+    let payload: Vec<u8> = frames.iter().flat_map(|frame| frame.payload.clone()).collect();
+    let expect = match String::from_utf8(payload) {
+        Ok(text) => Expect::Close(vec![Message::text(text)]),
+        Err(_) => Expect::Fail(vec![]),
+    };
+    case(id, vec![Step::Send(frames)], expect)
+}
+
+/// Split a text payload into a fragmented message.
+fn fragments(payloads: Vec<Vec<u8>>) -> Vec<RawFrame> {
+    let last = payloads.len() - 1;
+    payloads
+        .into_iter()
+        .enumerate()
+        .map(|(i, payload)| fragment(if i == 0 { TEXT } else { CONTINUATION }, payload, i == last))
+        .collect()
+}
+
+fn repeat(pattern: &[u8], len: usize) -> Vec<u8> {
+    pattern.iter().copied().cycle().take(len).collect()
+}
+
+fn text(payload: &str) -> Message {
+    Message::text(payload)
+}
+
+fn pong(payload: &[u8]) -> Message {
+    Message::Pong(payload.to_vec().into())
+}
+
+fn cases() -> Vec<Case> {
+    let mut cases = Vec::new();
+
+    // 1 Framing
+    let sizes = [0, 125, 126, 127, 128, 65535, 65536];
+    for (i, len) in sizes.into_iter().enumerate() {
+        cases.push(echo(format!("1.1.{}", i + 1), TEXT, repeat(b"*", len), None));
+        cases.push(echo(format!("1.2.{}", i + 1), BINARY, repeat(&[0xfe], len), None));
+    }
+    cases.push(echo("1.1.8", TEXT, repeat(b"*", 65536), Some(997)));
+    cases.push(echo("1.2.8", BINARY, repeat(&[0xfe], 65536), Some(997)));
+
+    // 2 Pings/Pongs
+    let pings: [&[u8]; 3] =
+        [b"", b"Hello, world!", &[0x00, 0xff, 0xfe, 0xfd, 0xfc, 0xfb, 0x00, 0xff]];
+    for (i, payload) in pings.into_iter().enumerate() {
+        let steps = vec![Step::Send(vec![frame(PING, payload)])];
+        cases.push(case(format!("2.{}", i + 1), steps, Expect::Close(vec![pong(payload)])));
+    }
+    let payload = repeat(&[0xfe], 125);
+    cases.push(case(
+        "2.4",
+        vec![Step::Send(vec![frame(PING, payload.clone())])],
+        Expect::Close(vec![pong(&payload)]),
+    ));
+    cases.push(case(
+        "2.5",
+        vec![Step::Send(vec![frame(PING, repeat(&[0xfe], 126))])],
+        Expect::Fail(vec![]),
+    ));
+    cases.push(case(
+        "2.6",
+        vec![Step::Chopped(vec![frame(PING, payload.clone())], 1)],
+        Expect::Close(vec![pong(&payload)]),
+    ));
+    cases.push(case("2.7", vec![Step::Send(vec![frame(PONG, "")])], Expect::Close(vec![])));
+    cases.push(case(
+        "2.8",
+        vec![Step::Send(vec![frame(PONG, "unsolicited pong payload")])],
+        Expect::Close(vec![]),
+    ));
+    cases.push(case(
+        "2.9",
+        vec![Step::Send(vec![
+            frame(PONG, "unsolicited pong payload"),
+            frame(PING, "ping payload"),
+        ])],
+        Expect::Close(vec![pong(b"ping payload")]),
+    ));
+    let pings: Vec<_> = (0..10).map(|i| frame(PING, format!("payload-{i}"))).collect();
+    let pongs: Vec<_> = (0..10).map(|i| pong(format!("payload-{i}").as_bytes())).collect();
+    cases.push(case("2.10", vec![Step::Send(pings.clone())], Expect::Close(pongs.clone())));
+    cases.push(case("2.11", vec![Step::FrameWise(pings)], Expect::Close(pongs)));
+
+    // 3 Reserved Bits
+    let small = || frame(TEXT, "Hello, world!");
+    let echoed = || vec![text("Hello, world!")];
+    cases.push(case("3.1", vec![Step::Send(vec![with_rsv(1, small())])], Expect::Fail(vec![])));
+    let violations = [
+        ("3.2", 2, Step::Send as fn(Vec<RawFrame>) -> Step),
+        ("3.3", 3, Step::FrameWise),
+        ("3.4", 4, |frames| Step::Chopped(frames, 1)),
+    ];
+    for (id, rsv, step) in violations {
+        let frames = vec![small(), with_rsv(rsv, small()), frame(PING, "")];
+        cases.push(case(id, vec![step(frames)], Expect::Fail(echoed())));
+    }
+    let frames = [
+        ("3.5", 5, frame(BINARY, repeat(&[0xff], 8))),
+        ("3.6", 6, frame(PING, "Hello, world!")),
+        ("3.7", 7, close(1000, b"")),
+    ];
+    for (id, rsv, frame) in frames {
+        cases.push(case(id, vec![Step::Send(vec![with_rsv(rsv, frame)])], Expect::Fail(vec![])));
+    }
+
+    // 4 Opcodes
+    for (section, opcodes) in [(1, [3, 4, 5, 6, 7]), (2, [11, 12, 13, 14, 15])] {
+        let [a, b, c, d, e] = opcodes;
+        let reserved = |opcode, payload: &str| frame(opcode, payload);
+        cases.push(case(
+            format!("4.{section}.1"),
+            vec![Step::Send(vec![reserved(a, "")])],
+            Expect::Fail(vec![]),
+        ));
+        cases.push(case(
+            format!("4.{section}.2"),
+            vec![Step::Send(vec![reserved(b, "reserved opcode payload")])],
+            Expect::Fail(vec![]),
+        ));
+        cases.push(case(
+            format!("4.{section}.3"),
+            vec![Step::Send(vec![small(), reserved(c, ""), frame(PING, "")])],
+            Expect::Fail(echoed()),
+        ));
+        cases.push(case(
+            format!("4.{section}.4"),
+            vec![Step::Send(vec![
+                small(),
+                reserved(d, "reserved opcode payload"),
+                frame(PING, ""),
+            ])],
+            Expect::Fail(echoed()),
+        ));
+        cases.push(case(
+            format!("4.{section}.5"),
+            vec![Step::FrameWise(vec![
+                small(),
+                reserved(e, "reserved opcode payload"),
+                frame(PING, ""),
+            ])],
+            Expect::Fail(echoed()),
+        ));
+    }
+
+    // 5 Fragmentation
+    for (id, opcode) in [("5.1", PING), ("5.2", PONG)] {
+        let frames =
+            vec![fragment(opcode, "fragment1", false), fragment(CONTINUATION, "fragment2", true)];
+        cases.push(case(id, vec![Step::Send(frames)], Expect::Fail(vec![])));
+    }
+    let fragmented = || fragments(vec![b"fragment1".to_vec(), b"fragment2".to_vec()]);
+    let modes = [Step::Send as fn(Vec<RawFrame>) -> Step, Step::FrameWise, |frames| {
+        Step::Chopped(frames, 1)
+    }];
+    for (i, step) in modes.into_iter().enumerate() {
+        cases.push(case(
+            format!("5.{}", 3 + i),
+            vec![step(fragmented())],
+            Expect::Close(vec![text("fragment1fragment2")]),
+        ));
+        let mut frames = fragmented();
+        frames.insert(1, frame(PING, "ping payload"));
+        cases.push(case(
+            format!("5.{}", 6 + i),
+            vec![step(frames)],
+            Expect::Close(vec![pong(b"ping payload"), text("fragment1fragment2")]),
+        ));
+        let frames = vec![
+            fragment(CONTINUATION, "non-continuation payload", true),
+            frame(TEXT, "Hello, world!"),
+        ];
+        cases.push(case(format!("5.{}", 9 + i), vec![step(frames)], Expect::Fail(vec![])));
+        let frames = vec![
+            fragment(CONTINUATION, "non-continuation payload", false),
+            frame(TEXT, "Hello, world!"),
+        ];
+        cases.push(case(format!("5.{}", 12 + i), vec![step(frames)], Expect::Fail(vec![])));
+    }
+    let frames = vec![
+        fragment(TEXT, "fragment1", false),
+        fragment(CONTINUATION, "fragment2", true),
+        fragment(CONTINUATION, "fragment3", false),
+        fragment(TEXT, "fragment4", true),
+    ];
+    cases.push(case(
+        "5.15",
+        vec![Step::Send(frames)],
+        Expect::Fail(vec![text("fragment1fragment2")]),
+    ));
+    for (id, first_fin) in [("5.16", false), ("5.17", true)] {
+        let frames = [
+            fragment(CONTINUATION, "fragment1", first_fin),
+            fragment(TEXT, "fragment2", false),
+            fragment(CONTINUATION, "fragment3", true),
+        ];
+        let frames = frames.iter().chain(frames.iter()).cloned().collect();
+        cases.push(case(id, vec![Step::Send(frames)], Expect::Fail(vec![])));
+    }
+    let frames = vec![fragment(TEXT, "fragment1", false), fragment(TEXT, "fragment2", true)];
+    cases.push(case("5.18", vec![Step::Send(frames)], Expect::Fail(vec![])));
+    for (id, step) in [("5.19", Step::Send as fn(Vec<RawFrame>) -> Step), ("5.20", Step::FrameWise)]
+    {
+        let pause = Step::Pause(Duration::from_millis(100));
+        let steps = vec![
+            step(vec![
+                fragment(TEXT, "fragment1", false),
+                fragment(CONTINUATION, "fragment2", false),
+            ]),
+            step(vec![frame(PING, "pongme 1!")]),
+            pause.clone(),
+            step(vec![
+                fragment(CONTINUATION, "fragment3", false),
+                fragment(CONTINUATION, "fragment4", false),
+            ]),
+            step(vec![frame(PING, "pongme 2!")]),
+            pause,
+            step(vec![fragment(CONTINUATION, "fragment5", true)]),
+        ];
+        let expect = vec![
+            pong(b"pongme 1!"),
+            pong(b"pongme 2!"),
+            text("fragment1fragment2fragment3fragment4fragment5"),
+        ];
+        cases.push(case(id, steps, Expect::Close(expect)));
+    }
+
+    // 6 UTF-8 Handling
+    cases.push(utf8("6.1.1", vec![frame(TEXT, "")]));
+    cases.push(utf8("6.1.2", fragments(vec![vec![], vec![], vec![]])));
+    cases.push(utf8("6.1.3", fragments(vec![vec![], b"middle frame payload".to_vec(), vec![]])));
+    let hello = "Hello-\u{b5}@\u{df}\u{f6}\u{e4}\u{fc}\u{e0}\u{e1}-UTF-8!!";
+    let kosme = "\u{3ba}\u{1f79}\u{3c3}\u{3bc}\u{3b5}";
+    cases.push(utf8("6.2.1", vec![frame(TEXT, hello)]));
+    let chars = hello.chars().map(|c| c.to_string().into_bytes()).collect();
+    cases.push(utf8("6.2.2", fragments(chars)));
+    cases.push(utf8("6.2.3", fragments(hello.bytes().map(|b| vec![b]).collect())));
+    cases.push(utf8("6.2.4", fragments(kosme.bytes().map(|b| vec![b]).collect())));
+    let invalid = [kosme.as_bytes(), b"\xed\xa0\x80", b"edited"].concat();
+    cases.push(utf8("6.3.1", vec![frame(TEXT, invalid.clone())]));
+    cases.push(utf8("6.3.2", fragments(invalid.iter().map(|&b| vec![b]).collect())));
+    // Fail fast on invalid UTF-8.
+    for (id, parts) in
+        [("6.4.1", [&b""[..], b"\xf4\x90\x80\x80"]), ("6.4.2", [b"\xf4", b"\x90\x80\x80"])]
+    {
+        let first = [kosme.as_bytes(), parts[0]].concat();
+        let steps = vec![
+            Step::FrameWise(vec![
+                fragment(TEXT, first, false),
+                fragment(CONTINUATION, parts[1], false),
+            ]),
+            Step::Probe,
+            Step::FrameWise(vec![fragment(CONTINUATION, "edited", true)]),
+        ];
+        cases.push(case(id, steps, Expect::Fail(vec![])));
+    }
+    let payload = [kosme.as_bytes(), b"\xf4\x90\x80\x80edited"].concat();
+    let kosme_len = kosme.len();
+    for (id, splits) in
+        [("6.4.3", vec![kosme_len, kosme_len + 4]), ("6.4.4", vec![kosme_len, kosme_len + 2])]
+    {
+        let steps = vec![Step::Split(frame(TEXT, payload.clone()), splits)];
+        cases.push(case(id, steps, Expect::Fail(vec![])));
+    }
+    cases.push(utf8("6.5.1", vec![frame(TEXT, kosme)]));
+    for i in 1..=kosme.len() {
+        cases.push(utf8(format!("6.6.{i}"), vec![frame(TEXT, &kosme.as_bytes()[..i])]));
+    }
+    let sequences: [(&str, &[&[u8]]); 15] = [
+        ("6.7", &[b"\x00", b"\xc2\x80", b"\xe0\xa0\x80", b"\xf0\x90\x80\x80"]),
+        ("6.8", &[b"\xf8\x88\x80\x80\x80", b"\xfc\x84\x80\x80\x80\x80"]),
+        ("6.9", &[b"\x7f", b"\xdf\xbf", b"\xef\xbf\xbf", b"\xf4\x8f\xbf\xbf"]),
+        ("6.10", &[b"\xf7\xbf\xbf\xbf", b"\xfb\xbf\xbf\xbf\xbf", b"\xfd\xbf\xbf\xbf\xbf\xbf"]),
+        (
+            "6.11",
+            &[
+                b"\xed\x9f\xbf",
+                b"\xee\x80\x80",
+                b"\xef\xbf\xbd",
+                b"\xf4\x8f\xbf\xbf",
+                b"\xf4\x90\x80\x80",
+            ],
+        ),
+        (
+            "6.12",
+            &[
+                b"\x80",
+                b"\xbf",
+                b"\x80\xbf",
+                b"\x80\xbf\x80",
+                b"\x80\xbf\x80\xbf",
+                b"\x80\xbf\x80\xbf\x80",
+                b"\x80\xbf\x80\xbf\x80\xbf",
+                &[],
+            ],
+        ),
+        ("6.13", &[&[], &[], &[], b"\xf8 \xf9 \xfa \xfb ", b"\xfc \xfd "]),
+        (
+            "6.14",
+            &[
+                b"\xc0",
+                b"\xe0\x80",
+                b"\xf0\x80\x80",
+                b"\xf8\x80\x80\x80",
+                b"\xfc\x80\x80\x80\x80",
+                b"\xdf",
+                b"\xef\xbf",
+                b"\xf7\xbf\xbf",
+                b"\xfb\xbf\xbf\xbf",
+                b"\xfd\xbf\xbf\xbf\xbf",
+            ],
+        ),
+        ("6.15", &[&[]]),
+        ("6.16", &[b"\xfe", b"\xff", b"\xfe\xfe\xff\xff"]),
+        (
+            "6.17",
+            &[
+                b"\xc0\xaf",
+                b"\xe0\x80\xaf",
+                b"\xf0\x80\x80\xaf",
+                b"\xf8\x80\x80\x80\xaf",
+                b"\xfc\x80\x80\x80\x80\xaf",
+            ],
+        ),
+        (
+            "6.18",
+            &[
+                b"\xc1\xbf",
+                b"\xe0\x9f\xbf",
+                b"\xf0\x8f\xbf\xbf",
+                b"\xf8\x87\xbf\xbf\xbf",
+                b"\xfc\x83\xbf\xbf\xbf\xbf",
+            ],
+        ),
+        (
+            "6.19",
+            &[
+                b"\xc0\x80",
+                b"\xe0\x80\x80",
+                b"\xf0\x80\x80\x80",
+                b"\xf8\x80\x80\x80\x80",
+                b"\xfc\x80\x80\x80\x80\x80",
+            ],
+        ),
+        (
+            "6.20",
+            &[
+                b"\xed\xa0\x80",
+                b"\xed\xad\xbf",
+                b"\xed\xae\x80",
+                b"\xed\xaf\xbf",
+                b"\xed\xb0\x80",
+                b"\xed\xbe\x80",
+                b"\xed\xbf\xbf",
+            ],
+        ),
+        (
+            "6.21",
+            &[
+                b"\xed\xa0\x80\xed\xb0\x80",
+                b"\xed\xa0\x80\xed\xbf\xbf",
+                b"\xed\xad\xbf\xed\xb0\x80",
+                b"\xed\xad\xbf\xed\xbf\xbf",
+                b"\xed\xae\x80\xed\xb0\x80",
+                b"\xed\xae\x80\xed\xbf\xbf",
+                b"\xed\xaf\xbf\xed\xb0\x80",
+                b"\xed\xaf\xbf\xed\xbf\xbf",
+            ],
+        ),
+    ];
+    // Sequences built from ranges: all continuation bytes, lonely start bytes followed by a
+    // space, the concatenation of 6.14 and noncharacters.
+    let continuations: Vec<u8> = (0x80..=0xbf).collect();
+    let lonely =
+        |range: std::ops::RangeInclusive<u8>| range.flat_map(|b| [b, b' ']).collect::<Vec<_>>();
+    let lonely = [lonely(0xc0..=0xdf), lonely(0xe0..=0xef), lonely(0xf0..=0xf7)];
+    let incomplete: Vec<u8> = sequences[7].1.concat();
+    let mut noncharacters = vec!["\u{fffe}".to_owned(), "\u{ffff}".to_owned()];
+    for plane in 1..=16 {
+        for last in [0xfffe, 0xffff] {
+            noncharacters.push(char::from_u32(plane << 16 | last).unwrap().to_string());
+        }
+    }
+    for (section, payloads) in sequences {
+        for (i, payload) in payloads.iter().enumerate() {
+            let payload = match (section, i) {
+                ("6.12", 7) => continuations.clone(),
+                ("6.13", 0..=2) => lonely[i].clone(),
+                ("6.15", 0) => incomplete.clone(),
+                _ => payload.to_vec(),
+            };
+            cases.push(utf8(format!("{section}.{}", i + 1), vec![frame(TEXT, payload)]));
+        }
+    }
+    for (i, payload) in noncharacters.into_iter().enumerate() {
+        cases.push(utf8(format!("6.22.{}", i + 1), vec![frame(TEXT, payload)]));
+    }
+
+    // 7 Close Handling
+    let hello = || frame(TEXT, "Hello World!");
+    let normal = || close(1000, b"");
+    cases.push(case(
+        "7.1.1",
+        vec![Step::Send(vec![hello(), normal()])],
+        Expect::Close(vec![text("Hello World!")]),
+    ));
+    cases.push(case("7.1.2", vec![Step::Send(vec![normal(), normal()])], Expect::Close(vec![])));
+    cases.push(case(
+        "7.1.3",
+        vec![Step::Send(vec![normal(), frame(PING, "")])],
+        Expect::Close(vec![]),
+    ));
+    cases.push(case("7.1.4", vec![Step::Send(vec![normal(), hello()])], Expect::Close(vec![])));
+    let frames = vec![
+        fragment(TEXT, "fragment1", false),
+        normal(),
+        fragment(CONTINUATION, "fragment2", true),
+    ];
+    cases.push(case("7.1.5", vec![Step::Send(frames)], Expect::Close(vec![])));
+    let big = frame(TEXT, repeat(b"BAsd7&jh23", 256 * 1024));
+    cases.push(case(
+        "7.1.6",
+        vec![Step::Send(vec![big, normal(), frame(PING, "")])],
+        Expect::Informational,
+    ));
+    cases.push(case("7.3.1", vec![Step::Send(vec![frame(CLOSE, "")])], Expect::Close(vec![])));
+    cases.push(case("7.3.2", vec![Step::Send(vec![frame(CLOSE, "a")])], Expect::Fail(vec![])));
+    let reasons = [("7.3.3", 0), ("7.3.4", 5), ("7.3.5", 123)];
+    for (id, len) in reasons {
+        cases.push(case(
+            id,
+            vec![Step::Send(vec![close(1000, &repeat(b"*", len))])],
+            Expect::Close(vec![]),
+        ));
+    }
+    cases.push(case(
+        "7.3.6",
+        vec![Step::Send(vec![close(1000, &repeat(b"*", 124))])],
+        Expect::Fail(vec![]),
+    ));
+    let invalid = [kosme.as_bytes(), b"\xed\xa0\x80edited"].concat();
+    cases.push(case("7.5.1", vec![Step::Send(vec![close(1000, &invalid)])], Expect::Fail(vec![])));
+    let valid = [1000, 1001, 1002, 1003, 1007, 1008, 1009, 1010, 1011, 3000, 3999, 4000, 4999];
+    for (i, code) in valid.into_iter().enumerate() {
+        cases.push(case(
+            format!("7.7.{}", i + 1),
+            vec![Step::Send(vec![close(code, b"")])],
+            Expect::Close(vec![]),
+        ));
+    }
+    let invalid = [0, 999, 1004, 1005, 1006, 1016, 1100, 2000, 2999];
+    for (i, code) in invalid.into_iter().enumerate() {
+        cases.push(case(
+            format!("7.9.{}", i + 1),
+            vec![Step::Send(vec![close(code, b"")])],
+            Expect::Close(vec![]),
+        ));
+    }
+    for (i, code) in [5000, 65535].into_iter().enumerate() {
+        cases.push(case(
+            format!("7.13.{}", i + 1),
+            vec![Step::Send(vec![close(code, b"")])],
+            Expect::Informational,
+        ));
+    }
+
+    // 9 Limits/Performance
+    const KIB: usize = 1024;
+    const MIB: usize = 1024 * KIB;
+    let sizes = [64 * KIB, 256 * KIB, MIB, 4 * MIB, 8 * MIB, 16 * MIB];
+    for (i, len) in sizes.into_iter().enumerate() {
+        cases.push(echo(format!("9.1.{}", i + 1), TEXT, repeat(b"BAsd7&jh23", len), None));
+        cases.push(echo(format!("9.2.{}", i + 1), BINARY, repeat(&[0xfe], len), None));
+    }
+    let fragment_sizes = [64, 256, KIB, 4 * KIB, 16 * KIB, 64 * KIB, 256 * KIB, MIB, 4 * MIB];
+    for (i, size) in fragment_sizes.into_iter().enumerate() {
+        for (section, opcode, payload) in
+            [(3, TEXT, repeat(b"*", 4 * MIB)), (4, BINARY, repeat(&[0xfe], 4 * MIB))]
+        {
+            let last = payload.len() / size - 1;
+            let frames = payload
+                .chunks(size)
+                .enumerate()
+                .map(|(j, chunk)| {
+                    fragment(if j == 0 { opcode } else { CONTINUATION }, chunk, j == last)
+                })
+                .collect();
+            let message = match opcode {
+                TEXT => Message::text(String::from_utf8(payload).unwrap()),
+                _ => Message::binary(payload),
+            };
+            cases.push(case(
+                format!("9.{section}.{}", i + 1),
+                vec![Step::Send(frames)],
+                Expect::Close(vec![message]),
+            ));
+        }
+    }
+    for (i, chop) in [64, 128, 256, 512, 1024, 2048].into_iter().enumerate() {
+        cases.push(echo(format!("9.5.{}", i + 1), TEXT, repeat(b"*", MIB), Some(chop)));
+        cases.push(echo(format!("9.6.{}", i + 1), BINARY, repeat(&[0xfe], MIB), Some(chop)));
+    }
+    for (i, len) in [0, 16, 64, 256, 1024, 4096].into_iter().enumerate() {
+        for (section, opcode, payload) in
+            [(7, TEXT, repeat(b"*", len)), (8, BINARY, repeat(&[0xfe], len))]
+        {
+            let mut steps = Vec::new();
+            let mut messages = Vec::new();
+            for n in 1..=1000 {
+                steps.push(Step::Send(vec![frame(opcode, payload.clone())]));
+                steps.push(Step::WaitFor(n));
+                messages.push(match opcode {
+                    TEXT => Message::text(String::from_utf8(payload.clone()).unwrap()),
+                    _ => Message::binary(payload.clone()),
+                });
+            }
+            cases.push(case(format!("9.{section}.{}", i + 1), steps, Expect::Close(messages)));
+        }
+    }
+
+    // 10 Misc
+    let payload = repeat(b"*", 65536);
+    let frames = fragments(payload.chunks(1300).map(<[u8]>::to_vec).collect());
+    let message = text(std::str::from_utf8(&payload).unwrap());
+    cases.push(case("10.1.1", vec![Step::Send(frames)], Expect::Close(vec![message])));
+
+    cases
+}
+
+/// The result of a case, as written by Autobahn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Outcome {
+    behavior: String,
+    behavior_close: String,
+    remote_close_code: Option<u16>,
+}
+
+/// Something the peer received.
+#[derive(Debug)]
+enum Event {
+    Message(Message),
+    Close(Option<u16>),
+    Closed,
+}
+
+/// How far the implementation got reading the data of the peer.
+#[derive(Default)]
+struct Progress {
+    state: Mutex<ReadState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct ReadState {
+    /// The number of bytes read by the implementation.
+    consumed: u64,
+    /// The implementation waits for data in a read.
+    reading: bool,
+    /// The implementation dropped the connection.
+    done: bool,
+}
+
+impl Progress {
+    fn update(&self, update: impl FnOnce(&mut ReadState)) {
+        update(&mut self.state.lock().unwrap());
+        self.changed.notify_all();
+    }
+
+    /// Wait until the implementation consumed `written` bytes and waits for more, or dropped
+    /// the connection. Returns true in the latter case.
+    fn wait_consumed(&self, written: u64) -> bool {
+        let state = self.state.lock().unwrap();
+        let busy =
+            |state: &mut ReadState| !(state.done || state.reading && state.consumed >= written);
+        let (state, _) = self.changed.wait_timeout_while(state, TIMEOUT, busy).unwrap();
+        state.done
+    }
+}
+
+/// The stream of the implementation, reporting its reads to the peer.
+struct Tracked {
+    stream: TcpStream,
+    progress: Arc<Progress>,
+}
+
+impl Read for Tracked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.progress.update(|state| state.reading = true);
+        let result = self.stream.read(buf);
+        let len = *result.as_ref().unwrap_or(&0) as u64;
+        self.progress.update(|state| {
+            state.reading = false;
+            state.consumed += len;
+        });
+        result
+    }
+}
+
+impl Write for Tracked {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.progress.update(|state| state.done = true);
+    }
+}
+
+/// The Autobahn side of a connection.
+struct Peer {
+    stream: TcpStream,
+    /// Mask frames, i.e. the implementation is the server.
+    mask: bool,
+    secret: Option<Jwk>,
+    events: Receiver<Event>,
+    progress: Arc<Progress>,
+    /// The number of bytes written to the implementation, including the handshake.
+    written: u64,
+    received: Vec<Message>,
+    close_code: Option<u16>,
+    closed: bool,
+    close_sent: bool,
+    /// The connection failed before the last write to probe for it.
+    failed_fast: Option<bool>,
+}
+
+impl Peer {
+    fn new(
+        stream: TcpStream,
+        role: Role,
+        secret: Option<Jwk>,
+        progress: Arc<Progress>,
+        written: u64,
+    ) -> Self {
+        stream.set_nodelay(true).unwrap();
+        let (sender, events) = mpsc::channel();
+        let reader = stream.try_clone().unwrap();
+        let reader_secret = secret.clone();
+        // The implementation as a client waits for the server to close the connection.
+        let shutdown_on_close = role == Role::Client;
+        thread::spawn(move || {
+            let mut reader = Reader { stream: reader, secret: reader_secret, message: None };
+            loop {
+                match reader.read() {
+                    Ok(Some(event)) => {
+                        let close = matches!(event, Event::Close(_));
+                        if sender.send(event).is_err() {
+                            return;
+                        }
+                        if close && shutdown_on_close {
+                            let _ = reader.stream.shutdown(Shutdown::Both);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(_) => {
+                        let _ = sender.send(Event::Closed);
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            stream,
+            mask: role == Role::Server,
+            secret,
+            events,
+            progress,
+            written,
+            received: Vec::new(),
+            close_code: None,
+            closed: false,
+            close_sent: false,
+            failed_fast: None,
+        }
+    }
+
+    /// The bytes of a frame on the wire, wrapped into an envelope with layer8.
+    fn wire(&mut self, frame: &RawFrame) -> Vec<u8> {
+        self.close_sent |= frame.opcode == CLOSE;
+        match &self.secret {
+            None => frame.encode(self.mask),
+            Some(secret) => {
+                let encrypted = secret.symmetric_encrypt(&frame.encode(false)).unwrap();
+                let envelope = RoundtripEnvelope::encode(&encrypted).to_json_bytes();
+                self::frame(BINARY, envelope).encode(self.mask)
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        // The implementation may have failed the connection already.
+        if self.stream.write_all(data).is_ok() {
+            self.written += data.len() as u64;
+        }
+    }
+
+    fn run(&mut self, step: Step) {
+        match step {
+            Step::Send(frames) => {
+                let data: Vec<u8> = frames.iter().flat_map(|frame| self.wire(frame)).collect();
+                self.write(&data);
+            }
+            Step::FrameWise(frames) => {
+                for frame in frames {
+                    let data = self.wire(&frame);
+                    self.write(&data);
+                }
+            }
+            Step::Chopped(frames, chop) => {
+                let data: Vec<u8> = frames.iter().flat_map(|frame| self.wire(frame)).collect();
+                for chunk in data.chunks(chop) {
+                    self.write(chunk);
+                }
+            }
+            Step::Split(frame, splits) => {
+                let data = self.wire(&frame);
+                // Map offsets of the payload to the data, an envelope is split proportionally.
+                let header_len = data.len() - frame.payload.len();
+                let mut start = 0;
+                for split in splits {
+                    let end = match self.secret {
+                        None => header_len + split,
+                        Some(_) => split * data.len() / frame.payload.len(),
+                    };
+                    self.write(&data[start..end]);
+                    start = end;
+                }
+                self.run(Step::Probe);
+                self.write(&data[start..]);
+            }
+            Step::Probe => {
+                let failed = self.progress.wait_consumed(self.written);
+                if failed {
+                    self.wait(|peer| peer.closed, TIMEOUT);
+                }
+                self.failed_fast = Some(failed);
+            }
+            Step::Pause(duration) => thread::sleep(duration),
+            Step::WaitFor(n) => self.wait(|peer| peer.received.len() >= n, TIMEOUT),
+        }
+    }
+
+    /// Handle events until the condition holds or the connection is closed.
+    fn wait(&mut self, done: impl Fn(&Self) -> bool, timeout: Duration) {
+        while !done(self) && !self.closed {
+            match self.events.recv_timeout(timeout) {
+                Ok(Event::Message(message)) => self.received.push(message),
+                Ok(Event::Close(code)) => self.close_code = code,
+                Ok(Event::Closed) | Err(RecvTimeoutError::Disconnected) => self.closed = true,
+                Err(RecvTimeoutError::Timeout) => return,
+            }
+        }
+    }
+
+    /// Run a case and judge the behavior of the implementation.
+    fn judge(mut self, case: &Case) -> Outcome {
+        for step in case.steps.clone() {
+            self.run(step);
+        }
+
+        let mut failed = false;
+        if let Expect::Fail(_) = case.expect {
+            // Failing on the data sent so far, or never.
+            if self.progress.wait_consumed(self.written) {
+                self.wait(|peer| peer.closed, TIMEOUT);
+            }
+            failed = self.closed;
+        }
+        if !self.closed && !self.close_sent {
+            let data = self.wire(&close(1000, b""));
+            self.write(&data);
+        }
+        self.wait(|_| false, TIMEOUT);
+
+        let behavior = match &case.expect {
+            Expect::Close(messages) if self.received == *messages => "OK",
+            Expect::Fail(messages) if failed && self.received == *messages => {
+                match self.failed_fast {
+                    Some(false) => "NON-STRICT",
+                    _ => "OK",
+                }
+            }
+            Expect::Informational => "INFORMATIONAL",
+            _ => "FAILED",
+        };
+        let behavior_close = match (&case.expect, self.closed) {
+            (Expect::Informational, _) => "INFORMATIONAL",
+            (_, true) => "OK",
+            (_, false) => "FAILED",
+        };
+        Outcome {
+            behavior: behavior.into(),
+            behavior_close: behavior_close.into(),
+            remote_close_code: self.close_code,
+        }
+    }
+}
+
+/// Reads frames from the implementation and assembles messages.
+struct Reader {
+    stream: TcpStream,
+    secret: Option<Jwk>,
+    message: Option<(u8, Vec<u8>)>,
+}
+
+impl Reader {
+    fn read(&mut self) -> io::Result<Option<Event>> {
+        let mut frame = read_frame(&mut self.stream)?;
+        if let Some(secret) = &self.secret {
+            let invalid = |_| io::Error::from(io::ErrorKind::InvalidData);
+            let envelope = RoundtripEnvelope::from_json_bytes(&frame.payload).map_err(invalid)?;
+            let data =
+                secret.symmetric_decrypt(&envelope.decode().map_err(invalid)?).map_err(invalid)?;
+            frame = read_frame(&mut &data[..])?;
+        }
+
+        Ok(match frame.opcode {
+            CLOSE => Some(Event::Close(match frame.payload[..] {
+                [high, low, ..] => Some(u16::from_be_bytes([high, low])),
+                _ => None,
+            })),
+            PONG => Some(Event::Message(pong(&frame.payload))),
+            PING => None,
+            opcode => {
+                let (opcode, payload) = match self.message.take() {
+                    Some((opcode, mut payload)) => {
+                        payload.extend_from_slice(&frame.payload);
+                        (opcode, payload)
+                    }
+                    None => (opcode, frame.payload),
+                };
+                if !frame.fin {
+                    self.message = Some((opcode, payload));
+                    return Ok(None);
+                }
+                Some(Event::Message(match opcode {
+                    TEXT => match String::from_utf8(payload) {
+                        Ok(text) => Message::text(text),
+                        Err(err) => Message::binary(err.into_bytes()),
+                    },
+                    _ => Message::binary(payload),
+                }))
+            }
+        })
+    }
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<RawFrame> {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header)?;
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut mask = [0; 4];
+    let masked = header[1] & 0x80 != 0;
+    if masked {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    if masked {
+        payload.iter_mut().zip(mask.iter().cycle()).for_each(|(byte, mask)| *byte ^= mask);
+    }
+    Ok(RawFrame {
+        fin: header[0] & 0x80 != 0,
+        rsv: header[0] >> 4 & 0x7,
+        opcode: header[0] & 0xf,
+        payload,
+    })
+}
+
+/// Read an HTTP head byte by byte, so that no frame is consumed.
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+fn header<'h>(head: &'h str, name: &str) -> Option<&'h str> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then_some(value.trim())
+    })
+}
+
+/// Echo every data message until the connection ends.
+fn echo_loop<S: Read + Write>(mut socket: WebSocket<S>, secret: Option<Jwk>) {
+    if let Some(secret) = secret {
+        socket.set_shared_secret(secret);
+        // Envelopes are bigger than the messages of case 9 they carry.
+        socket.set_config(|config| {
+            config.max_frame_size = Some(64 << 20);
+            config.max_message_size = Some(128 << 20);
+        });
+    }
+    loop {
+        match socket.read() {
+            Ok(message @ (Message::Text(_) | Message::Binary(_))) => {
+                if socket.send(message).is_err() {
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Connect the peer to an implementation of the other role. `deflate` offers permessage-deflate,
+/// returns whether it was negotiated.
+fn connect(role: Role, secret: &Option<Jwk>, deflate: bool) -> (Peer, JoinHandle<()>, bool) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let implementation_secret = secret.clone();
+    let progress = Arc::new(Progress::default());
+    let implementation_progress = progress.clone();
+
+    let (stream, handshake_len, implementation, negotiated) = match role {
+        Role::Server => {
+            let implementation = thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                let stream = Tracked { stream, progress: implementation_progress };
+                if let Ok(socket) = accept(stream) {
+                    echo_loop(socket, implementation_secret);
+                }
+            });
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let extensions = if deflate {
+                "Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n"
+            } else {
+                ""
+            };
+            let request = format!(
+                "GET / HTTP/1.1\r\nHost: {addr}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                Sec-WebSocket-Key: {KEY}\r\nSec-WebSocket-Version: 13\r\n{extensions}\r\n"
+            );
+            stream.write_all(request.as_bytes()).unwrap();
+            let head = read_head(&mut stream);
+            assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+            let accept_key = derive_accept_key(KEY.as_bytes());
+            assert_eq!(header(&head, "Sec-WebSocket-Accept"), Some(accept_key.as_str()));
+            let negotiated = header(&head, "Sec-WebSocket-Extensions").is_some();
+            (stream, request.len(), implementation, negotiated)
+        }
+        Role::Client => {
+            let implementation = thread::spawn(move || {
+                let stream = TcpStream::connect(addr).unwrap();
+                let stream = Tracked { stream, progress: implementation_progress };
+                if let Ok((socket, _)) = client(format!("ws://{addr}/"), stream) {
+                    echo_loop(socket, implementation_secret);
+                }
+            });
+            let (mut stream, _) = listener.accept().unwrap();
+            let head = read_head(&mut stream);
+            let key = header(&head, "Sec-WebSocket-Key").unwrap();
+            let negotiated = deflate && header(&head, "Sec-WebSocket-Extensions").is_some();
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                Sec-WebSocket-Accept: {}\r\n\r\n",
+                derive_accept_key(key.as_bytes())
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            (stream, response.len(), implementation, negotiated)
+        }
+    };
+    let peer = Peer::new(stream, role, secret.clone(), progress, handshake_len as u64);
+    (peer, implementation, negotiated)
+}
+
+fn expected_results() -> BTreeMap<String, Outcome> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/autobahn/expected-results.json");
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+    json["Tungstenite"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(id, result)| {
+            let outcome = Outcome {
+                behavior: result["behavior"].as_str().unwrap().into(),
+                behavior_close: result["behaviorClose"].as_str().unwrap().into(),
+                remote_close_code: result["remoteCloseCode"].as_u64().map(|code| code as u16),
+            };
+            (id.clone(), outcome)
+        })
+        .collect()
+}
+
+/// Run the cases selected by `filter` against an implementation of `role`.
+fn run_suite(role: Role, layer8: bool, filter: fn(&Case) -> bool) {
+    let secret = layer8.then(|| {
+        let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+        private_key.get_ecdh_shared_secret(&public_key).unwrap()
+    });
+    let expected = expected_results();
+
+    // 12 and 13 test permessage-deflate, which is not implemented.
+    let mut cases = cases();
+    cases.extend(
+        expected
+            .keys()
+            .filter(|id| id.starts_with("12.") || id.starts_with("13."))
+            .map(|id| case(id.clone(), vec![], Expect::Unimplemented)),
+    );
+    cases.retain(filter);
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        let deflate = matches!(case.expect, Expect::Unimplemented);
+        let (peer, implementation, negotiated) = connect(role, &secret, deflate);
+        let mut outcome = peer.judge(case);
+        if deflate {
+            outcome.behavior = if negotiated { "FAILED" } else { "UNIMPLEMENTED" }.into();
+        }
+        implementation.join().unwrap();
+
+        let expected =
+            expected.get(&case.id).unwrap_or_else(|| panic!("No result for {}", case.id));
+        if outcome != *expected {
+            failures.push(format!("{}: expected {expected:?}, got {outcome:?}", case.id));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} cases failed:\n{}",
+        failures.len(),
+        cases.len(),
+        failures.join("\n")
+    );
+}
+
+/// Sending and encrypting the biggest messages of case 9 takes a while, they run with
+/// `--ignored`.
+fn small(case: &Case) -> bool {
+    case.payload_len() <= 1024 * 1024
+}
+
+#[test]
+fn server_role() {
+    run_suite(Role::Server, false, small);
+}
+
+#[test]
+fn client_role() {
+    run_suite(Role::Client, false, small);
+}
+
+#[test]
+fn server_layer8_role() {
+    run_suite(Role::Server, true, small);
+}
+
+#[test]
+fn client_layer8_role() {
+    run_suite(Role::Client, true, small);
+}
+
+#[test]
+#[ignore = "slow"]
+fn server_role_big_messages() {
+    run_suite(Role::Server, false, |case| !small(case));
+}
+
+#[test]
+#[ignore = "slow"]
+fn client_role_big_messages() {
+    run_suite(Role::Client, false, |case| !small(case));
+}
+
+#[test]
+#[ignore = "slow"]
+fn server_layer8_role_big_messages() {
+    run_suite(Role::Server, true, |case| !small(case));
+}
+
+#[test]
+#[ignore = "slow"]
+fn client_layer8_role_big_messages() {
+    run_suite(Role::Client, true, |case| !small(case));
+}
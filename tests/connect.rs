@@ -0,0 +1,74 @@
+//! Verifies the connect and handshake timeouts and the errors of failed connections.
+
+#![cfg(feature = "handshake")]
+
+use std::{
+    io::{self, Write},
+    net::{Ipv4Addr, SocketAddrV4, TcpListener},
+    thread::{sleep, spawn},
+    time::{Duration, Instant},
+};
+
+use layer8_tungstenite::{
+    connect_with_options, handshake::machine::HandshakeConfig, ConnectOptions, Error,
+};
+
+#[test]
+fn refused_connection_lists_attempts() {
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let result = connect_with_options(format!("ws://{addr}"), ConnectOptions::default());
+    let Err(Error::Connect(err)) = result else { panic!("unexpected result {result:?}") };
+    assert_eq!(err.target, format!("ws://{addr}/"));
+    assert_eq!(err.attempts.len(), 1);
+    assert_eq!(err.attempts[0].0, addr);
+    assert_eq!(err.attempts[0].1.kind(), io::ErrorKind::ConnectionRefused);
+    assert!(err.to_string().contains(&addr.to_string()));
+}
+
+#[test]
+fn handshake_timeout() {
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Accept the connection but never answer the handshake.
+    spawn(move || {
+        let _stream = listener.accept().unwrap();
+        sleep(Duration::from_secs(5));
+    });
+
+    let start = Instant::now();
+    let options = ConnectOptions::default().handshake_timeout(Some(Duration::from_millis(100)));
+    let result = connect_with_options(format!("ws://{addr}"), options);
+    assert!(
+        matches!(&result, Err(Error::Io(err)) if err.kind() == io::ErrorKind::TimedOut),
+        "unexpected result {result:?}"
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn handshake_timeout_is_a_deadline() {
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Answer the handshake a byte at a time, never finishing the head.
+    spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nX-Slow: ").unwrap();
+        while stream.write_all(b"a").is_ok() {
+            sleep(Duration::from_millis(20));
+        }
+    });
+
+    let start = Instant::now();
+    let options = ConnectOptions::default()
+        .handshake_timeout(Some(Duration::from_millis(300)))
+        .handshake_config(HandshakeConfig::default().timeout(None).min_bytes_per_second(None));
+    let result = connect_with_options(format!("ws://{addr}"), options);
+    assert!(
+        matches!(&result, Err(Error::Io(err)) if err.kind() == io::ErrorKind::TimedOut),
+        "unexpected result {result:?}"
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,75 @@
+//! Verifies accepting WebSocket upgrades of requests parsed by an HTTP server.
+
+#![cfg(feature = "handshake")]
+#![allow(clippy::result_large_err)]
+
+use std::{
+    io::Read,
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use layer8_primitives::crypto::{generate_key_pair, KeyUse};
+use layer8_tungstenite::{
+    accept_hdr_from_request, connect,
+    handshake::server::{Request, Response},
+    ClientRequestBuilder, Message,
+};
+
+/// Read and parse the request head like an HTTP server, returning the bytes read after it.
+fn read_request(stream: &mut TcpStream) -> (http::Request<()>, Vec<u8>) {
+    let mut buf = Vec::new();
+    loop {
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).unwrap();
+        assert_ne!(n, 0, "connection closed during the request");
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        if let httparse::Status::Complete(size) = parsed.parse(&buf).unwrap() {
+            let mut request =
+                http::Request::builder().method(parsed.method.unwrap()).uri(parsed.path.unwrap());
+            for header in parsed.headers {
+                request = request.header(header.name, header.value);
+            }
+            return (request.body(()).unwrap(), buf[size..].to_vec());
+        }
+    }
+}
+
+#[test]
+fn upgrade_parsed_request() {
+    let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+    let symmetric_key = private_key.get_ecdh_shared_secret(&public_key).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("ws://{}/chat", listener.local_addr().unwrap()).parse().unwrap();
+    let server_key = symmetric_key.clone();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (mut request, leftover) = read_request(&mut stream);
+        request.extensions_mut().insert(server_key);
+
+        let callback = |request: &Request, mut response: Response| {
+            let requested = request.headers()["Sec-WebSocket-Protocol"].to_str().unwrap();
+            assert!(requested.split(", ").any(|protocol| protocol == "chat"));
+            response.headers_mut().insert("Sec-WebSocket-Protocol", "chat".parse().unwrap());
+            Ok(response)
+        };
+        let mut socket =
+            accept_hdr_from_request(stream, &request, leftover, callback, None).unwrap();
+        assert_eq!(socket.subprotocol(), Some("chat"));
+        let message = socket.read().unwrap();
+        socket.send(message).unwrap();
+    });
+
+    let request = ClientRequestBuilder::new(uri).with_sub_protocol("v2").with_sub_protocol("chat");
+    let (mut client, response) = connect(request).unwrap();
+    assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "chat");
+    assert_eq!(client.subprotocol(), Some("chat"));
+    client.set_shared_secret(symmetric_key);
+    client.send(Message::text("encrypted")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("encrypted"));
+    server.join().unwrap();
+}
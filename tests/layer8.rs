@@ -1,3 +1,5 @@
+#![cfg(feature = "handshake")]
+
 use std::{
     net::{Ipv4Addr, SocketAddrV4, TcpListener},
     thread::spawn,
@@ -38,6 +40,59 @@ fn ping() {
     socket.close(None).expect("close failed");
 }
 
+#[test]
+fn fragmented_envelope() {
+    let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+    let symmetric_key = private_key.get_ecdh_shared_secret(&public_key).unwrap();
+
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+        .expect("Can't listen, is port already in use?");
+    let port = listener.local_addr().unwrap().port();
+    let secret_key = symmetric_key.clone();
+    spawn(move || {
+        layer8_server_conn(&listener, secret_key);
+    });
+
+    let (mut socket, _) =
+        connect(format!("ws://localhost:{}", port)).expect("Can't connect to port");
+    socket.set_shared_secret(symmetric_key);
+    // The encrypted envelope is split into frames and reassembled before decryption.
+    socket.set_config(|cfg| cfg.max_outgoing_frame_size = Some(16));
+
+    let text = "a message bigger than a single frame";
+    socket.send(Message::Text(text.into())).expect("Failed to send text");
+    assert_eq!(socket.read().expect("Failed to read"), Message::Text(text.into()));
+
+    socket.close(None).expect("close failed");
+}
+
+#[test]
+fn ping_rtt() {
+    let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+    let symmetric_key = private_key.get_ecdh_shared_secret(&public_key).unwrap();
+
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+        .expect("Can't listen, is port already in use?");
+    let port = listener.local_addr().unwrap().port();
+    let secret_key = symmetric_key.clone();
+    spawn(move || {
+        layer8_server_conn(&listener, secret_key);
+    });
+
+    let (mut socket, _) =
+        connect(format!("ws://localhost:{}", port)).expect("Can't connect to port");
+    socket.set_shared_secret(symmetric_key);
+
+    // The automatic pong is encrypted as well and correlated after decryption.
+    socket.ping_with_id().expect("Failed to send ping");
+    while socket.last_rtt().is_none() {
+        assert!(socket.read().expect("Failed to read").is_pong());
+    }
+    assert_eq!(socket.rtt().count(), 1);
+
+    socket.close(None).expect("close failed");
+}
+
 fn layer8_server_conn(listener: &TcpListener, symmetric_key: Jwk) {
     for stream in listener.incoming() {
         let symmetric_key = symmetric_key.clone();
@@ -0,0 +1,108 @@
+//! Verifies that clients connect through local HTTP CONNECT and SOCKS5 stand-in proxies.
+
+#![cfg(feature = "handshake")]
+
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream},
+    thread::spawn,
+};
+
+use layer8_tungstenite::{
+    accept, connect_with_options, proxy::Proxy, ConnectOptions, Error, Message,
+};
+
+/// Run a WebSocket server echoing one message, returns its port.
+fn echo_server() -> u16 {
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    spawn(move || {
+        let mut socket = accept(listener.accept().unwrap().0).unwrap();
+        let msg = socket.read().unwrap();
+        socket.send(msg).unwrap();
+    });
+    port
+}
+
+/// Run a proxy accepting one connection, `handshake` reads the request and returns the target.
+fn proxy_server(handshake: fn(&mut TcpStream) -> io::Result<String>) -> u16 {
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    spawn(move || {
+        let mut client = listener.accept().unwrap().0;
+        let target = handshake(&mut client).unwrap();
+        let server = TcpStream::connect(target).unwrap();
+
+        let (mut client_read, mut server_write) =
+            (client.try_clone().unwrap(), server.try_clone().unwrap());
+        spawn(move || io::copy(&mut client_read, &mut server_write));
+        let (mut server_read, mut client_write) = (server, client);
+        let _ = io::copy(&mut server_read, &mut client_write);
+    });
+    port
+}
+
+fn http_connect(client: &mut TcpStream) -> io::Result<String> {
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        client.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8(request).unwrap();
+    // Basic dXNlcjpwYXNz is "user:pass".
+    if !request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n") {
+        client.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "no credentials"));
+    }
+    client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+    let target = request.strip_prefix("CONNECT ").unwrap().split(' ').next().unwrap();
+    Ok(target.to_owned())
+}
+
+fn socks5(client: &mut TcpStream) -> io::Result<String> {
+    let mut greeting = [0; 3];
+    client.read_exact(&mut greeting)?;
+    assert_eq!(greeting, [5, 1, 0]);
+    client.write_all(&[5, 0])?;
+
+    let mut request = [0; 5];
+    client.read_exact(&mut request)?;
+    assert_eq!(request[..4], [5, 1, 0, 3]);
+    let mut host = vec![0; usize::from(request[4])];
+    client.read_exact(&mut host)?;
+    let mut port = [0; 2];
+    client.read_exact(&mut port)?;
+    client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+    Ok(format!("{}:{}", String::from_utf8(host).unwrap(), u16::from_be_bytes(port)))
+}
+
+fn echo_through(proxy: Proxy, port: u16) {
+    let options = ConnectOptions::default().proxy(Some(proxy));
+    let (mut socket, _) = connect_with_options(format!("ws://localhost:{port}"), options).unwrap();
+    socket.send(Message::text("through the proxy")).unwrap();
+    assert_eq!(socket.read().unwrap(), Message::text("through the proxy"));
+}
+
+#[test]
+fn http_connect_proxy() {
+    let proxy_port = proxy_server(http_connect);
+    echo_through(
+        Proxy::http("127.0.0.1", proxy_port).with_credentials("user", "pass"),
+        echo_server(),
+    );
+}
+
+#[test]
+fn http_connect_proxy_auth_required() {
+    let proxy_port = proxy_server(http_connect);
+    let options = ConnectOptions::default().proxy(Some(Proxy::http("127.0.0.1", proxy_port)));
+    let result = connect_with_options("ws://localhost:1/", options);
+    assert!(matches!(result, Err(Error::Proxy(_))));
+}
+
+#[test]
+fn socks5_proxy() {
+    let proxy_port = proxy_server(socks5);
+    echo_through(Proxy::socks5("127.0.0.1", proxy_port), echo_server());
+}
@@ -0,0 +1,52 @@
+//! Verifies that the client follows redirects of the handshake.
+
+#![cfg(feature = "handshake")]
+#![allow(clippy::result_large_err)]
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddrV4, TcpListener},
+    thread::spawn,
+};
+
+use layer8_tungstenite::{
+    accept_hdr,
+    client::FinalUri,
+    connect_with_options,
+    handshake::server::{Request, Response},
+    http::header,
+    ClientRequestBuilder, ConnectOptions,
+};
+
+#[test]
+fn follows_relative_redirect() {
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = spawn(move || {
+        let mut stream = listener.accept().unwrap().0;
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+        stream.write_all(b"HTTP/1.1 302 Found\r\nLocation: ../moved?to=here\r\n\r\n").unwrap();
+        drop(stream);
+
+        let callback = |request: &Request, response: Response| {
+            assert_eq!(request.uri(), "/moved?to=here");
+            // Same origin, the credentials are replayed.
+            assert_eq!(request.headers()[header::AUTHORIZATION], "Bearer token");
+            Ok(response)
+        };
+        accept_hdr(listener.accept().unwrap().0, callback).unwrap();
+    });
+
+    let request =
+        ClientRequestBuilder::new(format!("ws://127.0.0.1:{port}/old/socket").parse().unwrap())
+            .with_header("Authorization", "Bearer token");
+    let (_, response) = connect_with_options(request, ConnectOptions::default()).unwrap();
+    let final_uri = response.extensions().get::<FinalUri>().unwrap();
+    assert_eq!(final_uri.0.to_string(), format!("ws://127.0.0.1:{port}/moved?to=here"));
+    server.join().unwrap();
+}
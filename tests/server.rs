@@ -0,0 +1,201 @@
+//! Verifies the multi-connection `WebSocketServer`.
+
+#![cfg(feature = "handshake")]
+#![allow(clippy::result_large_err)]
+
+use std::{
+    io::Read,
+    net::{SocketAddr, TcpStream},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use layer8_primitives::crypto::{generate_key_pair, KeyUse};
+use layer8_tungstenite::{
+    client::IntoClientRequest,
+    connect,
+    error::Result,
+    handshake::origin::OriginPolicy,
+    listener::{Connection, ShutdownHandle},
+    protocol::frame::coding::CloseCode,
+    Error, Message, WebSocketServer,
+};
+
+fn echo(conn: &mut Connection) -> Result<()> {
+    loop {
+        let msg = conn.read()?;
+        if msg.is_text() || msg.is_binary() {
+            conn.send(msg)?;
+        }
+    }
+}
+
+fn start(server: WebSocketServer) -> (SocketAddr, ShutdownHandle, JoinHandle<Result<()>>) {
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    (addr, shutdown, thread::spawn(move || server.run()))
+}
+
+#[test]
+fn echo_and_shutdown() {
+    let server = WebSocketServer::builder(echo).workers(2).bind("127.0.0.1:0").unwrap();
+    let (addr, shutdown, running) = start(server);
+
+    let mut clients: Vec<_> = (0..2).map(|_| connect(format!("ws://{addr}")).unwrap().0).collect();
+    for (i, client) in clients.iter_mut().enumerate() {
+        client.send(Message::text(format!("client {i}"))).unwrap();
+        assert_eq!(client.read().unwrap(), Message::text(format!("client {i}")));
+    }
+
+    shutdown.shutdown();
+    for client in &mut clients {
+        match client.read().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+            msg => panic!("unexpected message {msg:?}"),
+        }
+        assert!(matches!(client.read(), Err(Error::ConnectionClosed)));
+    }
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn connection_limit() {
+    let server = WebSocketServer::builder(echo).max_connections(1).bind("127.0.0.1:0").unwrap();
+    let (addr, shutdown, running) = start(server);
+
+    let (mut first, _) = connect(format!("ws://{addr}")).unwrap();
+    match connect(format!("ws://{addr}")) {
+        Err(Error::Http(response)) => assert_eq!(response.status(), 503),
+        result => panic!("unexpected result {result:?}"),
+    }
+
+    first.close(None).unwrap();
+    while first.read().is_ok() {}
+    // The slot is freed once the handler returned.
+    let connected = (0..100).any(|_| {
+        thread::sleep(Duration::from_millis(10));
+        connect(format!("ws://{addr}")).is_ok()
+    });
+    assert!(connected);
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn rejecting_does_not_block_accepting() {
+    let server = WebSocketServer::builder(echo).max_connections(1).bind("127.0.0.1:0").unwrap();
+    let (addr, shutdown, running) = start(server);
+
+    let (mut first, _) = connect(format!("ws://{addr}")).unwrap();
+    // Above the limit and never sending a handshake request.
+    let idle: Vec<_> = (0..3).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for mut stream in &idle {
+        let mut status = [0; 12];
+        stream.read_exact(&mut status).unwrap();
+        assert_eq!(&status, b"HTTP/1.1 503");
+    }
+    first.close(None).unwrap();
+    while first.read().is_ok() {}
+
+    let started = Instant::now();
+    let connected = (0..100).any(|_| {
+        thread::sleep(Duration::from_millis(10));
+        connect(format!("ws://{addr}")).is_ok()
+    });
+    assert!(connected);
+    // Waiting for the requests of the idle connections would take a second each.
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn shutdown_stops_blocked_send() {
+    let flood = |conn: &mut Connection| -> Result<()> {
+        loop {
+            conn.send(Message::binary(vec![0; 1 << 20]))?;
+        }
+    };
+    let server = WebSocketServer::builder(flood)
+        .close_timeout(Duration::from_millis(100))
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let (addr, shutdown, running) = start(server);
+
+    // Never reads, so the handler blocks once the socket buffers are full.
+    let (_client, _) = connect(format!("ws://{addr}")).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn handshake_timeout_frees_worker() {
+    let server = WebSocketServer::builder(echo)
+        .workers(1)
+        .handshake_timeout(Some(Duration::from_millis(100)))
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let (addr, shutdown, running) = start(server);
+
+    // Never sends a handshake request.
+    let _idle = TcpStream::connect(addr).unwrap();
+    let (mut client, _) = connect(format!("ws://{addr}")).unwrap();
+    client.send(Message::text("served")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("served"));
+    drop(client);
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn layer8_secret_provider() {
+    let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+    let symmetric_key = private_key.get_ecdh_shared_secret(&public_key).unwrap();
+
+    let server_key = symmetric_key.clone();
+    let server = WebSocketServer::builder(echo)
+        .secret_provider(move |request| {
+            (request.uri().path() == "/layer8").then(|| server_key.clone())
+        })
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let (addr, shutdown, running) = start(server);
+
+    let (mut client, _) = connect(format!("ws://{addr}/layer8")).unwrap();
+    client.set_shared_secret(symmetric_key);
+    client.send(Message::text("encrypted")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("encrypted"));
+    drop(client);
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn origin_policy() {
+    let policy = OriginPolicy::new().allow("https://*.example.com");
+    let server =
+        WebSocketServer::builder(echo).origin_policy(Some(policy)).bind("127.0.0.1:0").unwrap();
+    let (addr, shutdown, running) = start(server);
+
+    let request = |origin: &str| {
+        let mut request = format!("ws://{addr}").into_client_request().unwrap();
+        request.headers_mut().insert("Origin", origin.parse().unwrap());
+        request
+    };
+    match connect(request("https://evil.com")) {
+        Err(Error::Http(response)) => assert_eq!(response.status(), http::StatusCode::FORBIDDEN),
+        other => panic!("unexpected result {other:?}"),
+    }
+    let (mut client, _) = connect(request("https://app.example.com")).unwrap();
+    client.send(Message::text("allowed")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("allowed"));
+    drop(client);
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
@@ -0,0 +1,66 @@
+//! Verifies WebSocket connections over Unix domain sockets with `ws+unix://` URLs.
+
+#![cfg(all(unix, feature = "handshake"))]
+
+use std::{os::unix::net::UnixListener, path::PathBuf, process, thread::spawn};
+
+use layer8_primitives::crypto::{generate_key_pair, KeyUse};
+use layer8_tungstenite::{accept_unix, connect, connect_unix, error::UrlError, Error, Message};
+
+/// A socket path unique to the test, removed when dropped.
+struct SocketPath(PathBuf);
+
+impl SocketPath {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("tungstenite-{name}-{}.sock", process::id()));
+        let _ = std::fs::remove_file(&path);
+        Self(path)
+    }
+}
+
+impl Drop for SocketPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn echo_over_unix_socket() {
+    let path = SocketPath::new("echo");
+    let listener = UnixListener::bind(&path.0).unwrap();
+    let server = spawn(move || {
+        let mut socket = accept_unix(&listener).unwrap();
+        let msg = socket.read().unwrap();
+        socket.send(msg).unwrap();
+    });
+
+    let url = format!("ws+unix://{}:/agent", path.0.display());
+    assert!(matches!(connect(&url), Err(Error::Url(UrlError::UnsupportedUrlScheme))));
+    let (mut socket, response) = connect_unix(url).unwrap();
+    assert_eq!(response.status(), 101);
+    socket.send(Message::text("over a unix socket")).unwrap();
+    assert_eq!(socket.read().unwrap(), Message::text("over a unix socket"));
+    server.join().unwrap();
+}
+
+#[test]
+fn layer8_over_unix_socket() {
+    let (private_key, public_key) = generate_key_pair(KeyUse::Ecdh).unwrap();
+    let symmetric_key = private_key.get_ecdh_shared_secret(&public_key).unwrap();
+
+    let path = SocketPath::new("layer8");
+    let listener = UnixListener::bind(&path.0).unwrap();
+    let secret_key = symmetric_key.clone();
+    let server = spawn(move || {
+        let mut socket = accept_unix(&listener).unwrap();
+        socket.set_shared_secret(secret_key);
+        let msg = socket.read().unwrap();
+        socket.send(msg).unwrap();
+    });
+
+    let (mut socket, _) = connect_unix(format!("ws+unix://{}", path.0.display())).unwrap();
+    socket.set_shared_secret(symmetric_key);
+    socket.send(Message::text("encrypted over a unix socket")).unwrap();
+    assert_eq!(socket.read().unwrap(), Message::text("encrypted over a unix socket"));
+    server.join().unwrap();
+}